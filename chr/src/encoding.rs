@@ -0,0 +1,312 @@
+use std::io::{self, Read};
+
+#[cfg(feature = "gbk")]
+use crate::gbk::GBK;
+use crate::BUF_SIZE;
+use crate::MAX_CHR_LEN;
+
+// 输入文件的编码
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    // ISO-8859-1，一个byte就是一个码位
+    Latin1,
+    // 老的Windows编辑器存的简体中文，0x80是cp936里的€
+    #[cfg(feature = "gbk")]
+    Gbk,
+}
+
+impl Encoding {
+    // 命令行和配置里用的名字，不分大小写
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            #[cfg(feature = "gbk")]
+            "gbk" | "cp936" | "gb2312" => Some(Encoding::Gbk),
+            _ => None,
+        }
+    }
+
+    // 开头的BOM和它的长度
+    pub fn sniff(data: &[u8]) -> Option<(Self, usize)> {
+        match data {
+            [0xef, 0xbb, 0xbf, ..] => Some((Encoding::Utf8, 3)),
+            [0xff, 0xfe, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xfe, 0xff, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+}
+
+// 放在ChrIter前面，把别的编码转成utf8：ChrIter::new(Transcoder::new(file))
+// 有BOM的时候按BOM来，BOM本身去掉；转不了的byte换成U+FFFD，utf8原样交给ChrIter检查
+pub struct Transcoder<R> {
+    buf: [u8; BUF_SIZE],
+    r: R,
+    cursor: usize,
+    buf_len: usize,
+    eof: bool,
+    // 没有BOM时用的编码
+    fallback: Encoding,
+    // 看过开头之后才知道
+    encoding: Option<Encoding>,
+    // 转好了但是上次read放不下的utf8
+    pending: [u8; MAX_CHR_LEN],
+    pending_start: usize,
+    pending_len: usize,
+}
+
+impl<R: Read> Transcoder<R> {
+    // 没有BOM就当做utf8
+    pub fn new(r: R) -> Self {
+        Transcoder::with_encoding(r, Encoding::Utf8)
+    }
+
+    pub fn with_encoding(r: R, fallback: Encoding) -> Self {
+        Transcoder {
+            buf: [0; BUF_SIZE],
+            r,
+            cursor: 0,
+            buf_len: 0,
+            eof: false,
+            fallback,
+            encoding: None,
+            pending: [0; MAX_CHR_LEN],
+            pending_start: 0,
+            pending_len: 0,
+        }
+    }
+
+    // 第一次read之后才有
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding
+    }
+
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    // 和ChrIter一样，剩下的不够一个完整的字符时挪到开头再读
+    fn fill(&mut self) -> io::Result<()> {
+        while self.buf_len - self.cursor < MAX_CHR_LEN && !self.eof {
+            self.buf.copy_within(self.cursor..self.buf_len, 0);
+            self.buf_len -= self.cursor;
+            self.cursor = 0;
+
+            match self.r.read(&mut self.buf[self.buf_len..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf_len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn take_pending(&mut self, out: &mut [u8]) -> usize {
+        let n = self.pending_len.min(out.len());
+        out[..n].copy_from_slice(&self.pending[self.pending_start..self.pending_start + n]);
+        self.pending_start += n;
+        self.pending_len -= n;
+        n
+    }
+}
+
+impl<R: Read> Read for Transcoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut n = self.take_pending(out);
+
+        while n < out.len() {
+            if self.buf_len - self.cursor < MAX_CHR_LEN && !self.eof {
+                // 已经有东西了就先返回，不要为了凑满再等一次read
+                if n > 0 {
+                    break;
+                }
+                self.fill()?;
+            }
+
+            let encoding = match self.encoding {
+                Some(encoding) => encoding,
+                None => {
+                    let data = &self.buf[self.cursor..self.buf_len];
+                    let (encoding, bom_len) = Encoding::sniff(data).unwrap_or((self.fallback, 0));
+                    self.cursor += bom_len;
+                    self.encoding = Some(encoding);
+                    encoding
+                }
+            };
+
+            let data = &self.buf[self.cursor..self.buf_len];
+            if data.is_empty() {
+                break;
+            }
+
+            if encoding == Encoding::Utf8 {
+                let len = data.len().min(out.len() - n);
+                out[n..n + len].copy_from_slice(&data[..len]);
+                self.cursor += len;
+                n += len;
+                continue;
+            }
+
+            // fill过了，不够一个完整的字符只会是在结尾
+            let (c, len) = decode_one(encoding, data);
+            self.cursor += len;
+            let mut utf8 = [0; MAX_CHR_LEN];
+            let utf8 = c.encode_utf8(&mut utf8).as_bytes();
+            if n + utf8.len() <= out.len() {
+                out[n..n + utf8.len()].copy_from_slice(utf8);
+                n += utf8.len();
+            } else {
+                self.pending[..utf8.len()].copy_from_slice(utf8);
+                self.pending_start = 0;
+                self.pending_len = utf8.len();
+                n += self.take_pending(&mut out[n..]);
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+// data开头的一个字符和它用了几个byte，data不是空的
+fn decode_one(encoding: Encoding, data: &[u8]) -> (char, usize) {
+    match encoding {
+        Encoding::Utf8 => unreachable!(),
+        Encoding::Latin1 => (data[0] as char, 1),
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let unit = |i: usize| {
+                let pair = [data[i], data[i + 1]];
+                if encoding == Encoding::Utf16Le {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            };
+            if data.len() < 2 {
+                return (char::REPLACEMENT_CHARACTER, data.len());
+            }
+            let high = unit(0);
+            match high {
+                0xd800..=0xdbff => {
+                    let low = if data.len() >= 4 { unit(2) } else { 0 };
+                    if (0xdc00..=0xdfff).contains(&low) {
+                        let c = 0x10000 + ((high as u32 - 0xd800) << 10) + (low as u32 - 0xdc00);
+                        (char::from_u32(c).unwrap(), 4)
+                    } else {
+                        // 落单的代理，后面的那个单元另外再解
+                        (char::REPLACEMENT_CHARACTER, 2)
+                    }
+                }
+                0xdc00..=0xdfff => (char::REPLACEMENT_CHARACTER, 2),
+                _ => (char::from_u32(high as u32).unwrap(), 2),
+            }
+        }
+        #[cfg(feature = "gbk")]
+        Encoding::Gbk => match data {
+            [a, ..] if *a < 0x80 => (*a as char, 1),
+            [0x80, ..] => ('€', 1),
+            [lead @ 0x81..=0xfe, trail @ (0x40..=0x7e | 0x80..=0xfe), ..] => {
+                // 没有这个字时第二个byte是ascii的话不吃掉它
+                let index = (*lead as usize - 0x81) * 190 + *trail as usize
+                    - 0x40
+                    - (*trail > 0x7f) as usize;
+                match GBK[index] {
+                    0 => (char::REPLACEMENT_CHARACTER, 1 + (*trail >= 0x80) as usize),
+                    c => (char::from_u32(c as u32).unwrap(), 2),
+                }
+            }
+            _ => (char::REPLACEMENT_CHARACTER, 1),
+        },
+    }
+}
+
+#[test]
+fn test() {
+    // 每次只读几个byte，输出也每次只要几个byte，这样字符会被切开
+    struct Chunks<'a>(&'a [u8], usize);
+    impl Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+    let transcode = |data: &[u8], fallback: Encoding| {
+        let mut out = Vec::new();
+        for chunk in 1..9 {
+            let mut t = Transcoder::with_encoding(Chunks(data, chunk), fallback);
+            let mut piece = vec![0; chunk % 5 + 1];
+            let mut result = Vec::new();
+            loop {
+                let n = t.read(&mut piece).unwrap();
+                if n == 0 {
+                    break;
+                }
+                result.extend_from_slice(&piece[..n]);
+            }
+            if chunk > 1 {
+                assert_eq!(out, result);
+            }
+            out = result;
+        }
+        String::from_utf8(out).unwrap()
+    };
+
+    let text = "# 标题 café 😀\n- [ ] 中文\n";
+    let utf16le: Vec<u8> = text.encode_utf16().flat_map(|x| x.to_le_bytes()).collect();
+    let utf16be: Vec<u8> = text.encode_utf16().flat_map(|x| x.to_be_bytes()).collect();
+
+    assert_eq!(text, transcode(text.as_bytes(), Encoding::Utf8));
+    assert_eq!(
+        text,
+        transcode(
+            &[b"\xef\xbb\xbf", text.as_bytes()].concat(),
+            Encoding::Latin1
+        )
+    );
+    assert_eq!(
+        text,
+        transcode(&[&b"\xff\xfe"[..], &utf16le].concat(), Encoding::Utf8)
+    );
+    assert_eq!(
+        text,
+        transcode(&[&b"\xfe\xff"[..], &utf16be].concat(), Encoding::Utf8)
+    );
+    assert_eq!(text, transcode(&utf16le, Encoding::Utf16Le));
+    assert_eq!(text, transcode(&utf16be, Encoding::Utf16Be));
+    assert_eq!("café ÿ", transcode(b"caf\xe9 \xff", Encoding::Latin1));
+    // 落单的代理和结尾多出来的半个单元
+    assert_eq!(
+        "\u{fffd}a\u{fffd}\u{fffd}",
+        transcode(b"\x00\xd8a\x00\x00\xdc\x01", Encoding::Utf16Le)
+    );
+    assert_eq!("", transcode(b"\xff\xfe", Encoding::Utf8));
+    assert_eq!("\u{fffd}", transcode(b"a", Encoding::Utf16Le));
+
+    let mut t = Transcoder::new(&b"\xfe\xff\x00a"[..]);
+    assert_eq!(None, t.encoding());
+    let mut out = String::new();
+    t.read_to_string(&mut out).unwrap();
+    assert_eq!((Some(Encoding::Utf16Be), "a"), (t.encoding(), &out[..]));
+
+    assert_eq!(Some(Encoding::Utf16Le), Encoding::from_label("UTF-16LE"));
+    assert_eq!(None, Encoding::from_label("ebcdic"));
+
+    #[cfg(feature = "gbk")]
+    {
+        // "中文 €"的gbk，后面是没有这个字、第二个byte不对和0xff的情况
+        let gbk = b"\xd6\xd0\xce\xc4 \x80\xa1\x40\x81 \x81\x40\xff";
+        assert_eq!(
+            "中文 €\u{fffd}@\u{fffd} 丂\u{fffd}",
+            transcode(gbk, Encoding::Gbk)
+        );
+        assert_eq!(Some(Encoding::Gbk), Encoding::from_label("cp936"));
+    }
+}
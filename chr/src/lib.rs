@@ -1,25 +1,68 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
 use std::io::{self, Read};
 
+mod encoding;
+#[cfg(feature = "gbk")]
+mod gbk;
+mod tables;
+
+pub use encoding::Encoding;
+pub use encoding::Transcoder;
+
 const BUF_SIZE: usize = 8 * 1024;
 // const BUF_SIZE: usize = 24;
 // const BUF_SIZE: usize = 7;
+// 最长的utf8字符
+const MAX_CHR_LEN: usize = 4;
+// U+FFFD
+pub const REPLACEMENT: Chr = Chr::Three([0xef, 0xbf, 0xbd]);
 
 pub struct ChrIter<R> {
     buf: [u8; BUF_SIZE],
     r: R,
     cursor: usize,
     buf_len: usize,
-    remain: Remain,
+    // r已经读完了，buf里剩下的不完整的字符就是不合法的
+    eof: bool,
+    // buf[0]在整个输入里的位置
+    offset: u64,
+    policy: Utf8Policy,
+    invalid: Vec<InvalidByte>,
+    // peek过的字符，和std的Peekable一样，这样外面还能调用next_plain
+    peeked: Option<Option<io::Result<Chr>>>,
+    // peek过的字符开头的位置
+    peeked_at: u64,
+}
+
+// 遇到不合法的utf8怎么办
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Utf8Policy {
+    // 每个不合法的byte返回一个InvalidData的io::Error
+    Error,
+    // 每段不合法的byte换成一个U+FFFD
+    Replace,
+    // 每个不合法的byte原样当做Chr::One返回
+    PassThrough,
+}
+
+// 不合法的byte和它在输入里的位置
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct InvalidByte {
+    pub offset: u64,
+    pub byte: u8,
 }
 
-// 这个struct呢就是记录buf末尾剩余的byte的，因为最多只可能有3个byte
-#[derive(Clone, Copy)]
-struct Remain {
-    head_len: u8,
-    tail_len: u8,
-    a: u8,
-    b: u8,
-    c: u8,
+// data开头的一个字符
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Decoded {
+    // 合法的字符和它的长度
+    Chr(usize),
+    // 不合法，最长的可能是某个字符开头的那一段的长度，至少是1
+    Invalid(usize),
+    // 到结尾了还没有结束，要更多的byte才知道
+    Incomplete,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq,Debug)]
@@ -49,6 +92,13 @@ impl From<u8> for Chr {
     }
 }
 
+impl From<char> for Chr {
+    fn from(c: char) -> Self {
+        let mut buf = [0; MAX_CHR_LEN];
+        Chr::from_bytes(c.encode_utf8(&mut buf).as_bytes())
+    }
+}
+
 impl<'a> From<&'a Chr> for &'a [u8] {
     fn from(c: &'a Chr) -> Self {
 		 match c {
@@ -60,146 +110,225 @@ impl<'a> From<&'a Chr> for &'a [u8] {
     }
 }
 
-impl Remain {
-    fn new() -> Self {
-        Self {
-            head_len: 0,
-            tail_len: 0,
-            a: 0,
-            b: 0,
-            c: 0,
+impl Chr {
+    // bytes是一个完整的字符
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        match *bytes {
+            [a] => Chr::One([a]),
+            [a, b] => Chr::Two([a, b]),
+            [a, b, c] => Chr::Three([a, b, c]),
+            [a, b, c, d] => Chr::Four([a, b, c, d]),
+            _ => unreachable!(),
         }
     }
 
-    fn is_empty(self) -> bool {
-        self.head_len == 0
+    // PassThrough留下的不合法byte当做U+FFFD
+    pub fn to_char(&self) -> char {
+        let code = match *self {
+            Chr::One([a]) if a < 0x80 => a as u32,
+            Chr::One(_) => return char::REPLACEMENT_CHARACTER,
+            Chr::Two([a, b]) => (mask_tail_five(a) as u32) << 6 | mask_tail_six(b) as u32,
+            Chr::Three([a, b, c]) => {
+                (mask_tail_four(a) as u32) << 12
+                    | (mask_tail_six(b) as u32) << 6
+                    | mask_tail_six(c) as u32
+            }
+            Chr::Four([a, b, c, d]) => {
+                (mask_tail_three(a) as u32) << 18
+                    | (mask_tail_six(b) as u32) << 12
+                    | (mask_tail_six(c) as u32) << 6
+                    | mask_tail_six(d) as u32
+            }
+        };
+        char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+
+    // CommonMark的Unicode空白：Zs大类加上tab、换行、换页和回车
+    pub fn is_whitespace(&self) -> bool {
+        matches!(
+            self.to_char(),
+            '\t' | '\n' | '\u{c}' | '\r' | ' ' | '\u{a0}' | '\u{1680}'
+                | '\u{2000}'..='\u{200a}' | '\u{202f}' | '\u{205f}' | '\u{3000}'
+        )
     }
 
-    fn clear(&mut self) {
-        self.head_len = 0
+    // CommonMark的Unicode标点：P和S两个大类，左右侧定界符要用
+    pub fn is_punctuation(&self) -> bool {
+        match *self {
+            Chr::One([a]) if a < 0x80 => a.is_ascii_punctuation(),
+            _ => in_table(tables::PUNCTUATION, self.to_char() as u32),
+        }
+    }
+
+    // 在终端里占几列，控制字符和组合用的符号不占，东亚宽字符占两列
+    pub fn width(&self) -> usize {
+        match self.to_char() as u32 {
+            0..=0x1f | 0x7f..=0x9f => 0,
+            0x20..=0x7e => 1,
+            c if in_table(tables::ZERO_WIDTH, c) => 0,
+            c if in_table(tables::WIDE, c) => 2,
+            _ => 1,
+        }
     }
 }
 
+impl fmt::Display for Chr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.to_char(), f)
+    }
+}
+
+// 表里是排好序不重叠的闭区间
+fn in_table(table: &[(u32, u32)], c: u32) -> bool {
+    table
+        .binary_search_by(|&(lo, hi)| {
+            if hi < c {
+                Ordering::Less
+            } else if lo > c {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+impl fmt::Display for InvalidByte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid utf-8 byte 0x{:02x} at offset {}",
+            self.byte, self.offset
+        )
+    }
+}
+
+impl Error for InvalidByte {}
+
 impl<R> ChrIter<R> {
     pub fn new(r: R) -> Self {
+        Self::with_policy(r, Utf8Policy::PassThrough)
+    }
+
+    pub fn with_policy(r: R, policy: Utf8Policy) -> Self {
         Self {
             buf: [0; BUF_SIZE],
-            buf_len: usize::MAX,
+            buf_len: 0,
             r,
             cursor: 0,
-            remain: Remain::new(),
+            eof: false,
+            offset: 0,
+            policy,
+            invalid: Vec::new(),
+            peeked: None,
+            peeked_at: 0,
         }
     }
 
-    fn cur_u8_unite_len(&self) -> (u8, u8) {
-        let a = self.get_byte(self.cursor);
-        (utf8_len(a), a)
-    }
-
-    fn set_load_buf(&mut self) {
-        // 此时如果当前cursor已经等于buf.len()，那么我们再次read到buf一次~
-        self.buf_len = usize::MAX;
-        self.cursor = 0;
-    }
-
-    // 假定cursor和buf在一个合理范围内，同时必定buf是能够截取到对应长度的
-    // 然后我们写一个utf8 -> unicode的转换函数
-    fn chr(&mut self, char_len: u8, a: u8) -> Chr {
-        let chr = match char_len {
-            1 => Chr::One([a]),
-            2 => Chr::Two([a, self.get_byte(self.cursor + 1)]),
-            3 => Chr::Three([
-                a,
-                self.get_byte(self.cursor + 1),
-                self.get_byte(self.cursor + 2),
-            ]),
-            4 => Chr::Four([
-                a,
-                self.get_byte(self.cursor + 1),
-                self.get_byte(self.cursor + 2),
-                self.get_byte(self.cursor + 3),
-            ]),
-            // TODO change unreachable!() to unsafe uncheck unreachable
-            _ => unreachable!(),
-        };
+    // 只影响还没读到的byte
+    pub fn set_policy(&mut self, policy: Utf8Policy) {
+        self.policy = policy;
+    }
 
-        self.cursor += char_len as usize;
-        chr
-    }
-
-    fn get_byte(&self, index: usize) -> u8 {
-        unsafe { *self.buf.get_unchecked(index) }
-    }
-
-    fn set_remain(&mut self, a: u8, head_len: u8, tail_len: u8) {
-        self.remain = match head_len {
-            1 => Remain {
-                head_len,
-                tail_len,
-                a,
-                b: 0,
-                c: 0,
-            },
-            2 => Remain {
-                head_len,
-                tail_len,
-                a,
-                b: self.get_byte(self.cursor + 1),
-                c: 0,
-            },
-            3 => Remain {
-                head_len,
-                tail_len,
-                a,
-                b: self.get_byte(self.cursor + 1),
-                c: self.get_byte(self.cursor + 2),
-            },
-            _ => unreachable!(),
-        };
+    // 到目前为止遇到的不合法的byte，Error的时候也会记下来
+    pub fn take_invalid(&mut self) -> Vec<InvalidByte> {
+        std::mem::take(&mut self.invalid)
     }
 
-    fn get_remain_unite(&mut self) -> Chr {
-        let ret = match self.remain.head_len {
-            1 => match self.remain.tail_len {
-                1 => Chr::Two([self.remain.a, self.get_byte(self.cursor)]),
-                2 => Chr::Three([
-                    self.remain.a,
-                    self.get_byte(self.cursor),
-                    self.get_byte(self.cursor + 1),
-                ]),
-                3 => Chr::Four([
-                    self.remain.a,
-                    self.get_byte(self.cursor),
-                    self.get_byte(self.cursor + 1),
-                    self.get_byte(self.cursor + 2),
-                ]),
-                _ => unreachable!(),
-            },
-            2 => match self.remain.tail_len {
-                1 => Chr::Three([self.remain.a, self.remain.b, self.get_byte(self.cursor)]),
-                2 => Chr::Four([
-                    self.remain.a,
-                    self.remain.b,
-                    self.get_byte(self.cursor),
-                    self.get_byte(self.cursor + 1),
-				]),
-                _ => unreachable!(),
-            },
-            3 => match self.remain.tail_len {
-                1 => Chr::Four([
-                    self.remain.a,
-                    self.remain.b,
-                    self.remain.c,
-                    self.get_byte(self.cursor),
-                ]),
-                _ => unreachable!(),
-            },
-            _ => unreachable!(),
+    pub fn peek(&mut self) -> Option<&io::Result<Chr>>
+    where
+        R: Read,
+    {
+        if self.peeked.is_none() {
+            self.peeked_at = self.offset + self.cursor as u64;
+            self.peeked = Some(self.read_chr());
+        }
+        self.peeked.as_ref().unwrap().as_ref()
+    }
+
+    // 下一个要读的字符在输入里的位置，peek过的字符还没算读过
+    pub fn position(&self) -> u64 {
+        if self.peeked.is_some() {
+            self.peeked_at
+        } else {
+            self.offset + self.cursor as u64
+        }
+    }
+
+    // 从buf里成段地取出纯文本，直到set里的byte、不合法或者不完整的字符为止
+    // 有peek过的字符或者buf读完了的时候返回空，这时用next一个一个读
+    pub fn next_plain(&mut self, set: &[u8]) -> &[u8] {
+        if self.peeked.is_some() {
+            return &[];
+        }
+
+        let start = self.cursor;
+        self.cursor += plain_len(&self.buf[start..self.buf_len], set);
+        &self.buf[start..self.cursor]
+    }
+}
+
+impl<R: Read> ChrIter<R> {
+    // 剩下的byte不够一个最长的字符时，挪到buf的开头再读，直到够了或者读完
+    fn fill(&mut self) -> io::Result<()> {
+        while self.buf_len - self.cursor < MAX_CHR_LEN && !self.eof {
+            self.buf.copy_within(self.cursor..self.buf_len, 0);
+            self.offset += self.cursor as u64;
+            self.buf_len -= self.cursor;
+            self.cursor = 0;
+
+            match self.r.read(&mut self.buf[self.buf_len..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.buf_len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_chr(&mut self) -> Option<io::Result<Chr>> {
+        if let Err(e) = self.fill() {
+            return Some(Err(e));
+        }
+
+        let data = &self.buf[self.cursor..self.buf_len];
+        let first = *data.first()?;
+        let invalid_len = match decode(data) {
+            Decoded::Chr(len) => {
+                let chr = Chr::from_bytes(&data[..len]);
+                self.cursor += len;
+                return Some(Ok(chr));
+            }
+            Decoded::Invalid(len) => len,
+            // fill过了，只有读完的时候才会不完整
+            Decoded::Incomplete => data.len(),
         };
 
-        self.cursor += self.remain.tail_len as usize;
-        self.remain.clear();
-        ret
+        let offset = self.offset + self.cursor as u64;
+        let len = match self.policy {
+            Utf8Policy::Replace => invalid_len,
+            Utf8Policy::Error | Utf8Policy::PassThrough => 1,
+        };
+        for (i, &byte) in data[..len].iter().enumerate() {
+            self.invalid.push(InvalidByte {
+                offset: offset + i as u64,
+                byte,
+            });
+        }
+        self.cursor += len;
+
+        Some(match self.policy {
+            Utf8Policy::Error => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                InvalidByte {
+                    offset,
+                    byte: first,
+                },
+            )),
+            Utf8Policy::Replace => Ok(REPLACEMENT),
+            Utf8Policy::PassThrough => Ok(Chr::One([first])),
+        })
     }
 }
 
@@ -207,64 +336,154 @@ impl<R: Read> Iterator for ChrIter<R> {
     type Item = io::Result<Chr>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // 那么我们先去判断这个len，大概有三种情况，初始数值、等于buf.len()、小于buf.len()
-        // 我们还需要一个cursor去表明当前的buf的位置！
-        loop {
-            if self.buf_len == usize::MAX {
-                // println!("reach the MAX!");
-                // 每次在这个节点时，需要判断下上个remain是否不为空，即存在剩余
-                // byte，我们把它拼接起来。但还是先写构造时，清楚是如何构造的
-                self.buf_len = match self.r.read(&mut self.buf) {
-                    Ok(l) => l,
-                    Err(e) => return Some(Err(e)),
-                };
-
-                if self.buf_len == 0 {
-                    return None;
-                }
+        match self.peeked.take() {
+            Some(chr) => chr,
+            None => self.read_chr(),
+        }
+    }
+}
 
-                if !self.remain.is_empty() {
-                    return Some(Ok(self.get_remain_unite()));
-                }
+// 检查data开头的字符，不允许overlong、代理对（U+D800..U+DFFF）和大于U+10FFFF的编码
+// 不合法的时候返回从开头起还可能是合法字符的那一段的长度，和WHATWG的解码器一样
+pub fn decode(data: &[u8]) -> Decoded {
+    let first = match data.first() {
+        Some(&b) => b,
+        None => return Decoded::Incomplete,
+    };
+    // 第二个byte的范围，后面的都是 0x80..=0xBF
+    let (len, second) = match first {
+        0x00..=0x7f => return Decoded::Chr(1),
+        0xc2..=0xdf => (2, 0x80..=0xbf),
+        0xe0 => (3, 0xa0..=0xbf),
+        0xed => (3, 0x80..=0x9f),
+        0xe1..=0xef => (3, 0x80..=0xbf),
+        0xf0 => (4, 0x90..=0xbf),
+        0xf4 => (4, 0x80..=0x8f),
+        0xf1..=0xf3 => (4, 0x80..=0xbf),
+        _ => return Decoded::Invalid(1),
+    };
+
+    for i in 1..len {
+        let b = match data.get(i) {
+            Some(&b) => b,
+            None => return Decoded::Incomplete,
+        };
+        let valid = if i == 1 {
+            second.contains(&b)
+        } else {
+            (0x80..=0xbf).contains(&b)
+        };
+        if !valid {
+            return Decoded::Invalid(i);
+        }
+    }
+    Decoded::Chr(len)
+}
 
-                let (len, a) = self.cur_u8_unite_len();
-
-                return Some(Ok(self.chr(len, a)));
-            } else if self.buf_len == self.buf.len() {
-                // println!("reach the eq!");
-                // 此时在判断self.cursor和buf.len()的关系~
-                if self.cursor < self.buf_len {
-                    let (char_len, a) = self.cur_u8_unite_len();
-                    let tail_len = char_len as i32 + self.cursor as i32 - self.buf_len as i32;
-
-                    if tail_len > 0 {
-                        let head_len = char_len - tail_len as u8;
-                        self.set_remain(a, head_len, tail_len as u8);
-                        self.set_load_buf();
-                    } else {
-                        return Some(Ok(self.chr(char_len, a)));
-                    }
-                } else if self.cursor == self.buf_len {
-                    self.set_load_buf();
-                } else {
-                    unreachable!()
-                }
-            } else if self.buf_len < self.buf.len() {
-                // println!("reach the le!");
-                // 如果当前的cur_len小于buf.len()，自然是读到最后一轮了
-                // 那么我们再次判断cursor与self.cur_len的关系
-                if self.cursor == self.buf_len {
-                    return None;
-                } else if self.cursor < self.buf_len {
-                    let (len, a) = self.cur_u8_unite_len();
-                    return Some(Ok(self.chr(len, a)));
-                } else {
-                    unreachable!()
-                }
-            } else {
-                unreachable!()
+const LO: u64 = 0x0101_0101_0101_0101;
+const HI: u64 = 0x8080_8080_8080_8080;
+
+// data开头由合法的完整字符组成、不含set里的byte的最长的一段，set里只能是ascii
+// 合法的多byte字符里没有ascii，所以找到的位置一定在字符的边界上
+pub fn plain_len(data: &[u8], set: &[u8]) -> usize {
+    let stop = find_any(data, set).unwrap_or(data.len());
+    let mut walk = 0;
+    while walk < stop {
+        // ascii按8个byte一组跳过
+        if walk + 8 <= stop && read_u64(&data[walk..]) & HI == 0 {
+            walk += 8;
+        } else {
+            match decode(&data[walk..stop]) {
+                Decoded::Chr(len) => walk += len,
+                // 不合法或者不完整的字符留给next处理
+                Decoded::Invalid(_) | Decoded::Incomplete => return walk,
+            }
+        }
+    }
+    stop
+}
+
+// set里任意一个byte第一次出现的位置，x86_64上用SSE2/AVX2，其他平台一次比较8个byte
+pub fn find_any(data: &[u8], set: &[u8]) -> Option<usize> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { x86::find_any_avx2(data, set) };
+        }
+        unsafe { x86::find_any_sse2(data, set) }
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    find_any_swar(data, set)
+}
+
+// 一个u64里同时比较8个byte，值为0的byte会让最高位变成1
+pub fn find_any_swar(data: &[u8], set: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i + 8 <= data.len() {
+        let word = read_u64(&data[i..]);
+        let mut found = 0;
+        for &b in set {
+            let x = word ^ (LO * b as u64);
+            found |= x.wrapping_sub(LO) & !x & HI;
+        }
+        // 借位只会影响更高的byte，最低的那个是准确的
+        if found != 0 {
+            return Some(i + found.trailing_zeros() as usize / 8);
+        }
+        i += 8;
+    }
+    find_any_scalar(&data[i..], set).map(|x| i + x)
+}
+
+pub fn find_any_scalar(data: &[u8], set: &[u8]) -> Option<usize> {
+    data.iter().position(|b| set.contains(b))
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&data[..8]);
+    u64::from_le_bytes(word)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::find_any_swar;
+    use std::arch::x86_64::*;
+
+    // SSE2在x86_64上一定有
+    pub unsafe fn find_any_sse2(data: &[u8], set: &[u8]) -> Option<usize> {
+        let mut i = 0;
+        while i + 16 <= data.len() {
+            let chunk = _mm_loadu_si128(data.as_ptr().add(i) as *const __m128i);
+            let mut found = _mm_setzero_si128();
+            for &b in set {
+                found = _mm_or_si128(found, _mm_cmpeq_epi8(chunk, _mm_set1_epi8(b as i8)));
+            }
+            let mask = _mm_movemask_epi8(found);
+            if mask != 0 {
+                return Some(i + mask.trailing_zeros() as usize);
+            }
+            i += 16;
+        }
+        find_any_swar(&data[i..], set).map(|x| i + x)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn find_any_avx2(data: &[u8], set: &[u8]) -> Option<usize> {
+        let mut i = 0;
+        while i + 32 <= data.len() {
+            let chunk = _mm256_loadu_si256(data.as_ptr().add(i) as *const __m256i);
+            let mut found = _mm256_setzero_si256();
+            for &b in set {
+                found = _mm256_or_si256(found, _mm256_cmpeq_epi8(chunk, _mm256_set1_epi8(b as i8)));
+            }
+            let mask = _mm256_movemask_epi8(found);
+            if mask != 0 {
+                return Some(i + mask.trailing_zeros() as usize);
             }
+            i += 32;
         }
+        find_any_sse2(&data[i..], set).map(|x| i + x)
     }
 }
 
@@ -285,63 +504,216 @@ pub const fn utf8_len(first: u8) -> u8 {
     }
 }
 
+const fn mask_tail_six(byte: u8) -> u8 {
+    const MASK: u8 = 0b00_111111;
+    byte & MASK
+}
+
+const fn mask_tail_five(byte: u8) -> u8 {
+    const MASK: u8 = 0b000_11111;
+    byte & MASK
+}
+
+const fn mask_tail_four(byte: u8) -> u8 {
+    const MASK: u8 = 0b0000_1111;
+    byte & MASK
+}
+
+#[allow(clippy::unusual_byte_groupings)]
+const fn mask_tail_three(byte: u8) -> u8 {
+    const MASK: u8 = 0b00000_111;
+    byte & MASK
+}
+
 #[test]
-fn test2() {
-    let f = File::open("../123.txt").unwrap();
-    let chars = ChrIter::new(f);
-    for c in chars {
-        println!("{:?}", c);
+fn test_scan() {
+    // SIMD、SWAR和逐个byte的结果一样，next_plain和只用next读出来的内容一样
+    let set = b"\\ \t\n\r${[!";
+    let pieces: [&[u8]; 8] = [
+        b"a",
+        b"\n",
+        b"$",
+        "中".as_bytes(),
+        "é".as_bytes(),
+        &[0xe4],
+        &[0x80],
+        b"plain text ",
+    ];
+    let mut seed = 7u64;
+    for len in 0..600 {
+        let mut data = Vec::new();
+        while data.len() < len * 31 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let piece = pieces[(seed >> 33) as usize % pieces.len()];
+            // 大部分是纯文本，这样能走到整块跳过的分支
+            if (seed >> 40) & 3 == 0 {
+                data.extend_from_slice(piece)
+            } else {
+                data.extend_from_slice(b"abcdefgh")
+            }
+        }
+        for start in [0, 1, 3, 7] {
+            let data = &data[start.min(data.len())..];
+            let expected = find_any_scalar(data, set);
+            assert_eq!(expected, find_any_swar(data, set));
+            assert_eq!(expected, find_any(data, set));
+            #[cfg(target_arch = "x86_64")]
+            unsafe {
+                assert_eq!(expected, x86::find_any_sse2(data, set));
+                if is_x86_feature_detected!("avx2") {
+                    assert_eq!(expected, x86::find_any_avx2(data, set));
+                }
+            }
+        }
+
+        let mut plain = Vec::new();
+        let mut iter = ChrIter::new(&data[..]);
+        loop {
+            plain.extend_from_slice(iter.next_plain(set));
+            // PassThrough的时候读出来的byte数就是位置
+            assert_eq!(plain.len() as u64, iter.position());
+            iter.peek();
+            assert_eq!(plain.len() as u64, iter.position());
+            match iter.next() {
+                Some(c) => plain.extend_from_slice((&c.unwrap()).into()),
+                None => break,
+            }
+        }
+        let mut slow = Vec::new();
+        for c in ChrIter::new(&data[..]) {
+            slow.extend_from_slice((&c.unwrap()).into());
+        }
+        assert_eq!(slow, plain);
     }
 }
-// 0xxxxxxx
-// 110xxxxx 10xxxxxx
-// 1110xxxx 10xxxxxx 10xxxxxx
-// 11110xxx 10xxxxxx 10xxxxxx 10xxxxxx
-// pub fn unicode(chr: Chre) -> char {
-//     unsafe {
-//         match chr {
-//             Chre::OneByte(bytes) => *bytes.get_unchecked(0) as char,
-//             Chre::TwoByte(bytes) => {
-// 				let mut chr = 0_u32;
-//     			let &a = bytes.get_unchecked(0);
-//     			let &b = bytes.get_unchecked(1);
-// 				let b_last_six = mask_tail_six(b) as u32;
-// 				chr |= b_last_six;
-// 				let a_last_five = mask_tail_five(a) as u32;
-
-// 				todo!()
-//     		},
-//             Chre::ThreeByte(_) => todo!(),
-//             Chre::FourByte(_) => todo!(),
-//         }
-//     }
-// }
 
-// const fn mask_tail_six(byte: u8) -> u8 {
-//     const MASK: u8 = 0b00_111111;
-//     byte & MASK
-// }
+#[test]
+fn test_policy() {
+    // 每次只读几个byte，这样不完整的字符会跨过两次read
+    struct Chunks<'a>(&'a [u8], usize);
+    impl Read for Chunks<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
 
-// const fn mask_tail_five(byte: u8) -> u8 {
-//     const MASK: u8 = 0b000_11111;
-//     byte & MASK
-// }
+    let collect = |data: &[u8], chunk: usize, policy: Utf8Policy| {
+        let mut iter = ChrIter::with_policy(Chunks(data, chunk), policy);
+        let mut out = Vec::new();
+        let mut errors = Vec::new();
+        for c in iter.by_ref() {
+            match c {
+                Ok(c) => out.extend_from_slice((&c).into()),
+                Err(e) => {
+                    let invalid = e.get_ref().unwrap().downcast_ref::<InvalidByte>();
+                    errors.push(invalid.unwrap().offset);
+                }
+            }
+        }
+        (out, errors, iter.take_invalid())
+    };
+
+    let pieces: [&[u8]; 12] = [
+        b"ab",
+        "中".as_bytes(),
+        "😀".as_bytes(),
+        &[0xc0, 0xaf],             // overlong的 '/'
+        &[0xe0, 0x80, 0xaf],       // overlong
+        &[0xed, 0xa0, 0x80],       // 代理对
+        &[0xf4, 0x90, 0x80, 0x80], // 大于U+10FFFF
+        &[0xf8, 0x88],
+        &[0xff],
+        &[0xe4, 0xb8],
+        &[0x80],
+        &[0xf0, 0x9f, 0x98],
+    ];
+    let mut seed = 3u64;
+    for len in 0..2000 {
+        let mut data = Vec::new();
+        for _ in 0..len % 40 {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            data.extend_from_slice(pieces[(seed >> 33) as usize % pieces.len()]);
+        }
 
-// const fn mask_tail_four(byte: u8) -> u8 {
-//     const MASK: u8 = 0b0000_1111;
-//     byte & MASK
-// }
+        // 和std的from_utf8_lossy一样，一段不合法的byte换成一个U+FFFD
+        let chunk = len % 7 + 1;
+        let (replaced, _, invalid) = collect(&data, chunk, Utf8Policy::Replace);
+        assert_eq!(String::from_utf8_lossy(&data).as_bytes(), &replaced[..]);
+
+        let (passed, _, passed_invalid) = collect(&data, chunk, Utf8Policy::PassThrough);
+        assert_eq!(data, passed);
+        assert_eq!(invalid, passed_invalid);
+
+        let (valid, errors, error_invalid) = collect(&data, chunk, Utf8Policy::Error);
+        assert_eq!(invalid, error_invalid);
+        assert_eq!(invalid.iter().map(|x| x.offset).collect::<Vec<_>>(), errors);
+        let valid_len = data.len() - invalid.len();
+        assert_eq!(valid_len, valid.len());
+        for x in &invalid {
+            assert_eq!(data[x.offset as usize], x.byte);
+        }
+        assert_eq!(std::str::from_utf8(&data).is_ok(), invalid.is_empty());
+    }
+}
 
-// const fn mask_tail_three(byte: u8) -> u8 {
-//     const MASK: u8 = 0b00000_111;
-//     byte & MASK
-// }
+#[test]
+fn test_unicode() {
+    // 所有的char转成Chr再转回来都不变，和ChrIter读出来的一样
+    for c in (0..=0x10ffff).filter_map(char::from_u32) {
+        let chr = Chr::from(c);
+        assert_eq!(c, chr.to_char());
+        let mut buf = [0; 4];
+        assert_eq!(c.encode_utf8(&mut buf).as_bytes(), <&[u8]>::from(&chr));
+        assert_eq!(c.is_ascii_punctuation(), c.is_ascii() && chr.is_punctuation());
+    }
+    let text = "a中é😀\u{fffd}";
+    let chrs: Vec<Chr> = ChrIter::new(text.as_bytes()).map(|c| c.unwrap()).collect();
+    assert_eq!(text, chrs.iter().map(|c| c.to_string()).collect::<String>());
+    assert_eq!('\u{fffd}', Chr::from(0xff).to_char());
+    assert_eq!("\u{fffd}", format!("{}", Chr::One([0x80])));
+    assert_eq!("  中", format!("{:>3}", Chr::from('中')));
+
+    for c in [' ', '\t', '\n', '\u{c}', '\r', '\u{a0}', '\u{2003}', '\u{3000}'] {
+        assert!(Chr::from(c).is_whitespace(), "{:?}", c);
+    }
+    for c in ['a', '\u{b}', '\u{85}', '\u{200b}', '\u{2028}', '中'] {
+        assert!(!Chr::from(c).is_whitespace(), "{:?}", c);
+    }
+    for c in ['!', '$', '~', '，', '。', '「', '€', '©', '…', '\u{2014}', '😀'] {
+        assert!(Chr::from(c).is_punctuation(), "{:?}", c);
+    }
+    for c in ['a', ' ', '中', 'é', '\u{301}', '0'] {
+        assert!(!Chr::from(c).is_punctuation(), "{:?}", c);
+    }
 
-// #[test]
-// fn test() {
-//     println!("0b{:08b}", mask_tail_six(0b10_111101));
-// }
+    let width = |s: &str| s.chars().map(|c| Chr::from(c).width()).sum::<usize>();
+    assert_eq!(1, width("a"));
+    assert_eq!(2, width("中"));
+    assert_eq!(2, width("가"));
+    assert_eq!(2, width("😀"));
+    assert_eq!(2, width("Ａ"));
+    assert_eq!(1, width("e\u{301}"));
+    assert_eq!(0, width("\u{200b}\u{7}\u{9b}"));
+    assert_eq!(1, width("é"));
+    assert_eq!(1, Chr::from(0xff).width());
+}
 
+#[test]
+fn test2() {
+    use std::fs::File;
+    let f = File::open("../123.txt").unwrap();
+    let chars = ChrIter::new(f);
+    for c in chars {
+        println!("{:?}", c);
+    }
+}
 // #[test]
 // fn test() {
 //     assert_eq!(1, utf8_len(b'a'));
@@ -0,0 +1,268 @@
+// 属性列表：空白分隔，可以用引号包起来；key=value 是具名参数，
+// {#id .class key=value} 里的 #id 和 .class 分别等同于 id=... 和 class=...
+// 指令和短代码的参数也用同样的语法
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+pub struct Attrs {
+    positional: Vec<String>,
+    named: Vec<(String, String)>,
+}
+
+impl Attrs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(data: &[u8]) -> Self {
+        let mut attrs = Attrs::default();
+        attrs.extend_parse(data);
+        attrs
+    }
+
+    pub fn extend_parse(&mut self, data: &[u8]) {
+        let s = String::from_utf8_lossy(data);
+        let mut chars = s.chars().peekable();
+
+        loop {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '{' || c == '}' {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let key = read_word(&mut chars, true);
+            if chars.peek() == Some(&'=') {
+                chars.next();
+                let value = read_word(&mut chars, false);
+                self.set(&key, &value);
+            } else if let Some(id) = key.strip_prefix('#').filter(|x| !x.is_empty()) {
+                self.set("id", id);
+            } else if let Some(class) = key.strip_prefix('.').filter(|x| !x.is_empty()) {
+                self.set("class", class);
+            } else {
+                self.positional.push(key);
+            }
+        }
+    }
+
+    // class 可以出现多次，合并成一个；其他的后面的覆盖前面的
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.named.iter_mut().find(|(k, _v)| k == key) {
+            Some((_k, v)) if key == "class" => {
+                v.push(' ');
+                v.push_str(value);
+            }
+            Some((_k, v)) => *v = value.to_string(),
+            None => self.named.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn extend(&mut self, other: &Attrs) {
+        self.positional.extend(other.positional.iter().cloned());
+        for (key, value) in &other.named {
+            self.set(key, value);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.named
+            .iter()
+            .find(|(k, _v)| k == key)
+            .map(|(_k, v)| v.as_str())
+    }
+
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+
+    pub fn named(&self) -> &[(String, String)] {
+        &self.named
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positional.is_empty() && self.named.is_empty()
+    }
+
+    // 把属性合并到一个html的开始标签里，标签里已经有的class会保留
+    // 不能做属性名的key丢掉，免得写出别的属性或者标签
+    pub fn merge_into(&self, start: &str) -> String {
+        let tag_end = match start.find('>') {
+            Some(idx) if start[..idx].ends_with('/') => idx - 1,
+            Some(idx) => idx,
+            None => return start.to_string(),
+        };
+        let (tag, rest) = start.split_at(tag_end);
+        let mut tag = tag.to_string();
+
+        for (key, value) in self.named.iter().filter(|(k, _v)| is_attr_name(k)) {
+            let value = escape_attr(value);
+            let existing = format!(" {}=\"", key);
+
+            match tag.find(&existing) {
+                Some(idx) => {
+                    let value_start = idx + existing.len();
+                    let value_end = value_start + tag[value_start..].find('"').unwrap_or(0);
+                    if key == "class" {
+                        tag.insert_str(value_end, &format!(" {}", value));
+                    } else {
+                        tag.replace_range(value_start..value_end, &value);
+                    }
+                }
+                None => tag.push_str(&format!(" {}=\"{}\"", key, value)),
+            }
+        }
+        for key in self.positional.iter().filter(|k| is_attr_name(k)) {
+            tag.push(' ');
+            tag.push_str(key);
+        }
+
+        tag + rest
+    }
+
+    // 转回源码里的写法 {#id .class key=value}，parse之后得到同样的属性
+    pub fn to_source(&self) -> String {
+        let mut parts = Vec::new();
+
+        for (key, value) in &self.named {
+            match key.as_str() {
+                "id" if is_plain(value) => parts.push(format!("#{}", value)),
+                "class" if !value.trim().is_empty() && value.split_whitespace().all(is_plain) => {
+                    parts.extend(value.split_whitespace().map(|x| format!(".{}", x)))
+                }
+                _ => parts.push(format!("{}={}", key, quote(value))),
+            }
+        }
+        for key in &self.positional {
+            parts.push(quote(key));
+        }
+
+        format!("{{{}}}", parts.join(" "))
+    }
+}
+
+// 不需要引号的值
+fn is_plain(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '{' | '}' | '=' | '"' | '\''))
+}
+
+fn quote(value: &str) -> String {
+    if is_plain(value) {
+        value.to_string()
+    } else if value.contains('"') {
+        format!("'{}'", value)
+    } else {
+        format!("\"{}\"", value)
+    }
+}
+
+// 去掉结尾的 {...}，返回 (剩下的内容, 属性列表)
+pub fn split_trailing_attrs(data: &[u8]) -> (&[u8], &[u8]) {
+    let end = data
+        .iter()
+        .rposition(|&b| b != b' ' && b != b'\t')
+        .map_or(0, |i| i + 1);
+
+    if end > 0 && data[end - 1] == b'}' {
+        if let Some(start) = data[..end].iter().rposition(|&b| b == b'{') {
+            let rest_end = data[..start]
+                .iter()
+                .rposition(|&b| b != b' ' && b != b'\t')
+                .map_or(0, |i| i + 1);
+            return (&data[..rest_end], &data[start..end]);
+        }
+    }
+
+    (data, &[])
+}
+
+// html属性名只允许 [A-Za-z0-9_:.-]
+fn is_attr_name(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b':' | b'.' | b'-'))
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// stop_at_eq：读key的时候遇到 '=' 停下
+fn read_word(chars: &mut std::iter::Peekable<std::str::Chars>, stop_at_eq: bool) -> String {
+    let mut word = String::new();
+
+    match chars.peek() {
+        // 属性列表到第一个 } 为止，引号里也不能有 }
+        Some(&quote) if quote == '"' || quote == '\'' => {
+            chars.next();
+            while let Some(c) = chars.next_if(|&c| c != '}') {
+                if c == quote {
+                    break;
+                }
+                word.push(c);
+            }
+        }
+        _ => {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '}' || (stop_at_eq && c == '=') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+        }
+    }
+
+    word
+}
+
+#[test]
+fn test() {
+    let attrs = Attrs::parse(br#"intro {#top .wide .dark width=640 title="Hello world"}"#);
+    assert_eq!(&["intro".to_string()], attrs.positional());
+    assert_eq!(Some("top"), attrs.get("id"));
+    assert_eq!(Some("wide dark"), attrs.get("class"));
+    assert_eq!(Some("640"), attrs.get("width"));
+    assert_eq!(Some("Hello world"), attrs.get("title"));
+    assert_eq!(None, attrs.get("height"));
+
+    assert_eq!(
+        r#"{#top .wide .dark width=640 title="Hello world" intro}"#,
+        attrs.to_source()
+    );
+    assert_eq!(attrs, Attrs::parse(attrs.to_source().as_bytes()));
+
+    let attrs = Attrs::parse(br#"{#x .y data-a="1&2"}"#);
+    assert_eq!(
+        "<h1 id=\"x\" class=\"y\" data-a=\"1&amp;2\">",
+        attrs.merge_into("<h1>")
+    );
+    assert_eq!(
+        r#"<div class="block-quote y" id="x" data-a="1&amp;2"><p>"#,
+        attrs.merge_into(r#"<div class="block-quote"><p>"#)
+    );
+    assert_eq!(
+        r#"<img id="x" class="y" data-a="1&amp;2">"#,
+        attrs.merge_into("<img>")
+    );
+
+    let attrs = Attrs::parse(br#"{onclick"=x "a b" x>y=1 data-ok="<b>" ok}"#);
+    assert_eq!(r#"<p data-ok="&lt;b&gt;" ok>"#, attrs.merge_into("<p>"));
+
+    assert_eq!(
+        (&b"Title"[..], &b"{#id}"[..]),
+        split_trailing_attrs(b"Title {#id} ")
+    );
+    assert_eq!((&b"Title"[..], &b""[..]), split_trailing_attrs(b"Title"));
+}
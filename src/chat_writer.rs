@@ -0,0 +1,368 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dialect {
+    // Slack的mrkdwn，只需要转义 & < >
+    Slack,
+    // Telegram的MarkdownV2，标点都要用 \ 转义
+    Telegram,
+}
+
+// 聊天软件里的受限标记，段落的换行接成一行，让客户端自己折行
+// 标题变成粗体，任务列表前面是 ☒ / ☐，脚注是 [1]
+pub struct ChatWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    dialect: Dialect,
+    // 当前块的行内内容，块结束时输出
+    line: String,
+    // 块的开头，比如任务的 ☒ 和脚注的 [1]
+    lead: String,
+    bold: bool,
+    // 链接文字在line里开始的位置
+    link: Option<(usize, String)>,
+    // 引用和容器的层数
+    quote: usize,
+    // 上一个输出的块，决定中间要不要空行
+    last: Last,
+    // 脚注的label，下标+1是编号
+    footnotes: Vec<Vec<u8>>,
+}
+
+#[derive(PartialEq, Eq)]
+enum Last {
+    Nothing,
+    Item,
+    Quoted,
+    Other,
+}
+
+impl<W: Write> ChatWriter<W> {
+    pub fn new(w: W, dialect: Dialect) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            dialect,
+            line: String::new(),
+            lead: String::new(),
+            bold: false,
+            link: None,
+            quote: 0,
+            last: Last::Nothing,
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn slack(w: W) -> Self {
+        Self::new(w, Dialect::Slack)
+    }
+
+    pub fn telegram(w: W) -> Self {
+        Self::new(w, Dialect::Telegram)
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn escape(&self, s: &str) -> String {
+        let mut escaped = String::with_capacity(s.len());
+        for c in s.chars() {
+            match (self.dialect, c) {
+                (Dialect::Slack, '&') => escaped.push_str("&amp;"),
+                (Dialect::Slack, '<') => escaped.push_str("&lt;"),
+                (Dialect::Slack, '>') => escaped.push_str("&gt;"),
+                (
+                    Dialect::Telegram,
+                    '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '='
+                    | '|' | '{' | '}' | '.' | '!' | '\\',
+                ) => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    // 代码里Telegram只转义 ` 和 \，Slack没法转义反引号
+    // Slack的代码块里只有 ``` 会结束代码块，连续的反引号每两个中间加一个零宽空格
+    // 行内代码里的反引号都会结束代码，只能换成 '
+    fn escape_code(&self, s: &str, inline: bool) -> String {
+        match self.dialect {
+            Dialect::Slack if inline => self.escape(&s.replace('`', "'")),
+            Dialect::Slack => {
+                let mut broken = String::with_capacity(s.len());
+                let mut run = 0;
+                for c in s.chars() {
+                    if c == '`' {
+                        if run == 2 {
+                            broken.push('\u{200b}');
+                            run = 0;
+                        }
+                        run += 1;
+                    } else {
+                        run = 0;
+                    }
+                    broken.push(c);
+                }
+                self.escape(&broken)
+            }
+            Dialect::Telegram => s.replace('\\', r"\\").replace('`', r"\`"),
+        }
+    }
+
+    fn push_text(&mut self, s: &str) {
+        let escaped = self.escape(s);
+        self.line.push_str(&escaped);
+    }
+
+    // 连续的空白只留一个，开头不留
+    fn push_space(&mut self) {
+        if !self.line.is_empty() && !self.line.ends_with(' ') {
+            self.line.push(' ');
+        }
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.buf_writer.write_all(s.as_bytes())
+    }
+
+    fn quote_prefix(&self) -> &'static str {
+        match (self.quote, self.dialect) {
+            (0, _) => "",
+            (_, Dialect::Slack) => "> ",
+            (_, Dialect::Telegram) => ">",
+        }
+    }
+
+    // 块之间空一行，连续的任务之间不空行
+    fn emit(&mut self, text: &str, is_item: bool) -> io::Result<()> {
+        let prefix = self.quote_prefix();
+        match self.last {
+            Last::Nothing => {}
+            Last::Item if is_item => {}
+            Last::Quoted if self.quote > 0 => {
+                self.write_str(&format!("{}\n", prefix.trim_end()))?
+            }
+            _ => self.write_str("\n")?,
+        }
+        for line in text.split('\n') {
+            self.write_str(prefix)?;
+            self.write_str(line)?;
+            self.write_str("\n")?;
+        }
+
+        self.last = if is_item {
+            Last::Item
+        } else if self.quote > 0 {
+            Last::Quoted
+        } else {
+            Last::Other
+        };
+        Ok(())
+    }
+
+    fn flush_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let lead = std::mem::take(&mut self.lead);
+        let line = line.trim_end();
+        if line.is_empty() && lead.is_empty() {
+            return Ok(());
+        }
+
+        let text = if std::mem::take(&mut self.bold) {
+            format!("{}*{}*", lead, line)
+        } else {
+            format!("{}{}", lead, line)
+        };
+        let is_item = lead.starts_with(['☒', '☐']);
+        self.emit(&text, is_item)
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|x| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(label.to_vec());
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+impl<W: Write> Renderer for ChatWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        self.flush_line()?;
+        match tag {
+            Tag::Header(_level) => self.bold = true,
+            Tag::BlockQuote => self.quote += 1,
+            Tag::TaskList(is_done) => self.lead = if is_done { "☒ " } else { "☐ " }.to_string(),
+            Tag::Footnote => {
+                let number = self.footnote_number(info);
+                self.lead = self.escape(&format!("[{}] ", number));
+            }
+            Tag::Admonition(kind) => {
+                self.quote += 1;
+                let title = if info.is_empty() {
+                    kind.default_title().to_string()
+                } else {
+                    String::from_utf8_lossy(info).into_owned()
+                };
+                self.push_text(&title);
+                self.bold = true;
+                self.flush_line()?;
+            }
+            Tag::Paragraph | Tag::CodeBlock | Tag::None => {}
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        self.flush_line()?;
+        if let Tag::BlockQuote | Tag::Admonition(_) = tag {
+            self.quote = self.quote.saturating_sub(1);
+            if self.last == Last::Quoted {
+                self.last = Last::Other;
+            }
+        }
+        Ok(())
+    }
+
+    fn inline_start(&mut self, inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, .. } => {
+                self.link = Some((self.line.len(), String::from_utf8_lossy(url).into_owned()))
+            }
+        }
+        Ok(())
+    }
+
+    // Slack是 <url|text>，Telegram是 [text](url)
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } => {
+                if let Some((start, url)) = self.link.take() {
+                    let text = self.line.split_off(start);
+                    let link = match self.dialect {
+                        Dialect::Slack if text.is_empty() => format!("<{}>", self.escape(&url)),
+                        Dialect::Slack => format!("<{}|{}>", self.escape(&url), text),
+                        Dialect::Telegram => {
+                            let text = if text.is_empty() {
+                                self.escape(&url)
+                            } else {
+                                text
+                            };
+                            let url = url.replace('\\', r"\\").replace(')', r"\)");
+                            format!("[{}]({})", text, url)
+                        }
+                    };
+                    self.line.push_str(&link);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.push_text(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn line_break(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.flush_line()?;
+        let content = self.escape_code(&String::from_utf8_lossy(content), false);
+        let block = match self.dialect {
+            Dialect::Slack => format!("```\n{}\n```", content),
+            Dialect::Telegram => {
+                format!("```{}\n{}\n```", String::from_utf8_lossy(lang), content)
+            }
+        };
+        self.emit(&block, false)
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        let src = String::from_utf8_lossy(src).into_owned();
+        let alt = String::from_utf8_lossy(if alt.is_empty() { src.as_bytes() } else { alt });
+        let alt = self.escape(&alt);
+        let image = match self.dialect {
+            Dialect::Slack => format!("<{}|{}>", self.escape(&src), alt),
+            Dialect::Telegram => {
+                format!(
+                    "[{}]({})",
+                    alt,
+                    src.replace('\\', r"\\").replace(')', r"\)")
+                )
+            }
+        };
+        self.line.push_str(&image);
+        Ok(())
+    }
+
+    // 没有公式，tex放在行内代码里
+    fn math(&mut self, tex: &[u8], _display: bool, _pos: Pos) -> io::Result<()> {
+        let tex = self.escape_code(&String::from_utf8_lossy(tex), true);
+        self.line.push_str(&format!("`{}`", tex));
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        let number = self.footnote_number(label);
+        self.push_text(&format!("[{}]", number));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_line()?;
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    let input = "# Release 1.2\n\nFixed a < b & c.\nSee [docs](http://a.b/x_y)[^1]\n\n> quoted\n\n- [x] done\n- [ ] todo\n\n```rs\nlet a = `b`; // ````\n```\n\n[^1]: note!\n";
+    let render = |writer: ChatWriter<Vec<u8>>| {
+        let mut parser = StatefulParser::with_renderer(input.as_bytes(), writer);
+        parser.parse_and_write().unwrap();
+        String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap()
+    };
+
+    assert_eq!(
+        "*Release 1.2*\n\nFixed a &lt; b &amp; c. See <http://a.b/x_y|docs>[1]\n\n> quoted\n\n☒ done\n☐ todo\n\n```\nlet a = `b`; // ``\u{200b}``\n```\n\n[1] note!\n",
+        render(ChatWriter::slack(Vec::new()))
+    );
+    assert_eq!(
+        "*Release 1\\.2*\n\nFixed a < b & c\\. See [docs](http://a.b/x_y)\\[1\\]\n\n>quoted\n\n☒ done\n☐ todo\n\n```rs\nlet a = \\`b\\`; // \\`\\`\\`\\`\n```\n\n\\[1\\] note\\!\n",
+        render(ChatWriter::telegram(Vec::new()))
+    );
+}
@@ -0,0 +1,50 @@
+use crate::attr::Attrs;
+use crate::html_writer::HtmlWriter;
+use std::collections::HashMap;
+use std::io;
+
+// 自定义的块级指令 ::: name {attrs} 和行内短代码 {{< name args >}} 的处理器
+// content：块级指令是原始的markdown内容，短代码为空
+pub trait Directive {
+    fn render(&mut self, args: &Args, content: &[u8], w: &mut dyn HtmlWriter) -> io::Result<()>;
+}
+
+impl<F> Directive for F
+where
+    F: FnMut(&Args, &[u8], &mut dyn HtmlWriter) -> io::Result<()>,
+{
+    fn render(&mut self, args: &Args, content: &[u8], w: &mut dyn HtmlWriter) -> io::Result<()> {
+        self(args, content, w)
+    }
+}
+
+#[derive(Default)]
+pub struct Directives {
+    blocks: HashMap<String, Box<dyn Directive>>,
+    shortcodes: HashMap<String, Box<dyn Directive>>,
+}
+
+impl Directives {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register_block<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.blocks.insert(name.to_string(), Box::new(d));
+    }
+
+    pub fn register_shortcode<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.shortcodes.insert(name.to_string(), Box::new(d));
+    }
+
+    pub fn block(&mut self, name: &[u8]) -> Option<&mut Box<dyn Directive>> {
+        self.blocks.get_mut(std::str::from_utf8(name).ok()?)
+    }
+
+    pub fn shortcode(&mut self, name: &[u8]) -> Option<&mut Box<dyn Directive>> {
+        self.shortcodes.get_mut(std::str::from_utf8(name).ok()?)
+    }
+}
+
+// 指令和短代码的参数跟属性列表是同一种语法
+pub type Args = Attrs;
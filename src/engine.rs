@@ -1,20 +1,39 @@
 use crate::error_handle::ErrorHandler;
 use crate::parser::Parser;
+use chr::Utf8Policy;
 
-pub struct MarkdownEngine {}
+#[derive(Default)]
+pub struct MarkdownEngine {
+    // 不设置的话用parser自己的
+    utf8_policy: Option<Utf8Policy>,
+}
 
 impl MarkdownEngine {
     pub fn new() -> Self {
-        Self {}
+        Self { utf8_policy: None }
+    }
+
+    // 不合法的utf8返回错误、换成U+FFFD或者原样保留
+    pub fn with_utf8_policy(policy: Utf8Policy) -> Self {
+        Self {
+            utf8_policy: Some(policy),
+        }
     }
 
     // ok 架构基本完成
     pub fn start<P: Parser, H: ErrorHandler>(&mut self, mut p: P, mut h: H) {
+        if let Some(policy) = self.utf8_policy {
+            p.set_utf8_policy(policy);
+        }
         let parse_res = p.parse_and_write();
 
         match parse_res {
             Ok(_x) => {}
             Err(e) => h.handle_error(e),
         }
+
+        for w in p.take_warnings() {
+            h.handle_warning(w);
+        }
     }
 }
@@ -1,9 +1,24 @@
 use crate::parser::ParseError;
+use crate::parser::Warning;
 
 pub trait ErrorHandler {
     fn handle_error(&mut self, e: ParseError);
+
+    fn handle_warning(&mut self, w: Warning);
 }
 
+// 用完之后还能看到处理的结果
+impl<H: ErrorHandler + ?Sized> ErrorHandler for &mut H {
+    fn handle_error(&mut self, e: ParseError) {
+        (**self).handle_error(e)
+    }
+
+    fn handle_warning(&mut self, w: Warning) {
+        (**self).handle_warning(w)
+    }
+}
+
+#[derive(Default)]
 pub struct ErrorHandlerImpl {}
 
 impl ErrorHandlerImpl {
@@ -17,4 +32,8 @@ impl ErrorHandler for ErrorHandlerImpl {
         println!("{:?}", e);
         panic!()
     }
+
+    fn handle_warning(&mut self, w: Warning) {
+        println!("{:?}", w);
+    }
 }
@@ -0,0 +1,193 @@
+use crate::parser::Warning;
+use crate::renderer::Pos;
+
+// 脚注：有定义的引用按第一次出现的顺序编号，定义的内容先缓存起来，到文档结尾统一输出
+#[derive(Default)]
+pub struct Footnotes {
+    refs: Vec<FootnoteRef>,
+    defs: Vec<FootnoteDef>,
+    // 已经知道有定义的label，定义可以在引用的后面
+    defined: Vec<Vec<u8>>,
+    // 所有的定义都知道了，不在defined里的就是没有定义
+    complete: bool,
+    // 当前正在写入的定义在defs中的下标
+    capturing: Option<usize>,
+}
+
+struct FootnoteRef {
+    label: Vec<u8>,
+    // 没有定义的引用没有编号
+    number: Option<usize>,
+    // 被引用了几次，每次引用都有一个回链
+    count: u32,
+    pos: Pos,
+}
+
+struct FootnoteDef {
+    label: Vec<u8>,
+    content: Vec<u8>,
+    pos: Pos,
+}
+
+pub struct FootnoteEntry<'a> {
+    pub number: usize,
+    pub ref_count: u32,
+    pub content: &'a [u8],
+}
+
+impl Footnotes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 读到定义的开头或者提前扫描到定义的时候调用
+    pub fn declare(&mut self, label: &[u8]) {
+        if !self.is_defined(label) {
+            self.defined.push(label.to_vec());
+        }
+    }
+
+    // 后面不会再有新的定义了
+    pub fn set_complete(&mut self) {
+        self.complete = true;
+    }
+
+    fn is_defined(&self, label: &[u8]) -> bool {
+        self.defined.iter().any(|x| x == label)
+    }
+
+    // 已经知道这个label有没有定义，可以输出它的引用了
+    pub fn is_resolved(&self, label: &[u8]) -> bool {
+        self.complete || self.is_defined(label)
+    }
+
+    // 返回 (编号, 第几次引用)，都从1开始；没有定义的返回None
+    // 要先用is_resolved确认，不然编号可能不对
+    pub fn reference(&mut self, label: &[u8], pos: Pos) -> Option<(usize, u32)> {
+        debug_assert!(self.is_resolved(label));
+
+        if let Some(r) = self.refs.iter_mut().find(|r| r.label == label) {
+            r.count += 1;
+            return r.number.map(|number| (number, r.count));
+        }
+
+        let number = if self.is_defined(label) {
+            Some(self.refs.iter().filter(|r| r.number.is_some()).count() + 1)
+        } else {
+            None
+        };
+        self.refs.push(FootnoteRef {
+            label: label.to_vec(),
+            number,
+            count: 1,
+            pos,
+        });
+        number.map(|number| (number, 1))
+    }
+
+    pub fn start_def(&mut self, label: &[u8], pos: Pos) {
+        debug_assert!(self.capturing.is_none());
+        self.declare(label);
+
+        self.defs.push(FootnoteDef {
+            label: label.to_vec(),
+            content: Vec::new(),
+            pos,
+        });
+        self.capturing = Some(self.defs.len() - 1);
+    }
+
+    pub fn end_def(&mut self) {
+        self.capturing = None;
+    }
+
+    pub fn capture(&mut self) -> Option<&mut Vec<u8>> {
+        match self.capturing {
+            Some(idx) => Some(&mut self.defs[idx].content),
+            None => None,
+        }
+    }
+
+    // 重复的定义以第一个为准
+    fn find_def(&self, label: &[u8]) -> Option<&FootnoteDef> {
+        self.defs.iter().find(|d| d.label == label)
+    }
+
+    // 被引用且有定义的脚注，按编号排序
+    pub fn entries(&self) -> impl Iterator<Item = FootnoteEntry<'_>> {
+        self.refs.iter().filter_map(|r| {
+            let def = self.find_def(&r.label)?;
+            Some(FootnoteEntry {
+                number: r.number?,
+                ref_count: r.count,
+                content: &def.content,
+            })
+        })
+    }
+
+    pub fn warnings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+
+        for r in &self.refs {
+            if self.find_def(&r.label).is_none() {
+                warnings.push(Warning::new(
+                    format!(
+                        "undefined footnote [^{}]",
+                        String::from_utf8_lossy(&r.label)
+                    ),
+                    r.pos,
+                ));
+            }
+        }
+
+        for (idx, d) in self.defs.iter().enumerate() {
+            if self.defs[..idx].iter().any(|x| x.label == d.label) {
+                warnings.push(Warning::new(
+                    format!(
+                        "duplicate footnote definition [^{}]",
+                        String::from_utf8_lossy(&d.label)
+                    ),
+                    d.pos,
+                ));
+            } else if !self.refs.iter().any(|r| r.label == d.label) {
+                warnings.push(Warning::new(
+                    format!("unused footnote [^{}]", String::from_utf8_lossy(&d.label)),
+                    d.pos,
+                ));
+            }
+        }
+
+        warnings
+    }
+}
+
+#[test]
+fn test() {
+    let mut footnotes = Footnotes::new();
+    footnotes.declare(b"a");
+    footnotes.declare(b"b");
+    assert!(!footnotes.is_resolved(b"missing"));
+    footnotes.set_complete();
+
+    assert_eq!(Some((1, 1)), footnotes.reference(b"a", Pos::new(1, 1, 0)));
+    assert_eq!(None, footnotes.reference(b"missing", Pos::new(1, 5, 4)));
+    assert_eq!(Some((2, 1)), footnotes.reference(b"b", Pos::new(1, 15, 14)));
+    assert_eq!(Some((1, 2)), footnotes.reference(b"a", Pos::new(2, 1, 19)));
+
+    footnotes.start_def(b"b", Pos::new(3, 1, 24));
+    footnotes.capture().unwrap().extend_from_slice(b"bbb");
+    footnotes.end_def();
+    footnotes.start_def(b"a", Pos::new(4, 1, 34));
+    footnotes.capture().unwrap().extend_from_slice(b"aaa");
+    footnotes.end_def();
+    footnotes.start_def(b"unused", Pos::new(5, 1, 44));
+    footnotes.end_def();
+    assert!(footnotes.capture().is_none());
+
+    let entries: Vec<_> = footnotes
+        .entries()
+        .map(|e| (e.number, e.ref_count, e.content))
+        .collect();
+    assert_eq!(vec![(1, 2, &b"aaa"[..]), (2, 1, &b"bbb"[..])], entries);
+    assert_eq!(2, footnotes.warnings().len());
+}
@@ -0,0 +1,279 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+// Gemini的gemtext：没有行内标记，一行就是一段
+// 链接不能写在段落里，段落结束后单独输出 => 行
+pub struct GemtextWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    // 当前块的文字和块里的链接
+    line: String,
+    links: Vec<(String, String)>,
+    // 链接外面有没有文字
+    plain: bool,
+    // 块开头的标记，比如 "## " 和 "* ☒ "
+    lead: String,
+    // 链接文字在line里开始的位置
+    link: Option<(usize, String)>,
+    // 引用和容器的层数
+    quote: usize,
+    // 上一个输出的是任务，连续的任务之间不空行
+    after_item: bool,
+    // 上一个输出的是引用，同一个引用里的块之间用 > 空行
+    after_quote: bool,
+    started: bool,
+    // 脚注的label，下标+1是编号
+    footnotes: Vec<Vec<u8>>,
+}
+
+impl<W: Write> GemtextWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            line: String::new(),
+            links: Vec::new(),
+            plain: false,
+            lead: String::new(),
+            link: None,
+            quote: 0,
+            after_item: false,
+            after_quote: false,
+            started: false,
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.buf_writer.write_all(s.as_bytes())
+    }
+
+    fn push_text(&mut self, s: &str) {
+        if self.link.is_none() && !s.trim().is_empty() {
+            self.plain = true;
+        }
+        self.line.push_str(s);
+    }
+
+    fn push_space(&mut self) {
+        if !self.line.is_empty() && !self.line.ends_with(' ') {
+            self.line.push(' ');
+        }
+    }
+
+    fn before_block(&mut self, is_item: bool) -> io::Result<()> {
+        if self.after_quote && self.quote > 0 {
+            self.write_str(">\n")?;
+        } else if self.started && !(is_item && self.after_item) {
+            self.write_str("\n")?;
+        }
+        self.started = true;
+        self.after_quote = self.quote > 0;
+        self.after_item = is_item;
+        Ok(())
+    }
+
+    fn flush_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let lead = std::mem::take(&mut self.lead);
+        let links = std::mem::take(&mut self.links);
+        let plain = std::mem::take(&mut self.plain);
+        let line = line.trim();
+
+        // 段落里只有链接的时候只输出链接行
+        let only_links = lead.is_empty() && !links.is_empty() && !plain;
+        if !only_links && (!line.is_empty() || !lead.is_empty()) {
+            let is_item = lead.starts_with("* ");
+            self.before_block(is_item)?;
+
+            let text = if self.quote > 0 {
+                format!("> {}{}", lead, line)
+            } else if lead.is_empty() && is_line_type(line) {
+                // 开头是其他行类型的标记，加个空格变成普通的文字行
+                format!(" {}", line)
+            } else {
+                format!("{}{}", lead, line)
+            };
+            self.write_str(text.trim_end())?;
+            self.write_str("\n")?;
+        }
+
+        if !links.is_empty() {
+            if only_links {
+                self.before_block(false)?;
+            }
+            for (url, text) in links {
+                self.write_str(format!("=> {} {}", url, text).trim_end())?;
+                self.write_str("\n")?;
+            }
+            self.after_item = false;
+        }
+        Ok(())
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|x| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(label.to_vec());
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+// 文字行的开头不能是 # * > => ```
+fn is_line_type(line: &str) -> bool {
+    line.starts_with(['#', '>'])
+        || line.starts_with("* ")
+        || line.starts_with("=>")
+        || line.starts_with("```")
+}
+
+impl<W: Write> Renderer for GemtextWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        self.flush_line()?;
+        match tag {
+            Tag::Header(level) => self.lead = format!("{} ", "#".repeat(level.min(3) as usize)),
+            Tag::BlockQuote => self.quote += 1,
+            Tag::TaskList(is_done) => self.lead = if is_done { "* ☒ " } else { "* ☐ " }.to_string(),
+            Tag::Footnote => self.lead = format!("[{}] ", self.footnote_number(info)),
+            Tag::Admonition(kind) => {
+                self.quote += 1;
+                self.line = if info.is_empty() {
+                    kind.default_title().to_string()
+                } else {
+                    String::from_utf8_lossy(info).into_owned()
+                };
+                self.flush_line()?;
+            }
+            Tag::Paragraph | Tag::CodeBlock | Tag::None => {}
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        self.flush_line()?;
+        if let Tag::BlockQuote | Tag::Admonition(_) = tag {
+            self.quote = self.quote.saturating_sub(1);
+            self.after_quote = false;
+        }
+        Ok(())
+    }
+
+    fn inline_start(&mut self, inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, .. } => {
+                self.link = Some((self.line.len(), String::from_utf8_lossy(url).into_owned()))
+            }
+        }
+        Ok(())
+    }
+
+    // 链接文字留在段落里，链接放到段落后面
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } => {
+                if let Some((start, url)) = self.link.take() {
+                    let text = self.line[start..].trim().to_string();
+                    self.links.push((url, text));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.push_text(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn line_break(&mut self) -> io::Result<()> {
+        self.push_space();
+        Ok(())
+    }
+
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.flush_line()?;
+        self.before_block(false)?;
+        // 开头的 ``` 后面是alt文字
+        self.write_str("```")?;
+        self.buf_writer.write_all(lang)?;
+        self.write_str("\n")?;
+        for line in content.split(|&b| b == b'\n') {
+            // 内容里的 ``` 会提前结束预格式化
+            if line.starts_with(b"```") {
+                self.write_str(" ")?;
+            }
+            self.buf_writer.write_all(line)?;
+            self.write_str("\n")?;
+        }
+        self.write_str("```\n")
+    }
+
+    // 图片也是链接行
+    fn image(&mut self, src: &[u8], alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        self.links.push((
+            String::from_utf8_lossy(src).into_owned(),
+            String::from_utf8_lossy(alt).into_owned(),
+        ));
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], _display: bool, _pos: Pos) -> io::Result<()> {
+        self.push_text(&String::from_utf8_lossy(tex));
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        let number = self.footnote_number(label);
+        self.push_text(&format!("[{}]", number));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush_line()?;
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    let input = "# Release 1.2\n\n#### Details\n\nRead the [docs](gemini://a.b/docs) and\nthe [faq](https://a.b/faq)[^1].\n\n[home](gemini://a.b)\n\n\\=> not a link\n\n> quoted\n\n- [x] done\n- [ ] todo\n\n```sh\n```nested\n```\n\n[^1]: note\n";
+    let mut parser =
+        StatefulParser::with_renderer(input.as_bytes(), GemtextWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        "# Release 1.2\n\n### Details\n\nRead the docs and the faq[1].\n=> gemini://a.b/docs docs\n=> https://a.b/faq faq\n\n=> gemini://a.b home\n\n => not a link\n\n> quoted\n\n* ☒ done\n* ☐ todo\n\n```sh\n ```nested\n```\n\n[1] note\n",
+        out
+    );
+}
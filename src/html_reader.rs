@@ -0,0 +1,975 @@
+use crate::attr::Attrs;
+use crate::markdown_writer::MarkdownWriter;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::schema::DefaultSchema;
+use crate::schema::Schema;
+use crate::tag::AdmonitionKind;
+use crate::tag::Tag;
+use std::io;
+use std::ops::Range;
+
+// 没有结束标签的元素
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+// 内容不是html，直接读到结束标签
+const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "title"];
+
+// 开始时会结束前面没有关闭的 <p>
+const BLOCK_ELEMENTS: [&str; 27] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "details",
+    "dl",
+    "div",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+// 只有里面的内容有用的元素，布局用的容器的属性会丢掉
+const TRANSPARENT_ELEMENTS: [&str; 7] = ["html", "body", "p", "div", "main", "article", "section"];
+
+// 不输出的元素，style是schema的css
+const SKIPPED_ELEMENTS: [&str; 2] = ["head", "style"];
+
+// 把html转换成markdown：用一个宽松的分词器建成树，再按schema的标签和class
+// 还原成Renderer的事件，交给MarkdownWriter输出
+// 不能转换的元素原样当做html输出，md_engine会把它们原样写回html
+pub struct HtmlReader<S: Schema> {
+    schema: S,
+}
+
+// schema里的一个开始标签，比如 <div class="block-quote">
+struct Sig {
+    name: String,
+    classes: Vec<String>,
+    // class以外的属性，xmlns不算
+    attrs: Vec<(String, String)>,
+}
+
+enum Node {
+    Element(Element),
+    // 原样的文本，实体还没有解码
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    // 值已经解码
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+    // 在源码里的范围，不能转换时原样输出
+    src: Range<usize>,
+}
+
+// 段落之外的块
+enum Piece<'n> {
+    // 用 <br> 分开的行
+    Paragraph(Vec<Vec<&'n Node>>),
+    Block(&'n Element),
+}
+
+// 块里的内容怎么输出，和parser的事件一致
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    // 顶层和 ::: 容器：每段是一个Paragraph
+    Paragraphs,
+    // 引用、任务、脚注和 > [!NOTE]：直接是行内内容
+    Inline,
+}
+
+// 一行里的空白：连续的只输出一个，行首和行尾的不输出
+#[derive(Default)]
+struct Spacing {
+    started: bool,
+    pending: bool,
+}
+
+impl Element {
+    fn attr(&self, key: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _v)| k == key)
+            .map(|(_k, v)| v.as_str())
+    }
+
+    fn classes(&self) -> impl Iterator<Item = &str> {
+        self.attr("class").unwrap_or("").split_whitespace()
+    }
+
+    fn has_class(&self, class: &str) -> bool {
+        self.classes().any(|x| x == class)
+    }
+
+    fn is(&self, sig: &Sig) -> bool {
+        self.name == sig.name
+            && sig.classes.iter().all(|x| self.has_class(x))
+            && sig
+                .attrs
+                .iter()
+                .all(|(k, v)| self.attr(k) == Some(v.as_str()))
+    }
+
+    // 没有解码的文本内容
+    fn text_content(&self, out: &mut String) {
+        for child in &self.children {
+            match child {
+                Node::Text(text) => out.push_str(text),
+                Node::Element(el) => el.text_content(out),
+            }
+        }
+    }
+}
+
+impl Spacing {
+    fn before<R: Renderer>(&mut self, r: &mut R) -> io::Result<()> {
+        if self.started && self.pending {
+            r.space()?;
+        }
+        self.started = true;
+        self.pending = false;
+        Ok(())
+    }
+
+    // 空白分开的词，&nbsp; 也算空白
+    fn words<R: Renderer>(&mut self, text: &str, r: &mut R) -> io::Result<()> {
+        let text = decode_entities(text, true);
+        for (idx, word) in text.split(char::is_whitespace).enumerate() {
+            if idx > 0 {
+                self.pending = true;
+            }
+            if !word.is_empty() {
+                self.before(r)?;
+                r.text(word.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for HtmlReader<DefaultSchema> {
+    fn default() -> Self {
+        Self::new(DefaultSchema)
+    }
+}
+
+impl<S: Schema> HtmlReader<S> {
+    pub fn new(schema: S) -> Self {
+        Self { schema }
+    }
+
+    pub fn read<R: Renderer>(&self, html: &[u8], r: &mut R) -> io::Result<()> {
+        let html = String::from_utf8_lossy(html);
+        let nodes = parse(&html);
+
+        let mut pieces = Vec::new();
+        self.pieces(&nodes, &mut pieces);
+        self.blocks(&pieces, Mode::Paragraphs, &html, r)?;
+        r.finish()
+    }
+
+    fn sig(&self, start: &str) -> Sig {
+        sigs(start).into_iter().next().unwrap_or(Sig {
+            name: String::new(),
+            classes: Vec::new(),
+            attrs: Vec::new(),
+        })
+    }
+
+    fn header_level(&self, el: &Element) -> Option<u8> {
+        (1..=6).find(|&level| el.is(&self.sig(Tag::header_start(&self.schema, level))))
+    }
+
+    fn is_quote(&self, el: &Element) -> bool {
+        el.name == "blockquote" || el.is(&self.sig(self.schema.block_quote_start()))
+    }
+
+    // 任务列表在schema里是 标记+内容 两个元素，内容是最后一个
+    fn task(&self, el: &Element) -> Option<(bool, bool)> {
+        [true, false].into_iter().find_map(|is_done| {
+            let sigs = sigs(Tag::TaskList(is_done).start_tag(&self.schema));
+            let (content, markers) = sigs.split_last()?;
+            if el.is(content) {
+                Some((is_done, true))
+            } else if markers.iter().any(|x| el.is(x)) {
+                Some((is_done, false))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn admonition(&self, el: &Element) -> Option<AdmonitionKind> {
+        AdmonitionKind::ALL
+            .into_iter()
+            .find(|&kind| el.is(&self.sig(Tag::Admonition(kind).start_tag(&self.schema))))
+    }
+
+    fn is_code(&self, el: &Element) -> bool {
+        el.name == "pre" || el.is(&self.sig(self.schema.code_block_start()))
+    }
+
+    fn is_footnotes(&self, el: &Element) -> bool {
+        el.is(&self.sig(self.schema.footnotes_start()))
+    }
+
+    // schema里有对应的块
+    fn is_known_block(&self, el: &Element) -> bool {
+        self.header_level(el).is_some()
+            || self.is_quote(el)
+            || self.task(el).is_some()
+            || self.admonition(el).is_some()
+            || self.is_code(el)
+            || self.is_footnotes(el)
+    }
+
+    // 把节点分成段落和块，<br><br> 是段落的分隔
+    fn pieces<'n, I: IntoIterator<Item = &'n Node>>(&self, nodes: I, out: &mut Vec<Piece<'n>>) {
+        let mut lines: Vec<Vec<&Node>> = vec![Vec::new()];
+
+        for node in nodes {
+            match node {
+                Node::Element(el) if SKIPPED_ELEMENTS.contains(&el.name.as_str()) => {}
+                Node::Element(el) if self.is_known_block(el) => {
+                    flush_paragraph(&mut lines, out);
+                    out.push(Piece::Block(el));
+                }
+                Node::Element(el) if TRANSPARENT_ELEMENTS.contains(&el.name.as_str()) => {
+                    flush_paragraph(&mut lines, out);
+                    self.pieces(&el.children, out);
+                }
+                Node::Element(el) if BLOCK_ELEMENTS.contains(&el.name.as_str()) => {
+                    flush_paragraph(&mut lines, out);
+                    out.push(Piece::Block(el));
+                }
+                Node::Element(el) if el.name == "br" => {
+                    if is_blank(lines.last().unwrap()) {
+                        flush_paragraph(&mut lines, out);
+                    } else {
+                        lines.push(Vec::new());
+                    }
+                }
+                _ => lines.last_mut().unwrap().push(node),
+            }
+        }
+        flush_paragraph(&mut lines, out);
+    }
+
+    fn blocks<R: Renderer>(
+        &self,
+        pieces: &[Piece],
+        mode: Mode,
+        html: &str,
+        r: &mut R,
+    ) -> io::Result<()> {
+        for piece in pieces {
+            match piece {
+                Piece::Paragraph(lines) if mode == Mode::Paragraphs => {
+                    r.block_start(Tag::Paragraph, b"", &Attrs::new(), Pos::default())?;
+                    self.lines(lines, html, r)?;
+                    r.block_end(Tag::Paragraph, Pos::default())?;
+                }
+                Piece::Paragraph(lines) => self.lines(lines, html, r)?,
+                Piece::Block(el) => self.block(el, mode, html, r)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn lines<R: Renderer>(&self, lines: &[Vec<&Node>], html: &str, r: &mut R) -> io::Result<()> {
+        for line in lines {
+            let mut spacing = Spacing::default();
+            for node in line {
+                self.inline(node, &mut spacing, html, r)?;
+            }
+            r.line_break()?;
+        }
+        Ok(())
+    }
+
+    fn children<R: Renderer>(
+        &self,
+        el: &Element,
+        mode: Mode,
+        html: &str,
+        r: &mut R,
+    ) -> io::Result<()> {
+        let mut pieces = Vec::new();
+        self.pieces(&el.children, &mut pieces);
+        self.blocks(&pieces, mode, html, r)
+    }
+
+    fn block<R: Renderer>(
+        &self,
+        el: &Element,
+        mode: Mode,
+        html: &str,
+        r: &mut R,
+    ) -> io::Result<()> {
+        let pos = Pos::default();
+
+        if let Some(level) = self.header_level(el) {
+            let sig = self.sig(Tag::header_start(&self.schema, level));
+            r.block_start(Tag::Header(level), b"", &Attrs::new(), pos)?;
+            let mut spacing = Spacing::default();
+            for child in &el.children {
+                self.inline(child, &mut spacing, html, r)?;
+            }
+            let attrs = extra_attrs(el, &sig, &[]);
+            if !attrs.is_empty() {
+                r.block_attrs(&attrs)?;
+            }
+            return r.block_end(Tag::Header(level), pos);
+        }
+
+        if self.is_quote(el) {
+            r.block_start(Tag::BlockQuote, b"", &Attrs::new(), pos)?;
+            self.children(el, Mode::Inline, html, r)?;
+            return r.block_end(Tag::BlockQuote, pos);
+        }
+
+        if let Some((is_done, is_content)) = self.task(el) {
+            if is_content {
+                r.block_start(Tag::TaskList(is_done), b"", &Attrs::new(), pos)?;
+                self.children(el, Mode::Inline, html, r)?;
+                r.block_end(Tag::TaskList(is_done), pos)?;
+            }
+            return Ok(());
+        }
+
+        if let Some(kind) = self.admonition(el) {
+            return self.admonition_block(el, kind, html, r);
+        }
+
+        if self.is_code(el) {
+            return self.code(el, r);
+        }
+
+        if self.is_footnotes(el) {
+            return self.footnotes(el, html, r);
+        }
+
+        // 不认识的块原样输出
+        if mode == Mode::Paragraphs {
+            r.block_start(Tag::Paragraph, b"", &Attrs::new(), pos)?;
+        }
+        raw(&html[el.src.clone()], &mut Spacing::default(), r)?;
+        r.line_break()?;
+        if mode == Mode::Paragraphs {
+            r.block_end(Tag::Paragraph, pos)?;
+        }
+        Ok(())
+    }
+
+    // 标题是默认的而且只有一段时是 > [!NOTE]，否则是 ::: 容器
+    fn admonition_block<R: Renderer>(
+        &self,
+        el: &Element,
+        kind: AdmonitionKind,
+        html: &str,
+        r: &mut R,
+    ) -> io::Result<()> {
+        let tag = Tag::Admonition(kind);
+        let title_sig = self.sig(self.schema.admonition_title_start());
+        let mut title = String::new();
+        let mut content = Vec::new();
+        for child in &el.children {
+            match child {
+                Node::Element(x) if x.is(&title_sig) && title.is_empty() => {
+                    x.text_content(&mut title)
+                }
+                _ => content.push(child),
+            }
+        }
+        let title = collapse(&decode_entities(&title, true));
+        let info = if title == kind.default_title() {
+            ""
+        } else {
+            title.as_str()
+        };
+
+        let mut pieces = Vec::new();
+        self.pieces(content, &mut pieces);
+        let mode = match &pieces[..] {
+            [Piece::Paragraph(_)] if info.is_empty() => Mode::Inline,
+            _ => Mode::Paragraphs,
+        };
+
+        let attrs = extra_attrs(el, &self.sig(tag.start_tag(&self.schema)), &[]);
+        r.block_start(tag, info.as_bytes(), &attrs, Pos::default())?;
+        self.blocks(&pieces, mode, html, r)?;
+        r.block_end(tag, Pos::default())
+    }
+
+    fn code<R: Renderer>(&self, el: &Element, r: &mut R) -> io::Result<()> {
+        // <pre><code class="language-x"> 或者 <pre class="language-x">
+        let code = el.children.iter().find_map(|x| match x {
+            Node::Element(x) if x.name == "code" => Some(x),
+            _ => None,
+        });
+        let lang = el
+            .classes()
+            .chain(code.into_iter().flat_map(|x| x.classes()))
+            .find_map(|x| x.strip_prefix("language-"))
+            .unwrap_or("");
+
+        let mut content = String::new();
+        code.unwrap_or(el).text_content(&mut content);
+        let content = decode_entities(&content, false);
+        // html会忽略 <pre> 后面的第一个换行
+        let content = content.strip_prefix('\n').unwrap_or(&content);
+        let content = content.strip_suffix('\n').unwrap_or(content);
+
+        let attrs = extra_attrs(el, &self.sig(self.schema.code_block_start()), &[]);
+        r.code_block(lang.as_bytes(), content.as_bytes(), &attrs, Pos::default())
+    }
+
+    // <li id="fn-1"> 是脚注的定义，去掉回链
+    fn footnotes<R: Renderer>(&self, el: &Element, html: &str, r: &mut R) -> io::Result<()> {
+        let mut items = Vec::new();
+        collect_items(el, &mut items);
+
+        for li in items {
+            let label = li.attr("id").unwrap_or("");
+            let label = label.strip_prefix("fn-").unwrap_or(label);
+            let content = li
+                .children
+                .iter()
+                .filter(|x| !matches!(x, Node::Element(x) if x.has_class("footnote-backref")));
+
+            r.block_start(
+                Tag::Footnote,
+                label.as_bytes(),
+                &Attrs::new(),
+                Pos::default(),
+            )?;
+            let mut pieces = Vec::new();
+            self.pieces(content, &mut pieces);
+            self.blocks(&pieces, Mode::Inline, html, r)?;
+            r.block_end(Tag::Footnote, Pos::default())?;
+        }
+        Ok(())
+    }
+
+    fn inline<R: Renderer>(
+        &self,
+        node: &Node,
+        spacing: &mut Spacing,
+        html: &str,
+        r: &mut R,
+    ) -> io::Result<()> {
+        let el = match node {
+            Node::Text(text) => return spacing.words(text, r),
+            Node::Element(el) => el,
+        };
+
+        match el.name.as_str() {
+            "br" => {
+                spacing.pending = true;
+                Ok(())
+            }
+            "a" if el.has_class("footnote-backref") => Ok(()),
+            "a" if el.attr("href").is_some() => {
+                spacing.before(r)?;
+                let url = el.attr("href").unwrap_or("");
+                let title = el.attr("title").unwrap_or("");
+                let link = Inline::Link {
+                    url: url.as_bytes(),
+                    title: title.as_bytes(),
+                };
+                r.inline_start(
+                    link,
+                    &extra_attrs(el, &self.sig(self.schema.link_start()), &["href", "title"]),
+                )?;
+                // 和parser一样，链接的文字是一个text
+                let mut text = String::new();
+                el.text_content(&mut text);
+                let text = decode_entities(&text, true);
+                let text = collapse(&text);
+                if !text.is_empty() {
+                    r.text(text.as_bytes())?;
+                }
+                r.inline_end(link)
+            }
+            "img" => {
+                spacing.before(r)?;
+                let attrs =
+                    extra_attrs(el, &self.sig(self.schema.image()), &["src", "alt", "title"]);
+                r.image(
+                    el.attr("src").unwrap_or("").as_bytes(),
+                    el.attr("alt").unwrap_or("").as_bytes(),
+                    el.attr("title").unwrap_or("").as_bytes(),
+                    &attrs,
+                )
+            }
+            _ if el.is(&self.sig(self.schema.footnote_ref_start())) => {
+                spacing.before(r)?;
+                let mut label = String::new();
+                el.text_content(&mut label);
+                r.footnote_ref(collapse(&label).as_bytes(), Pos::default())
+            }
+            "math" => match tex_annotation(el) {
+                Some(tex) => {
+                    spacing.before(r)?;
+                    let display = el.is(&self.sig(self.schema.math_display_start()));
+                    r.math(tex.as_bytes(), display, Pos::default())
+                }
+                None => raw(&html[el.src.clone()], spacing, r),
+            },
+            _ => raw(&html[el.src.clone()], spacing, r),
+        }
+    }
+}
+
+// 用MarkdownWriter输出，标签和class按DefaultSchema
+pub fn html_to_markdown(html: &[u8]) -> io::Result<Vec<u8>> {
+    let mut writer = MarkdownWriter::new(Vec::new());
+    HtmlReader::default().read(html, &mut writer)?;
+    writer.into_inner()
+}
+
+// 去掉开头和结尾的空行
+fn flush_paragraph<'n>(lines: &mut Vec<Vec<&'n Node>>, out: &mut Vec<Piece<'n>>) {
+    let mut taken = std::mem::replace(lines, vec![Vec::new()]);
+    while taken.last().is_some_and(|x| is_blank(x)) {
+        taken.pop();
+    }
+    let start = taken
+        .iter()
+        .position(|x| !is_blank(x))
+        .unwrap_or(taken.len());
+    if start < taken.len() {
+        out.push(Piece::Paragraph(taken.split_off(start)));
+    }
+}
+
+fn is_blank(line: &[&Node]) -> bool {
+    line.iter().all(|x| match x {
+        Node::Text(text) => decode_entities(text, true).trim().is_empty(),
+        Node::Element(_) => false,
+    })
+}
+
+fn collect_items<'n>(el: &'n Element, out: &mut Vec<&'n Element>) {
+    for child in &el.children {
+        if let Node::Element(x) = child {
+            if x.name == "li" {
+                out.push(x);
+            } else {
+                collect_items(x, out);
+            }
+        }
+    }
+}
+
+// <semantics> 里的 <annotation encoding="application/x-tex">
+fn tex_annotation(el: &Element) -> Option<String> {
+    for child in &el.children {
+        if let Node::Element(x) = child {
+            if x.name == "annotation" && x.attr("encoding") == Some("application/x-tex") {
+                let mut tex = String::new();
+                x.text_content(&mut tex);
+                return Some(decode_entities(&tex, false));
+            }
+            if let Some(tex) = tex_annotation(x) {
+                return Some(tex);
+            }
+        }
+    }
+    None
+}
+
+// 去掉schema里已经有的class和单独处理的属性，剩下的是 {#id .class key=value}
+fn extra_attrs(el: &Element, sig: &Sig, skip: &[&str]) -> Attrs {
+    let mut attrs = Attrs::new();
+    for (key, value) in &el.attrs {
+        match key.as_str() {
+            "class" => {
+                for class in value.split_whitespace() {
+                    if !sig.classes.iter().any(|x| x == class) && !class.starts_with("language-") {
+                        attrs.set("class", class);
+                    }
+                }
+            }
+            "xmlns" => {}
+            key if skip.contains(&key) || sig.attrs.iter().any(|(k, _v)| k == key) => {}
+            key => attrs.set(key, value),
+        }
+    }
+    attrs
+}
+
+// 不能转换的html原样输出，换行变成空格
+fn raw<R: Renderer>(src: &str, spacing: &mut Spacing, r: &mut R) -> io::Result<()> {
+    for (idx, word) in src.split(char::is_whitespace).enumerate() {
+        if idx > 0 {
+            spacing.pending = true;
+        }
+        if !word.is_empty() {
+            spacing.before(r)?;
+            r.text(word.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+fn collapse(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// keep_markup：文本里的 &lt; &gt; &amp; 保持原样，因为markdown里的文本会原样写回html
+// &nbsp; 这时变成普通的空格
+fn decode_entities(s: &str, keep_markup: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let end = match rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '#') {
+            Some(x) if rest[1 + x..].starts_with(';') => 1 + x,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let name = &rest[1..end];
+        let c = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => name
+                .strip_prefix("#x")
+                .or_else(|| name.strip_prefix("#X"))
+                .and_then(|x| u32::from_str_radix(x, 16).ok())
+                .or_else(|| name.strip_prefix('#').and_then(|x| x.parse().ok()))
+                .and_then(char::from_u32),
+        };
+
+        match c {
+            Some('&') if keep_markup => out.push_str("&amp;"),
+            Some('<') if keep_markup => out.push_str("&lt;"),
+            Some('>') if keep_markup => out.push_str("&gt;"),
+            Some('\u{a0}') if keep_markup => out.push(' '),
+            Some(c) => out.push(c),
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// 分词的结果
+enum Token {
+    Start {
+        name: String,
+        attrs: Vec<(String, String)>,
+        self_closing: bool,
+    },
+    End(String),
+    // 注释、<!DOCTYPE> 和 <?xml ?>
+    Ignored,
+}
+
+// 读 pos 处的一个标签，返回标签和结束的位置
+// 不是合法的标签时返回None，'<' 当做文本
+fn read_tag(html: &str, pos: usize) -> Option<(Token, usize)> {
+    let bytes = html.as_bytes();
+    let rest = &html[pos..];
+
+    if let Some(comment) = rest.strip_prefix("<!--") {
+        let end = comment.find("-->").map_or(html.len(), |x| pos + 4 + x + 3);
+        return Some((Token::Ignored, end));
+    }
+    if rest.starts_with("<!") || rest.starts_with("<?") {
+        return rest.find('>').map(|x| (Token::Ignored, pos + x + 1));
+    }
+
+    let is_end = rest.starts_with("</");
+    let mut i = pos + if is_end { 2 } else { 1 };
+    if !bytes.get(i).is_some_and(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name_start = i;
+    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && !matches!(bytes[i], b'>' | b'/') {
+        i += 1;
+    }
+    let name = html[name_start..i].to_ascii_lowercase();
+
+    if is_end {
+        let end = html[i..].find('>')?;
+        return Some((Token::End(name), i + end + 1));
+    }
+
+    let mut attrs = Vec::new();
+    loop {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        match bytes.get(i)? {
+            b'>' => {
+                return Some((
+                    Token::Start {
+                        name,
+                        attrs,
+                        self_closing: false,
+                    },
+                    i + 1,
+                ))
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'>') => {
+                return Some((
+                    Token::Start {
+                        name,
+                        attrs,
+                        self_closing: true,
+                    },
+                    i + 2,
+                ))
+            }
+            b'/' => {
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let key_start = i;
+        while i < bytes.len()
+            && !bytes[i].is_ascii_whitespace()
+            && !matches!(bytes[i], b'=' | b'>' | b'/')
+        {
+            i += 1;
+        }
+        let key = html[key_start..i].to_ascii_lowercase();
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+
+        let mut value = String::new();
+        if bytes.get(i) == Some(&b'=') {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            match bytes.get(i)? {
+                &quote @ (b'"' | b'\'') => {
+                    let end = html[i + 1..].find(quote as char)?;
+                    value = decode_entities(&html[i + 1..i + 1 + end], false);
+                    i += end + 2;
+                }
+                _ => {
+                    let value_start = i;
+                    while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' {
+                        i += 1;
+                    }
+                    value = decode_entities(&html[value_start..i], false);
+                }
+            }
+        }
+        if !key.is_empty() && !attrs.iter().any(|(k, _v)| *k == key) {
+            attrs.push((key, value));
+        }
+    }
+}
+
+// 宽松地建树：没有关闭的元素在父元素结束时关闭，多余的结束标签忽略
+fn parse(html: &str) -> Vec<Node> {
+    let mut stack = vec![Element {
+        name: String::new(),
+        attrs: Vec::new(),
+        children: Vec::new(),
+        src: 0..html.len(),
+    }];
+    let mut pos = 0;
+    let mut text_start = 0;
+
+    while let Some(idx) = html[pos..].find('<') {
+        let tag_start = pos + idx;
+        let Some((token, end)) = read_tag(html, tag_start) else {
+            pos = tag_start + 1;
+            continue;
+        };
+        push_text(&mut stack, &html[text_start..tag_start]);
+        pos = end;
+
+        match token {
+            Token::Ignored => {}
+            Token::Start {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                if BLOCK_ELEMENTS.contains(&name.as_str()) && stack.last().unwrap().name == "p" {
+                    close(&mut stack, tag_start);
+                }
+                if name == "li" {
+                    let list = stack.iter().rposition(|x| x.name == "ul" || x.name == "ol");
+                    if let Some(li) = stack.iter().rposition(|x| x.name == "li") {
+                        if list.is_none_or(|list| li > list) {
+                            while stack.len() > li {
+                                close(&mut stack, tag_start);
+                            }
+                        }
+                    }
+                }
+
+                let mut el = Element {
+                    name,
+                    attrs,
+                    children: Vec::new(),
+                    src: tag_start..end,
+                };
+                if RAW_TEXT_ELEMENTS.contains(&el.name.as_str()) {
+                    let close_tag = format!("</{}", el.name);
+                    let content_end = html[end..]
+                        .to_ascii_lowercase()
+                        .find(&close_tag)
+                        .map_or(html.len(), |x| end + x);
+                    el.children
+                        .push(Node::Text(html[end..content_end].to_string()));
+                    pos = html[content_end..]
+                        .find('>')
+                        .map_or(html.len(), |x| content_end + x + 1);
+                    el.src.end = pos;
+                    stack.last_mut().unwrap().children.push(Node::Element(el));
+                } else if self_closing || VOID_ELEMENTS.contains(&el.name.as_str()) {
+                    stack.last_mut().unwrap().children.push(Node::Element(el));
+                } else {
+                    stack.push(el);
+                }
+            }
+            Token::End(name) => {
+                if let Some(idx) = stack.iter().rposition(|x| x.name == name) {
+                    if idx > 0 {
+                        while stack.len() > idx + 1 {
+                            close(&mut stack, tag_start);
+                        }
+                        close(&mut stack, end);
+                    }
+                }
+            }
+        }
+        text_start = pos;
+    }
+
+    push_text(&mut stack, &html[text_start..]);
+    while stack.len() > 1 {
+        close(&mut stack, html.len());
+    }
+    stack.pop().unwrap().children
+}
+
+fn push_text(stack: &mut [Element], text: &str) {
+    if !text.is_empty() {
+        let parent = stack.last_mut().unwrap();
+        parent.children.push(Node::Text(text.to_string()));
+    }
+}
+
+fn close(stack: &mut Vec<Element>, end: usize) {
+    let mut el = stack.pop().unwrap();
+    el.src.end = end;
+    stack.last_mut().unwrap().children.push(Node::Element(el));
+}
+
+// schema的开始标签里的所有元素
+fn sigs(start: &str) -> Vec<Sig> {
+    let mut sigs = Vec::new();
+    let mut pos = 0;
+    while let Some(idx) = start[pos..].find('<') {
+        pos += idx;
+        match read_tag(start, pos) {
+            Some((Token::Start { name, attrs, .. }, end)) => {
+                let classes = attrs
+                    .iter()
+                    .filter(|(k, _v)| k == "class")
+                    .flat_map(|(_k, v)| v.split_whitespace().map(|x| x.to_string()))
+                    .collect();
+                let attrs = attrs
+                    .into_iter()
+                    .filter(|(k, _v)| k != "class" && k != "xmlns")
+                    .collect();
+                sigs.push(Sig {
+                    name,
+                    classes,
+                    attrs,
+                });
+                pos = end;
+            }
+            Some((_token, end)) => pos = end,
+            None => pos += 1,
+        }
+    }
+    sigs
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    // md_engine自己的输出转回来和格式化的结果一样
+    let input = "# Title {#top .x}\n\nSome text with $x^2$ and [link](http://a.b \"T\") and ![img](a.png)[^1].\nsecond line\n\n> quote line\n\n- [x] done\n- [ ] todo\n\n> [!NOTE]\n> careful\n\n:::tip Custom\npara\n\nmore\n:::\n\n```rust\nfn a() { 1 < 2 }\n```\n\n[^1]: the note\n";
+    let mut html = Vec::new();
+    let writer = crate::html_writer::HtmlWriterImpl::new(&mut html);
+    let mut parser = StatefulParser::new(input.as_bytes(), DefaultSchema, writer);
+    parser.parse_and_write().unwrap();
+    drop(parser);
+
+    let mut formatter =
+        StatefulParser::with_renderer(input.as_bytes(), MarkdownWriter::new(Vec::new()));
+    formatter.parse_and_write().unwrap();
+    let formatted = formatter.into_renderer().into_inner().unwrap();
+
+    assert_eq!(
+        String::from_utf8(formatted).unwrap(),
+        String::from_utf8(html_to_markdown(&html).unwrap()).unwrap()
+    );
+
+    // 其他工具导出的html，不认识的元素原样保留
+    let legacy = br#"<!DOCTYPE html><html><head><title>Wiki</title></head><body>
+<h2 id=intro>Intro</h2>
+<p>Hello <b>bold</b> &amp; <a href="/x?a=1&amp;b=2">a link</a>&nbsp;here
+<p>Second
+paragraph<br>next line</p>
+<blockquote><p>quoted</p></blockquote>
+<pre><code class="language-sh">ls &lt;dir&gt;
+</code></pre>
+<ul><li>one<li>two</ul>
+</body></html>"#;
+    assert_eq!(
+        "## Intro {#intro}\n\nHello <b>bold</b> &amp; [a link](/x?a=1&b=2) here\n\nSecond paragraph\nnext line\n\n> quoted\n\n```sh\nls <dir>\n```\n\n<ul><li>one<li>two</ul>\n",
+        String::from_utf8(html_to_markdown(legacy).unwrap()).unwrap()
+    );
+}
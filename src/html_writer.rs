@@ -4,9 +4,14 @@ use crate::tag::Tag;
 use std::io;
 use std::io::BufWriter;
 use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
 
-const TAG_LEN: usize = tag::LEN - 1 + 5 + 1;
-static mut USED_TAG: [bool; TAG_LEN] = [false; TAG_LEN];
+// 去掉None和Paragraph，Header多出5个level，TaskList多出1个，Admonition多出4个种类
+const TAG_LEN: usize = tag::LEN - 2 + 5 + 1 + 4;
+const ADMONITION_INDEX: std::ops::RangeInclusive<usize> = 10..=14;
+// 一个块很大（比如很长的代码块）时，ChunkWriter攒到这么多就在块中间交出去
+const MAX_CHUNK: usize = 64 * 1024;
 
 pub trait HtmlWriter {
     fn write(&mut self, data: &[u8]) -> io::Result<usize>;
@@ -27,43 +32,55 @@ pub trait HtmlWriter {
         self.write(b"\n")
     }
 
-    fn set_used_tag(tag: Tag) {
-        unsafe {
-            *USED_TAG.get_unchecked_mut(tag.tag_index()) = true;
-        }
+    // 块的边界，Mapper在一个块写完、后面没有等着合并的内容时调用，输出可以在这里切开
+    fn flush_point(&mut self) -> io::Result<()> {
+        Ok(())
     }
 
-    fn write_css<S: Schema>(&mut self, s: &S) -> io::Result<()> {
-        let res = self.write_css0(s);
-        Self::clear_used_tag();
-        res
+    // 文档结束，剩下的全部交出去
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 
-    fn write_css0<S: Schema>(&mut self, s: &S) -> io::Result<()> {
+    // 只输出用到的标签的css
+    fn write_css<S: Schema>(&mut self, s: &S, used_tags: &UsedTags) -> io::Result<()>
+    where
+        Self: Sized,
+    {
         self.write_ln()?;
         self.write(s.css_tag_start().as_bytes())?;
         self.write_ln()?;
 
-        unsafe {
-            for (idx, &used) in USED_TAG.iter().enumerate() {
-                if used {
-                    self.write(
-                        match idx {
-                            0 => s.h1_css(),
-                            1 => s.h2_css(),
-                            2 => s.h3_css(),
-                            3 => s.h4_css(),
-                            4 => s.h5_css(),
-                            5 => s.h6_css(),
-                            6 => s.block_quote_css(),
-                            7 => s.task_list_done_css(),
-                            8 => s.task_list_todo_css(),
-                            _ => unreachable!(),
-                        }
-                        .as_bytes(),
-                    )?;
-                    self.write_ln()?;
-                }
+        if used_tags.0[ADMONITION_INDEX].contains(&true) {
+            self.write(s.admonition_css().as_bytes())?;
+            self.write_ln()?;
+        }
+
+        for (idx, &used) in used_tags.0.iter().enumerate() {
+            if used {
+                self.write(
+                    match idx {
+                        0 => s.h1_css(),
+                        1 => s.h2_css(),
+                        2 => s.h3_css(),
+                        3 => s.h4_css(),
+                        4 => s.h5_css(),
+                        5 => s.h6_css(),
+                        6 => s.block_quote_css(),
+                        7 => s.task_list_done_css(),
+                        8 => s.task_list_todo_css(),
+                        9 => s.footnotes_css(),
+                        10 => s.admonition_note_css(),
+                        11 => s.admonition_tip_css(),
+                        12 => s.admonition_important_css(),
+                        13 => s.admonition_warning_css(),
+                        14 => s.admonition_caution_css(),
+                        15 => s.code_block_css(),
+                        _ => unreachable!(),
+                    }
+                    .as_bytes(),
+                )?;
+                self.write_ln()?;
             }
         }
 
@@ -71,11 +88,19 @@ pub trait HtmlWriter {
 
         Ok(())
     }
+}
 
-    fn clear_used_tag() {
-        unsafe {
-            USED_TAG = [false; TAG_LEN];
-        }
+// 一次输出里用到了哪些标签，每个Mapper一份，同时输出的文档互不影响
+#[derive(Clone, Default)]
+pub struct UsedTags([bool; TAG_LEN]);
+
+impl UsedTags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, tag: Tag) {
+        self.0[tag.tag_index()] = true;
     }
 }
 
@@ -91,8 +116,161 @@ impl<W: Write> HtmlWriterImpl<W> {
     }
 }
 
+// 脚注定义等需要延后输出的内容，先写进内存里
+impl HtmlWriter for Vec<u8> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.extend_from_slice(data);
+        Ok(data.len())
+    }
+}
+
 impl<W: Write> HtmlWriter for HtmlWriterImpl<W> {
     fn write(&mut self, data: &[u8]) -> io::Result<usize> {
         self.buf_writer.write(data)
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buf_writer.flush()
+    }
+}
+
+// ChunkWriter输出的去处：回调或者channel
+// SyncSender的channel满了会阻塞解析，内存就不会一直涨
+pub trait Sink {
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()>;
+}
+
+impl<F: FnMut(Vec<u8>) -> io::Result<()>> Sink for F {
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        self(chunk)
+    }
+}
+
+impl Sink for Sender<Vec<u8>> {
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        Sender::send(self, chunk).map_err(|_e| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl Sink for SyncSender<Vec<u8>> {
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        SyncSender::send(self, chunk).map_err(|_e| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+// 输出一段一段地交给Sink，只在块的边界切开，攒够chunk_size才交
+// 不在块的边界切开的只有超过MAX_CHUNK的块
+pub struct ChunkWriter<K: Sink> {
+    sink: K,
+    buf: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl<K: Sink> ChunkWriter<K> {
+    // 每个块都单独交出去
+    pub fn new(sink: K) -> Self {
+        Self::with_chunk_size(sink, 0)
+    }
+
+    pub fn with_chunk_size(sink: K, chunk_size: usize) -> Self {
+        Self {
+            sink,
+            buf: Vec::new(),
+            chunk_size,
+        }
+    }
+
+    pub fn into_inner(self) -> K {
+        self.sink
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(self.chunk_size));
+        self.sink.send(chunk)
+    }
+}
+
+impl<K: Sink> HtmlWriter for ChunkWriter<K> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= MAX_CHUNK.max(self.chunk_size) {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush_point(&mut self) -> io::Result<()> {
+        if self.buf.len() >= self.chunk_size {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[test]
+fn test() {
+    use crate::mapper::Mapper;
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::push_parser::TakeOutput;
+    use crate::schema::DefaultSchema;
+    use std::sync::mpsc;
+    use std::thread;
+
+    let test_md = include_bytes!("../test.md");
+    let mut parser = StatefulParser::from_slice(test_md, Mapper::new(DefaultSchema, Vec::new()));
+    parser.parse_and_write().unwrap();
+    let html = parser.into_renderer().take_output();
+
+    // 每个块一段，交给回调
+    let mut chunks = Vec::new();
+    let writer = ChunkWriter::new(|chunk| {
+        chunks.push(chunk);
+        Ok(())
+    });
+    StatefulParser::from_slice(test_md, Mapper::new(DefaultSchema, writer))
+        .parse_and_write()
+        .unwrap();
+    assert!(chunks.len() > 100);
+    assert_eq!(html, chunks.concat());
+
+    // 容量是1的channel，另一边取得慢的时候解析会等着
+    let (tx, rx) = mpsc::sync_channel(1);
+    let receiver = thread::spawn(move || rx.iter().collect::<Vec<Vec<u8>>>());
+    let writer = ChunkWriter::with_chunk_size(tx, 4096);
+    StatefulParser::from_slice(test_md, Mapper::new(DefaultSchema, writer))
+        .parse_and_write()
+        .unwrap();
+    let chunks = receiver.join().unwrap();
+    assert!(chunks.iter().all(|x| x.len() < MAX_CHUNK));
+    assert!(chunks[..chunks.len() - 1].iter().all(|x| x.len() >= 4096));
+    assert_eq!(html, chunks.concat());
+
+    // 同时输出的文档，css只和自己用到的标签有关
+    let render = |input: &[u8]| {
+        let mut parser = StatefulParser::from_slice(input, Mapper::new(DefaultSchema, Vec::new()));
+        parser.parse_and_write().unwrap();
+        parser.into_renderer().take_output()
+    };
+    let plain = render(b"text\n");
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            thread::spawn(move || {
+                let input: &[u8] = if i % 2 == 0 { test_md } else { b"text\n" };
+                (0..5).map(|_x| render(input)).collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for (i, t) in threads.into_iter().enumerate() {
+        for out in t.join().unwrap() {
+            assert_eq!(&out, if i % 2 == 0 { &html } else { &plain });
+        }
+    }
 }
@@ -0,0 +1,411 @@
+use crate::mapper::Mapper;
+use crate::parser::Context;
+use crate::parser::Warning;
+use crate::push_parser::TakeOutput;
+use crate::renderer::Pos;
+use crate::schema::Schema;
+use crate::slice_tokenizer::SliceTokenizer;
+use crate::tag::Tag;
+use crate::tokenizer::Token;
+use crate::tokenizer::TokenSource;
+use std::io;
+use std::ops::Range;
+
+// 编辑器里每次改动之后只重新解析受影响的块
+// 文档在空行和标题的地方切成块，这些地方前面什么都没有打开，从块的开头单独解析结果也一样
+// 输出是块的html，没有css；脚注的编号和整个文档有关，改到脚注的引用或者定义时从头解析
+pub struct IncrementalParser<S: Schema> {
+    context: Context<Mapper<S, Vec<u8>>>,
+    source: Vec<u8>,
+    blocks: Vec<Block>,
+    // 脚注列表，放在所有块的后面
+    footnotes: Vec<u8>,
+    footnote_warnings: Vec<Warning>,
+}
+
+// 对上的旧块的下标和它新的行号
+type Resumed = Option<(usize, u32)>;
+
+struct Block {
+    // 在source里的开头，结尾是下一个块的开头
+    start: usize,
+    line_num: u32,
+    html: Vec<u8>,
+    warnings: Vec<Warning>,
+    // 有没有脚注的引用或者定义
+    footnotes: bool,
+}
+
+// 修改之前的html里range这一段换成html
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub range: Range<usize>,
+    pub html: Vec<u8>,
+}
+
+impl<S: Schema> IncrementalParser<S> {
+    pub fn new(mapper: Mapper<S, Vec<u8>>, source: Vec<u8>) -> io::Result<Self> {
+        let mut parser = Self {
+            context: Context::new(mapper),
+            source,
+            blocks: Vec::new(),
+            footnotes: Vec::new(),
+            footnote_warnings: Vec::new(),
+        };
+        parser.parse_all()?;
+        Ok(parser)
+    }
+
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    pub fn html(&self) -> Vec<u8> {
+        let mut html: Vec<u8> = self.blocks.iter().flat_map(|b| &b.html).copied().collect();
+        html.extend_from_slice(&self.footnotes);
+        html
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &Warning> {
+        self.blocks
+            .iter()
+            .flat_map(|b| &b.warnings)
+            .chain(&self.footnote_warnings)
+    }
+
+    // source里range这一段换成replacement，返回html里变了的部分
+    pub fn edit(&mut self, range: Range<usize>, replacement: &[u8]) -> io::Result<Change> {
+        let old_len =
+            self.blocks.iter().map(|b| b.html.len()).sum::<usize>() + self.footnotes.len();
+        self.source
+            .splice(range.clone(), replacement.iter().copied());
+
+        let delta = replacement.len() as i64 - range.len() as i64;
+        // 包含开头的块和它前面的一个块，改动在块的开头时前一个块怎么结束也可能变
+        let first = self
+            .blocks
+            .partition_point(|b| b.start <= range.start)
+            .saturating_sub(2);
+        let (start, line_num) = (self.blocks[first].start, self.blocks[first].line_num);
+
+        // 新解析到的块的开头和一个没改过的旧块对上，后面的就不用再解析了
+        // 行号变了的话，sourcepos也跟着变，只能解析到结尾
+        let sourcepos = self.context.renderer_mut().sourcepos();
+        let old_starts: Vec<(usize, u32)> =
+            self.blocks.iter().map(|b| (b.start, b.line_num)).collect();
+        let resume = |at: usize, line_num: u32| {
+            let old = at as i64 - delta;
+            if old < range.end as i64 {
+                return None;
+            }
+            let idx = old_starts
+                .binary_search_by_key(&(old as usize), |x| x.0)
+                .ok()?;
+            (!sourcepos || old_starts[idx].1 == line_num).then_some(idx)
+        };
+        let (blocks, resumed) = self.parse(start, line_num, resume)?;
+
+        let (end, line_delta) = match resumed {
+            Some((idx, line_num)) => (idx, line_num as i64 - old_starts[idx].1 as i64),
+            None => (self.blocks.len(), 0),
+        };
+
+        // 重新解析的块里有脚注的引用或者定义，编号和脚注列表都可能变
+        if self.blocks[first..end]
+            .iter()
+            .chain(&blocks)
+            .any(|b| b.footnotes)
+        {
+            self.parse_all()?;
+            return Ok(Change {
+                range: 0..old_len,
+                html: self.html(),
+            });
+        }
+        let html_start = self.blocks[..first]
+            .iter()
+            .map(|b| b.html.len())
+            .sum::<usize>();
+        let html_end = html_start
+            + self.blocks[first..end]
+                .iter()
+                .map(|b| b.html.len())
+                .sum::<usize>();
+        let html = blocks.iter().flat_map(|b| &b.html).copied().collect();
+        self.blocks.splice(first..end, blocks);
+
+        let first_kept = self.blocks.len() - (old_starts.len() - end);
+        for block in &mut self.blocks[first_kept..] {
+            block.start = (block.start as i64 + delta) as usize;
+            block.line_num = (block.line_num as i64 + line_delta) as u32;
+            if delta != 0 || line_delta != 0 {
+                block.warnings = shift(std::mem::take(&mut block.warnings), 0, delta, line_delta);
+            }
+        }
+        // 脚注的警告在没有重新解析的块里，后面的跟着块移动
+        if delta != 0 || line_delta != 0 {
+            let kept = old_starts.get(end).map_or(u64::MAX, |x| x.0 as u64);
+            let warnings = std::mem::take(&mut self.footnote_warnings);
+            self.footnote_warnings = shift(warnings, kept, delta, line_delta);
+        }
+
+        Ok(Change {
+            range: html_start..html_end,
+            html,
+        })
+    }
+
+    fn parse_all(&mut self) -> io::Result<()> {
+        let labels = footnote_labels(&self.source)?;
+        self.context.renderer_mut().reset_footnotes(&labels);
+        let (blocks, _resumed) = self.parse(0, 1, |_at, _line_num| None)?;
+        self.blocks = blocks;
+
+        let mapper = self.context.renderer_mut();
+        mapper.write_footnotes()?;
+        self.footnotes = mapper.take_output();
+        self.footnote_warnings = mapper.footnote_warnings();
+        Ok(())
+    }
+
+    // 从start开始解析，直到resume返回旧块的下标或者到结尾
+    fn parse<F: Fn(usize, u32) -> Option<usize>>(
+        &mut self,
+        start: usize,
+        line_num: u32,
+        resume: F,
+    ) -> io::Result<(Vec<Block>, Resumed)> {
+        self.context.restart(line_num);
+        let mut blocks = Vec::new();
+        let mut block_start = (start, line_num);
+        let mut footnotes = false;
+        // 没有闭合的 $$ 只作为文本输出，但是后面加上 $$ 就会一直到那里都是公式
+        // 所以到下一个空行之前都不能从中间重新开始
+        let mut open_math = false;
+        let mut last_ln = false;
+
+        let mut tokenizer = SliceTokenizer::new(&self.source[start..]);
+        while let Some(res) = tokenizer.next_token() {
+            let (token, span) = res?;
+            let span = span.start + start as u64..span.end + start as u64;
+
+            let is_ln = matches!(token, Token::Ln);
+            if is_ln && last_ln {
+                open_math = false;
+            }
+            last_ln = is_ln;
+            open_math |= matches!(token, Token::PureText { data: b"$$", .. });
+
+            let restart_at = if let Token::Tag(Tag::Header(_level)) = token {
+                (span.start as usize > block_start.0 && self.context.at_line_start() && !open_math)
+                    .then_some(span.start as usize)
+            } else {
+                None
+            };
+            if let Some(at) = restart_at {
+                self.context.close_blocks()?;
+                let footnotes = std::mem::take(&mut footnotes);
+                blocks.push(take_block(&mut self.context, block_start, footnotes));
+                block_start = (at, self.context.line_num());
+                if let Some(idx) = resume(at, block_start.1) {
+                    return Ok((blocks, Some((idx, block_start.1))));
+                }
+            }
+
+            footnotes |= matches!(token, Token::FootnoteRef { .. } | Token::FootnoteDef { .. });
+            let end = span.end as usize;
+            self.context.solve_token(token, span)?;
+
+            // 后面还要有数据，不然不知道"\r"后面是不是"\n"
+            if is_ln && !open_math && self.context.is_clean() && end < self.source.len() {
+                let footnotes = std::mem::take(&mut footnotes);
+                blocks.push(take_block(&mut self.context, block_start, footnotes));
+                block_start = (end, self.context.line_num());
+                if let Some(idx) = resume(end, block_start.1) {
+                    return Ok((blocks, Some((idx, block_start.1))));
+                }
+            }
+        }
+
+        self.context.close_all()?;
+        blocks.push(take_block(&mut self.context, block_start, footnotes));
+        Ok((blocks, None))
+    }
+}
+
+// 写好的html和警告都归到这个块
+fn take_block<S: Schema>(
+    context: &mut Context<Mapper<S, Vec<u8>>>,
+    (start, line_num): (usize, u32),
+    footnotes: bool,
+) -> Block {
+    let mut warnings = context.take_warnings();
+    let mapper = context.renderer_mut();
+    warnings.extend(mapper.take_block_warnings());
+    Block {
+        start,
+        line_num,
+        html: mapper.take_output(),
+        warnings,
+        footnotes,
+    }
+}
+
+// 提前找出所有脚注的定义，引用就不用等到定义出现才能输出，输出也就不会跨块
+fn footnote_labels(source: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut labels = Vec::new();
+    let mut tokenizer = SliceTokenizer::new(source);
+    while let Some(res) = tokenizer.next_token() {
+        if let (Token::FootnoteDef { label, .. }, _span) = res? {
+            labels.push(label.to_vec());
+        }
+    }
+    Ok(labels)
+}
+
+// offset在from之后的警告移动位置
+fn shift(warnings: Vec<Warning>, from: u64, delta: i64, line_delta: i64) -> Vec<Warning> {
+    warnings
+        .into_iter()
+        .map(|w| {
+            let pos = w.pos();
+            if pos.offset < from {
+                return w;
+            }
+            let pos = Pos::new(
+                (pos.line_num as i64 + line_delta) as u32,
+                pos.column_num,
+                (pos.offset as i64 + delta) as u64,
+            );
+            Warning::new(w.msg().to_string(), pos)
+        })
+        .collect()
+}
+
+#[test]
+fn test() {
+    use crate::schema::DefaultSchema;
+
+    // 不切块，整个文档一次解析
+    let render = |source: &[u8], sourcepos: bool| {
+        let mapper = if sourcepos {
+            Mapper::with_sourcepos(DefaultSchema, Vec::new())
+        } else {
+            Mapper::new(DefaultSchema, Vec::new())
+        };
+        let mut context = Context::new(mapper);
+        let mut tokenizer = SliceTokenizer::new(source);
+        while let Some(res) = tokenizer.next_token() {
+            let (token, span) = res.unwrap();
+            context.solve_token(token, span).unwrap();
+        }
+        context.close_all().unwrap();
+        let mut warnings = context.take_warnings();
+        let mapper = context.renderer_mut();
+        mapper.write_footnotes().unwrap();
+        warnings.extend(mapper.take_block_warnings());
+        warnings.extend(mapper.footnote_warnings());
+        let mut warnings: Vec<String> = warnings.iter().map(|w| format!("{:?}", w)).collect();
+        warnings.sort();
+        (mapper.take_output(), warnings)
+    };
+
+    let pieces: [&[u8]; 16] = [
+        b"\n",
+        b"\n\n",
+        b"# ",
+        b"> ",
+        b":::tip\n",
+        b":::\n",
+        b"```\n",
+        b"x",
+        b"$$",
+        b"\r\n",
+        b"- [ ] ",
+        b"> [!NOTE]\n",
+        b"\\frac",
+        b"[^1]",
+        b"[^1]: ",
+        b" $$",
+    ];
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let mut random = |n: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % n as u64) as usize
+    };
+
+    let readme = include_bytes!("../README.md");
+    let input = b"# A\ntext\n\n> q\n# B\n:::tip\nx\n\n:::\n\n```\na\n\nb\n```\n\npara\n";
+    for (source, sourcepos) in [
+        (&readme[..], false),
+        (&input[..], false),
+        (&input[..], true),
+    ] {
+        let mapper = if sourcepos {
+            Mapper::with_sourcepos(DefaultSchema, Vec::new())
+        } else {
+            Mapper::new(DefaultSchema, Vec::new())
+        };
+        let mut parser = IncrementalParser::new(mapper, source.to_vec()).unwrap();
+        let mut html = parser.html();
+        assert_eq!(render(source, sourcepos).0, html);
+
+        for _ in 0..300 {
+            let len = parser.source().len();
+            let start = random(len + 1);
+            let end = (start + random(4)).min(len);
+            let replacement = if random(3) == 0 {
+                &b""[..]
+            } else {
+                pieces[random(pieces.len())]
+            };
+
+            let change = parser.edit(start..end, replacement).unwrap();
+            html.splice(change.range, change.html);
+            assert_eq!(parser.html(), html);
+
+            let (expected, expected_warnings) = render(parser.source(), sourcepos);
+            assert_eq!(expected, html);
+            let mut warnings: Vec<String> = parser.warnings().map(|w| format!("{:?}", w)).collect();
+            warnings.sort();
+            assert_eq!(expected_warnings, warnings);
+        }
+    }
+
+    // 只有改动的段落和它前面的一个重新输出
+    let source = "para\n\n".repeat(100);
+    let mapper = Mapper::new(DefaultSchema, Vec::new());
+    let mut parser = IncrementalParser::new(mapper, source.into()).unwrap();
+    let change = parser.edit(304..304, b" x").unwrap();
+    assert_eq!(
+        Change {
+            range: 49 * 14..51 * 14,
+            html: b"para<br>\n<br>\npara&nbsp;x<br>\n<br>\n".to_vec()
+        },
+        change
+    );
+
+    // 标题前面有没有闭合的 $$ 时，标题不能当做块的开头
+    let source = b"intro\n$$\n# Heading\ntext\n";
+    let mut doc =
+        IncrementalParser::new(Mapper::new(DefaultSchema, Vec::new()), source.to_vec()).unwrap();
+    let mut html = doc.html();
+    let change = doc.edit(23..23, b" $$").unwrap();
+    html.splice(change.range, change.html);
+    assert_eq!(render(doc.source(), false).0, html);
+    assert!(String::from_utf8(html).unwrap().contains("<math"));
+
+    // 改到脚注的时候整个重新输出
+    let change = parser.edit(0..0, b"[^1]: note\n\n[^1]").unwrap();
+    assert_eq!(0..100 * 14 + 7, change.range);
+    assert_eq!(render(parser.source(), false).0, change.html);
+
+    // 离脚注远的改动还是只输出改动的段落
+    let mut html = parser.html();
+    let change = parser.edit(320..320, b" x").unwrap();
+    assert!(change.range.len() < 50);
+    html.splice(change.range, change.html);
+    assert_eq!(render(parser.source(), false).0, html);
+}
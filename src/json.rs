@@ -0,0 +1,144 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+// 手写的json，输出mdast、pandoc这些格式用，不引入依赖
+// 对象保持插入的顺序
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn str<S: Into<String>>(s: S) -> Self {
+        Json::String(s.into())
+    }
+
+    // 字节不是合法的utf8时用U+FFFD代替
+    pub fn bytes(data: &[u8]) -> Self {
+        Json::String(String::from_utf8_lossy(data).into_owned())
+    }
+
+    pub fn object<K: Into<String>, I: IntoIterator<Item = (K, Json)>>(fields: I) -> Self {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _v)| k == key).map(|(_k, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Json> {
+        match self {
+            Json::Object(fields) => fields.iter_mut().find(|(k, _v)| k == key).map(|(_k, v)| v),
+            _ => None,
+        }
+    }
+
+    // 对象里有这个key就替换，没有就加在最后
+    pub fn set<K: Into<String>>(&mut self, key: K, value: Json) {
+        if let Json::Object(fields) = self {
+            let key = key.into();
+            match fields.iter_mut().find(|(k, _v)| *k == key) {
+                Some((_k, v)) => *v = value,
+                None => fields.push((key, value)),
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn write<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Json::Null => w.write_all(b"null"),
+            Json::Bool(b) => w.write_all(if *b { b"true" } else { b"false" }),
+            Json::Number(n) => write!(w, "{}", n),
+            Json::String(s) => write_str(w, s),
+            Json::Array(items) => {
+                w.write_all(b"[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        w.write_all(b",")?;
+                    }
+                    item.write(w)?;
+                }
+                w.write_all(b"]")
+            }
+            Json::Object(fields) => {
+                w.write_all(b"{")?;
+                for (idx, (key, value)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        w.write_all(b",")?;
+                    }
+                    write_str(w, key)?;
+                    w.write_all(b":")?;
+                    value.write(w)?;
+                }
+                w.write_all(b"}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut buf = Vec::new();
+        self.write(&mut buf).map_err(|_e| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&buf))
+    }
+}
+
+// 控制字符、引号和反斜杠要转义，其他的utf8原样输出
+fn write_str<W: Write + ?Sized>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_all(b"\"")?;
+    let mut last = 0;
+    for (idx, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            '\n' => "\\n".to_string(),
+            '\r' => "\\r".to_string(),
+            '\t' => "\\t".to_string(),
+            c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+            _ => continue,
+        };
+        w.write_all(&s.as_bytes()[last..idx])?;
+        w.write_all(escaped.as_bytes())?;
+        last = idx + c.len_utf8();
+    }
+    w.write_all(&s.as_bytes()[last..])?;
+    w.write_all(b"\"")
+}
+
+#[test]
+fn test() {
+    let mut json = Json::object([
+        ("type", Json::str("text")),
+        ("value", Json::str("a \"b\"\\\n\u{1}中")),
+        (
+            "list",
+            Json::Array(vec![Json::Null, Json::Bool(true), Json::Number(-3)]),
+        ),
+    ]);
+    assert_eq!(
+        r#"{"type":"text","value":"a \"b\"\\\n\u0001中","list":[null,true,-3]}"#,
+        json.to_string()
+    );
+
+    json.set("type", Json::str("inlineCode"));
+    json.set("extra", Json::Object(Vec::new()));
+    assert_eq!(Some("inlineCode"), json.get("type").and_then(Json::as_str));
+    assert_eq!(Some(&Json::Object(Vec::new())), json.get("extra"));
+}
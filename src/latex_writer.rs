@@ -0,0 +1,379 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+const PREAMBLE: &[u8] = br"\documentclass{article}
+\usepackage[utf8]{inputenc}
+\usepackage{amsmath}
+\usepackage{amssymb}
+\usepackage{graphicx}
+\usepackage{listings}
+\usepackage{hyperref}
+\begin{document}
+";
+
+// listings自带的语言，其他的语言不指定language，否则编译会报错
+const LISTINGS_LANGUAGES: [(&str, &str); 15] = [
+    ("c", "C"),
+    ("cpp", "C++"),
+    ("c++", "C++"),
+    ("java", "Java"),
+    ("python", "Python"),
+    ("py", "Python"),
+    ("bash", "bash"),
+    ("sh", "sh"),
+    ("html", "HTML"),
+    ("xml", "XML"),
+    ("sql", "SQL"),
+    ("ruby", "Ruby"),
+    ("perl", "Perl"),
+    ("php", "PHP"),
+    ("haskell", "Haskell"),
+];
+
+pub struct LatexWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    // 输出完整的文档，否则只输出正文
+    standalone: bool,
+    started: bool,
+    // 连续的任务列表放在同一个itemize里，遇到其他内容时才结束
+    in_list: bool,
+    in_item: bool,
+    header_label: Option<String>,
+    // 脚注的label，下标+1是编号
+    footnotes: Vec<Vec<u8>>,
+}
+
+impl<W: Write> LatexWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            standalone: true,
+            started: false,
+            in_list: false,
+            in_item: false,
+            header_label: None,
+            footnotes: Vec::new(),
+        }
+    }
+
+    // 不带 \documentclass 和 \begin{document}，方便 \input 到别的文档里
+    pub fn fragment(w: W) -> Self {
+        Self {
+            standalone: false,
+            ..Self::new(w)
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            if self.standalone {
+                self.buf_writer.write_all(PREAMBLE)?;
+            }
+        }
+        self.buf_writer.write_all(data)
+    }
+
+    // # $ % & _ { } ~ ^ \ 都要转义
+    fn write_escaped(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut last = 0;
+        for (idx, &b) in data.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'#' => br"\#",
+                b'$' => br"\$",
+                b'%' => br"\%",
+                b'&' => br"\&",
+                b'_' => br"\_",
+                b'{' => br"\{",
+                b'}' => br"\}",
+                b'~' => br"\textasciitilde{}",
+                b'^' => br"\textasciicircum{}",
+                b'\\' => br"\textbackslash{}",
+                _ => continue,
+            };
+            self.write(&data[last..idx])?;
+            self.write(escaped)?;
+            last = idx + 1;
+        }
+        self.write(&data[last..])
+    }
+
+    // \verb的分隔符不能出现在这一行里，都出现了就转义之后用 \texttt
+    fn write_verb_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.write(b"\\noindent")?;
+        match b"|!+=@:;/?*".iter().find(|d| !line.contains(d)) {
+            Some(&delimiter) => {
+                self.write(b"\\verb")?;
+                self.write(&[delimiter])?;
+                self.write(line)?;
+                self.write(&[delimiter])?;
+            }
+            None => {
+                self.write(b"\\texttt{")?;
+                self.write_escaped(line)?;
+                self.write(b"}")?;
+            }
+        }
+        self.write(b"\\par\n")
+    }
+
+    fn close_list(&mut self) -> io::Result<()> {
+        if self.in_list && !self.in_item {
+            self.in_list = false;
+            self.write(b"\\end{itemize}\n")?;
+        }
+        Ok(())
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|x| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(label.to_vec());
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+impl<W: Write> Renderer for LatexWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        if let Tag::TaskList(is_done) = tag {
+            if !self.in_list {
+                self.in_list = true;
+                self.write(b"\\begin{itemize}\n")?;
+            }
+            self.in_item = true;
+            return self.write(if is_done {
+                br"\item[$\boxtimes$] "
+            } else {
+                br"\item[$\square$] "
+            });
+        }
+        self.close_list()?;
+
+        match tag {
+            Tag::Header(level) => self.write(match level {
+                1 => br"\section{",
+                2 => br"\subsection{",
+                3 => br"\subsubsection{",
+                4 => br"\paragraph{",
+                _ => br"\subparagraph{",
+            }),
+            Tag::BlockQuote => self.write(b"\\begin{quote}\n"),
+            Tag::Admonition(kind) => {
+                self.write(b"\\begin{quote}\n\\textbf{")?;
+                if info.is_empty() {
+                    self.write(kind.default_title().as_bytes())?;
+                } else {
+                    self.write_escaped(info)?;
+                }
+                self.write(b"}\\par\n")
+            }
+            Tag::Footnote => {
+                let number = self.footnote_number(info);
+                self.write(format!("\\footnotetext[{}]{{", number).as_bytes())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn block_attrs(&mut self, attrs: &Attrs) -> io::Result<()> {
+        if let Some(id) = attrs.get("id") {
+            self.header_label = Some(id.to_string());
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Header(_level) => {
+                self.write(b"}")?;
+                if let Some(label) = self.header_label.take() {
+                    self.write(format!("\\label{{{}}}", label).as_bytes())?;
+                }
+                self.write(b"\n\n")
+            }
+            Tag::BlockQuote | Tag::Admonition(_) => self.write(b"\\end{quote}\n\n"),
+            Tag::TaskList(_) => {
+                self.in_item = false;
+                Ok(())
+            }
+            Tag::Footnote => self.write(b"}\n"),
+            Tag::Paragraph => self.write(b"\n"),
+            _ => Ok(()),
+        }
+    }
+
+    fn inline_start(&mut self, inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        self.close_list()?;
+        match inline {
+            Inline::Link { url, .. } => {
+                self.write(br"\href{")?;
+                for &b in url {
+                    if b == b'#' || b == b'%' {
+                        self.write(b"\\")?;
+                    }
+                    self.write(&[b])?;
+                }
+                self.write(b"}{")
+            }
+        }
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } => self.write(b"}"),
+        }
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.close_list()?;
+        self.write_escaped(data)
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.close_list()?;
+        self.write(b" ")
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.close_list()?;
+        self.write(b" ")
+    }
+
+    // latex里单个换行不分段
+    fn line_break(&mut self) -> io::Result<()> {
+        self.close_list()?;
+        self.write(b"\n")
+    }
+
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.close_list()?;
+
+        let lang = String::from_utf8_lossy(lang).to_ascii_lowercase();
+        let (begin, end) = match LISTINGS_LANGUAGES.iter().find(|(name, _)| *name == lang) {
+            Some((_name, language)) => (
+                format!("\\begin{{lstlisting}}[language={}]\n", language),
+                "\\end{lstlisting}\n\n",
+            ),
+            None if !lang.is_empty() => {
+                ("\\begin{lstlisting}\n".to_string(), "\\end{lstlisting}\n\n")
+            }
+            None => ("\\begin{verbatim}\n".to_string(), "\\end{verbatim}\n\n"),
+        };
+
+        // 内容里有结束标记的行会提前结束环境，这样的行放到环境外面用 \verb 输出
+        let marker = end.trim_end().as_bytes();
+        let mut open = false;
+        for line in content.split(|&b| b == b'\n') {
+            let inside = !line.windows(marker.len()).any(|x| x == marker);
+            if inside && !open {
+                self.write(begin.as_bytes())?;
+            } else if !inside && open {
+                self.write(marker)?;
+                self.write(b"\n")?;
+            }
+            open = inside;
+
+            if inside {
+                self.write(line)?;
+                self.write(b"\n")?;
+            } else {
+                self.write_verb_line(line)?;
+            }
+        }
+        if open {
+            self.write(end.as_bytes())
+        } else {
+            self.write(b"\n")
+        }
+    }
+
+    fn image(&mut self, src: &[u8], _alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        self.close_list()?;
+        self.write(br"\includegraphics{")?;
+        self.write(src)?;
+        self.write(b"}")
+    }
+
+    // tex原样输出
+    fn math(&mut self, tex: &[u8], display: bool, _pos: Pos) -> io::Result<()> {
+        self.close_list()?;
+        if display {
+            self.write(b"\\[")?;
+            self.write(tex)?;
+            self.write(b"\\]\n")
+        } else {
+            self.write(b"$")?;
+            self.write(tex)?;
+            self.write(b"$")
+        }
+    }
+
+    // 定义在引用之后才知道内容，所以用 \footnotemark 和 \footnotetext
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        self.close_list()?;
+        let number = self.footnote_number(label);
+        self.write(format!("\\footnotemark[{}]", number).as_bytes())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close_list()?;
+        if self.standalone {
+            self.write(b"\\end{document}\n")?;
+        }
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    let input = "# A_b {#intro}\n\n> 100% & $x^2$\n\n- [x] done\n- [ ] todo\n\n```python\nprint('{}')\n```\n\nsee [site](http://a.b/#c)[^1]\n\n[^1]: note\n";
+    let mut parser =
+        StatefulParser::with_renderer(input.as_bytes(), LatexWriter::fragment(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert!(out.contains(r"\section{A\_b}\label{intro}"));
+    assert!(out.contains(r"\begin{quote}"));
+    assert!(out.contains(r"100\% \& $x^2$"));
+    assert!(out.contains(
+        "\\begin{itemize}\n\\item[$\\boxtimes$] done\n\\item[$\\square$] todo\n\\end{itemize}"
+    ));
+    assert!(out.contains("\\begin{lstlisting}[language=Python]\nprint('{}')\n\\end{lstlisting}"));
+    assert!(out.contains(r"\href{http://a.b/\#c}{site}\footnotemark[1]"));
+    assert!(out.contains(r"\footnotetext[1]{note"));
+    assert!(!out.contains(r"\documentclass"));
+
+    // 代码里的 \end{verbatim} 不能结束环境
+    let input = "```\na\n\\end{verbatim}\nb\n```\n";
+    let mut parser =
+        StatefulParser::with_renderer(input.as_bytes(), LatexWriter::fragment(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+    assert!(out.contains(concat!(
+        "\\begin{verbatim}\na\n\\end{verbatim}\n",
+        "\\noindent\\verb|\\end{verbatim}|\\par\n",
+        "\\begin{verbatim}\nb\n\\end{verbatim}\n"
+    )));
+}
@@ -1,13 +1,31 @@
-#![feature(trace_macros)]
 use std::borrow::Cow;
 
+pub mod attr;
+pub mod chat_writer;
+pub mod directive;
 pub mod engine;
 pub mod error_handle;
+pub mod footnote;
+pub mod gemtext_writer;
+pub mod html_reader;
 pub mod html_writer;
+pub mod incremental;
+pub mod json;
+pub mod latex_writer;
+pub mod man_writer;
 pub mod mapper;
+pub mod markdown_writer;
+pub mod math;
+pub mod mdast_writer;
+pub mod pandoc_writer;
 pub mod parser;
+pub mod push_parser;
+pub mod renderer;
 pub mod schema;
+pub mod slice_tokenizer;
 pub mod tag;
+pub mod term_writer;
+pub mod text_writer;
 pub mod tokenizer;
 
 pub type CowStr = Cow<'static, str>;
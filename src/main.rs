@@ -1,12 +1,98 @@
+use chr::Encoding;
+use chr::Transcoder;
+use chr::Utf8Policy;
+use md_engine::chat_writer::ChatWriter;
 use md_engine::engine::MarkdownEngine;
+use md_engine::error_handle::ErrorHandler;
 use md_engine::error_handle::ErrorHandlerImpl;
+use md_engine::gemtext_writer::GemtextWriter;
+use md_engine::html_reader;
 use md_engine::html_writer::HtmlWriterImpl;
+use md_engine::latex_writer::LatexWriter;
+use md_engine::man_writer::ManWriter;
+use md_engine::mapper::Mapper;
+use md_engine::markdown_writer::MarkdownWriter;
+use md_engine::mdast_writer::MdastWriter;
+use md_engine::pandoc_writer::PandocWriter;
+use md_engine::parser::ParseError;
+use md_engine::parser::Parser;
 use md_engine::parser::StatefulParser;
+use md_engine::parser::Warning;
 use md_engine::schema::DefaultSchema;
+use md_engine::term_writer;
+use md_engine::term_writer::TermWriter;
+use md_engine::text_writer::TextWriter;
 use std::fs::File;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::process;
 use std::time::Instant;
 
+const USAGE: &str = "usage:
+    md_engine                         convert test.md to test.html
+    md_engine render [options] [FILE] render FILE (or stdin) to stdout
+    md_engine fmt [--check] [FILE...] rewrite FILEs as canonical markdown (stdin to stdout)
+    md_engine from-html [FILE]        convert html in FILE (or stdin) to markdown on stdout
+
+options:
+    --to <html|latex|man|text|term|gemtext|slack|telegram|mdast|pandoc-json>
+                                  output format, default html
+    --term                        same as --to term
+    --width <N>                   wrap width for text and term
+    --color / --no-color          force ANSI colors on or off for term
+    --encoding <utf-8|utf-16le|utf-16be|latin1|gbk>
+                                  input encoding when there is no BOM, default utf-8
+                                  (gbk needs the gbk feature)
+    --utf8=<replace|strict|pass>  invalid utf-8: replace with U+FFFD, stop with an error,
+                                  or keep the bytes (default); each one is reported as a warning
+    --sourcepos                   html: add data-sourcepos=\"line:col-line:col\" to block elements
+    --check                       fmt: only list the files that would change, exit 1 if any";
+
+// 命令行里输出到stdout，警告写到stderr，不要混在输出里
+struct StderrHandler {
+    failed: bool,
+}
+
+impl ErrorHandler for StderrHandler {
+    fn handle_error(&mut self, e: ParseError) {
+        eprintln!("{:?}", e);
+        self.failed = true;
+    }
+
+    fn handle_warning(&mut self, w: Warning) {
+        eprintln!("{:?}", w);
+    }
+}
+
+struct RenderArgs {
+    to: String,
+    width: Option<usize>,
+    color: Option<bool>,
+    encoding: Encoding,
+    utf8: Utf8Policy,
+    sourcepos: bool,
+    file: Option<String>,
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|x| x.as_str()) {
+        None => convert_test_md(),
+        Some("render") => match parse_render_args(&args[1..]) {
+            Ok(render_args) => process::exit(render(render_args)),
+            Err(msg) => usage_error(&msg),
+        },
+        Some("fmt") => process::exit(fmt(&args[1..])),
+        Some("from-html") => process::exit(from_html(&args[1..])),
+        Some("-h" | "--help" | "help") => println!("{}", USAGE),
+        Some(other) => usage_error(&format!("unknown command \"{}\"", other)),
+    }
+}
+
+fn convert_test_md() {
     let now = Instant::now();
 
     let schema = DefaultSchema;
@@ -20,3 +106,245 @@ fn main() {
 
     println!("ok. cost time: {:?}", now.elapsed());
 }
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("error: {}\n\n{}", msg, USAGE);
+    process::exit(2)
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs, String> {
+    let mut render_args = RenderArgs {
+        to: "html".to_string(),
+        width: None,
+        color: None,
+        encoding: Encoding::Utf8,
+        utf8: Utf8Policy::PassThrough,
+        sourcepos: false,
+        file: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--to" => render_args.to = iter.next().ok_or("--to needs a format")?.clone(),
+            "--term" => render_args.to = "term".to_string(),
+            "--width" => {
+                let width = iter.next().ok_or("--width needs a number")?;
+                render_args.width = Some(
+                    width
+                        .parse()
+                        .map_err(|_e| format!("invalid width \"{}\"", width))?,
+                );
+            }
+            "--encoding" => {
+                let label = iter.next().ok_or("--encoding needs a name")?;
+                render_args.encoding = Encoding::from_label(label)
+                    .ok_or_else(|| format!("unknown encoding \"{}\"", label))?;
+            }
+            "--utf8" => {
+                render_args.utf8 = utf8_policy(iter.next().ok_or("--utf8 needs a policy")?)?
+            }
+            x if x.starts_with("--utf8=") => render_args.utf8 = utf8_policy(&x["--utf8=".len()..])?,
+            "--sourcepos" => render_args.sourcepos = true,
+            "--color" => render_args.color = Some(true),
+            "--no-color" => render_args.color = Some(false),
+            x if x.starts_with("--") => return Err(format!("unknown option \"{}\"", x)),
+            x => {
+                if render_args.file.is_some() {
+                    return Err("only one input file is allowed".to_string());
+                }
+                render_args.file = Some(x.to_string());
+            }
+        }
+    }
+
+    Ok(render_args)
+}
+
+fn utf8_policy(name: &str) -> Result<Utf8Policy, String> {
+    match name {
+        "replace" => Ok(Utf8Policy::Replace),
+        "strict" => Ok(Utf8Policy::Error),
+        "pass" => Ok(Utf8Policy::PassThrough),
+        _ => Err(format!("unknown utf-8 policy \"{}\"", name)),
+    }
+}
+
+// 返回进程的退出码
+fn render(args: RenderArgs) -> i32 {
+    let input: Box<dyn Read> = match args.file.as_deref() {
+        None | Some("-") => Box::new(io::stdin()),
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("error: can not open \"{}\": {}", path, e);
+                return 1;
+            }
+        },
+    };
+    // 有BOM时按BOM来
+    let input = Transcoder::with_encoding(input, args.encoding);
+    let stdout = io::stdout();
+    let mut handler = StderrHandler { failed: false };
+    let mut engine = MarkdownEngine::with_utf8_policy(args.utf8);
+
+    match args.to.as_str() {
+        "html" if args.sourcepos => {
+            let mapper = Mapper::with_sourcepos(DefaultSchema, HtmlWriterImpl::new(stdout.lock()));
+            let parser = StatefulParser::with_renderer(input, mapper);
+            engine.start(parser, &mut handler);
+        }
+        "html" => {
+            let parser =
+                StatefulParser::new(input, DefaultSchema, HtmlWriterImpl::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "gemtext" => {
+            let parser = StatefulParser::with_renderer(input, GemtextWriter::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "latex" => {
+            let parser = StatefulParser::with_renderer(input, LatexWriter::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "man" => {
+            let parser = StatefulParser::with_renderer(input, ManWriter::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "mdast" => {
+            let parser = StatefulParser::with_renderer(input, MdastWriter::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "pandoc-json" => {
+            let parser = StatefulParser::with_renderer(input, PandocWriter::new(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "slack" => {
+            let parser = StatefulParser::with_renderer(input, ChatWriter::slack(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "telegram" => {
+            let parser = StatefulParser::with_renderer(input, ChatWriter::telegram(stdout.lock()));
+            engine.start(parser, &mut handler);
+        }
+        "text" => {
+            let width = args.width.unwrap_or(md_engine::text_writer::DEFAULT_WIDTH);
+            let writer = TextWriter::with_width(stdout.lock(), width);
+            engine.start(StatefulParser::with_renderer(input, writer), &mut handler);
+        }
+        "term" => {
+            let width = args.width.unwrap_or_else(term_writer::terminal_width);
+            // 不是终端或者设置了NO_COLOR时不输出颜色
+            let color = args
+                .color
+                .unwrap_or_else(|| stdout.is_terminal() && std::env::var_os("NO_COLOR").is_none());
+            let writer = TermWriter::new(stdout.lock(), width, color);
+            engine.start(StatefulParser::with_renderer(input, writer), &mut handler);
+        }
+        other => usage_error(&format!("unknown format \"{}\"", other)),
+    }
+
+    handler.failed as i32
+}
+
+// 返回进程的退出码
+fn fmt(args: &[String]) -> i32 {
+    let check = args.iter().any(|x| x == "--check");
+    let files: Vec<&String> = args.iter().filter(|x| *x != "--check").collect();
+    if let Some(x) = files.iter().find(|x| x.starts_with("--")) {
+        usage_error(&format!("unknown option \"{}\"", x));
+    }
+
+    if files.is_empty() || files == ["-"] {
+        let mut input = Vec::new();
+        if let Err(e) = io::stdin().read_to_end(&mut input) {
+            eprintln!("error: can not read stdin: {}", e);
+            return 1;
+        }
+        return match format_markdown(&input) {
+            Some(output) if check => (output != input) as i32,
+            Some(output) => match io::stdout().write_all(&output) {
+                Ok(()) => 0,
+                Err(_e) => 1,
+            },
+            None => 1,
+        };
+    }
+
+    let mut code = 0;
+    for path in files {
+        let input = match std::fs::read(path) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("error: can not open \"{}\": {}", path, e);
+                code = 1;
+                continue;
+            }
+        };
+        let output = match format_markdown(&input) {
+            Some(x) => x,
+            None => {
+                code = 1;
+                continue;
+            }
+        };
+        if output == input {
+            continue;
+        }
+
+        if check {
+            eprintln!("would reformat {}", path);
+            code = 1;
+        } else if let Err(e) = std::fs::write(path, &output) {
+            eprintln!("error: can not write \"{}\": {}", path, e);
+            code = 1;
+        }
+    }
+    code
+}
+
+// 返回进程的退出码
+fn from_html(args: &[String]) -> i32 {
+    if let Some(x) = args.iter().find(|x| x.starts_with("--")) {
+        usage_error(&format!("unknown option \"{}\"", x));
+    }
+    let input = match args {
+        [] => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input).map(|_n| input)
+        }
+        [path] if path == "-" => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input).map(|_n| input)
+        }
+        [path] => std::fs::read(path),
+        _ => usage_error("only one input file is allowed"),
+    };
+    let input = match input {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: can not read input: {}", e);
+            return 1;
+        }
+    };
+
+    match html_reader::html_to_markdown(&input).and_then(|x| io::stdout().write_all(&x)) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn format_markdown(input: &[u8]) -> Option<Vec<u8>> {
+    let mut parser = StatefulParser::from_slice(input, MarkdownWriter::new(Vec::new()));
+    if let Err(e) = parser.parse_and_write() {
+        eprintln!("{:?}", e);
+        return None;
+    }
+    for w in parser.take_warnings() {
+        eprintln!("{:?}", w);
+    }
+    parser.into_renderer().into_inner().ok()
+}
@@ -0,0 +1,303 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+// 没有写 "name(section)" 时的手册章节，1是命令行工具
+const DEFAULT_SECTION: &str = "1";
+
+// man(7) 格式，可以用 man -l 查看
+// # name(1) 是 .TH，## 是 .SH，### 及以下是 .SS，段落是 .PP
+pub struct ManWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    // 输出在一行的开头，这时 . 和 ' 会被当成命令
+    line_start: bool,
+    // 标题要写在宏的同一行，先收集起来
+    header: Option<(u8, Vec<u8>)>,
+    // 只有第一个一级标题是 .TH，后面的当做 .SH
+    titled: bool,
+    // 空格等到后面有文字时才输出，行尾不留空格
+    pending_space: bool,
+    // 脚注的label，下标+1是编号
+    footnotes: Vec<Vec<u8>>,
+}
+
+impl<W: Write> ManWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            line_start: true,
+            header: None,
+            titled: false,
+            pending_space: false,
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(last) = data.last() {
+            self.line_start = *last == b'\n';
+        }
+        self.buf_writer.write_all(data)
+    }
+
+    // 宏要在单独的一行
+    fn request(&mut self, line: &str) -> io::Result<()> {
+        self.pending_space = false;
+        if !self.line_start {
+            self.write(b"\n")?;
+        }
+        self.write(line.as_bytes())?;
+        self.write(b"\n")
+    }
+
+    // \ 写成 \e，- 写成 \-，行首的 . 和 ' 前面加 \&
+    fn write_escaped(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some((_level, header)) = &mut self.header {
+            header.extend_from_slice(data);
+            return Ok(());
+        }
+
+        let mut data = data;
+        if self.pending_space && !data.is_empty() {
+            self.pending_space = false;
+            self.write(b" ")?;
+        }
+        if self.line_start {
+            // 行首的空格会让roff断行
+            let start = data.iter().position(|&b| b != b' ' && b != b'\t');
+            data = &data[start.unwrap_or(data.len())..];
+            if matches!(data.first(), Some(b'.' | b'\'')) {
+                self.write(br"\&")?;
+            }
+        }
+        self.write(escape(data, false).as_bytes())
+    }
+
+    fn write_header(&mut self, level: u8, text: &[u8]) -> io::Result<()> {
+        let text = String::from_utf8_lossy(text);
+        let text = text.trim();
+        let line = match level {
+            1 if !self.titled => {
+                self.titled = true;
+                // "name(section)" 或者 "name"
+                let (name, section) = match text.strip_suffix(')').and_then(|x| x.rsplit_once('('))
+                {
+                    Some((name, section)) if !section.is_empty() => (name.trim(), section),
+                    _ => (text, DEFAULT_SECTION),
+                };
+                format!(
+                    ".TH \"{}\" \"{}\"",
+                    escape(name.to_uppercase().as_bytes(), true),
+                    escape(section.as_bytes(), true)
+                )
+            }
+            1 | 2 => format!(".SH {}", escape(text.as_bytes(), true)),
+            _ => format!(".SS {}", escape(text.as_bytes(), true)),
+        };
+        self.request(&line)
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|x| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push(label.to_vec());
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+impl<W: Write> Renderer for ManWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Header(level) => {
+                self.header = Some((level, Vec::new()));
+                Ok(())
+            }
+            Tag::Paragraph => self.request(".PP"),
+            Tag::BlockQuote => {
+                self.request(".RS")?;
+                self.request(".PP")
+            }
+            Tag::TaskList(is_done) => self.request(if is_done {
+                ".IP [x] 4"
+            } else {
+                ".IP \"[ ]\" 4"
+            }),
+            Tag::Footnote => {
+                let number = self.footnote_number(info);
+                self.request(&format!(".IP [{}] 4", number))
+            }
+            Tag::Admonition(kind) => {
+                self.request(".RS")?;
+                self.request(".PP")?;
+                self.write(br"\fB")?;
+                if info.is_empty() {
+                    self.write(kind.default_title().as_bytes())?;
+                } else {
+                    self.write_escaped(info)?;
+                }
+                self.write(br"\fR")?;
+                self.request(".br")
+            }
+            Tag::CodeBlock | Tag::None => Ok(()),
+        }
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Header(_level) => match self.header.take() {
+                Some((level, text)) => self.write_header(level, &text),
+                None => Ok(()),
+            },
+            Tag::BlockQuote | Tag::Admonition(_) => self.request(".RE"),
+            _ => Ok(()),
+        }
+    }
+
+    // man -l 会把 .UR/.UE 显示成 "text <url>"
+    fn inline_start(&mut self, inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, .. } if self.header.is_none() => {
+                let url = String::from_utf8_lossy(url).replace('\\', r"\e");
+                self.request(&format!(".UR {}", url))
+            }
+            Inline::Link { .. } => Ok(()),
+        }
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } if self.header.is_none() => self.request(".UE"),
+            Inline::Link { .. } => Ok(()),
+        }
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_escaped(data)
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        match &mut self.header {
+            Some((_level, header)) => header.push(b' '),
+            None => self.pending_space = !self.line_start,
+        }
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.space()
+    }
+
+    // roff会把相邻的行接起来，空行会输出空行，所以不能连续换行
+    fn line_break(&mut self) -> io::Result<()> {
+        match &mut self.header {
+            Some((_level, header)) => header.push(b' '),
+            None if !self.line_start => {
+                self.pending_space = false;
+                self.write(b"\n")?
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    fn code_block(
+        &mut self,
+        _lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.request(".PP")?;
+        self.request(".RS 4")?;
+        self.request(".nf")?;
+        for line in content.split(|&b| b == b'\n') {
+            if matches!(line.first(), Some(b'.' | b'\'')) {
+                self.write(br"\&")?;
+            }
+            self.write(
+                String::from_utf8_lossy(line)
+                    .replace('\\', r"\e")
+                    .as_bytes(),
+            )?;
+            self.write(b"\n")?;
+        }
+        self.request(".fi")?;
+        self.request(".RE")
+    }
+
+    // 终端里显示不了图片，输出alt
+    fn image(&mut self, src: &[u8], alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        self.write_escaped(b"[")?;
+        self.write_escaped(if alt.is_empty() { src } else { alt })?;
+        self.write_escaped(b"]")
+    }
+
+    // tex原样输出，公式块单独缩进
+    fn math(&mut self, tex: &[u8], display: bool, _pos: Pos) -> io::Result<()> {
+        if display && self.header.is_none() {
+            self.request(".RS 4")?;
+            self.write_escaped(tex)?;
+            self.request(".RE")
+        } else {
+            self.write_escaped(tex)
+        }
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        let number = self.footnote_number(label);
+        self.write_escaped(format!("[{}]", number).as_bytes())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.line_start {
+            self.write(b"\n")?;
+        }
+        self.buf_writer.flush()
+    }
+}
+
+// 宏的参数里 " 也要转义
+fn escape(data: &[u8], in_request: bool) -> String {
+    let mut s = String::new();
+    for c in String::from_utf8_lossy(data).chars() {
+        match c {
+            '\\' => s.push_str(r"\e"),
+            '-' => s.push_str(r"\-"),
+            '"' if in_request => s.push_str(r"\(dq"),
+            _ => s.push(c),
+        }
+    }
+    s
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    let input = "# md-engine(1)\n\n# NAME\n\nmd-engine \\- render markdown\n\n## SYNOPSIS\n\n.hidden line\n'quoted\n\n### Options\n\n> see [site](http://a.b)[^1]\n\n- [x] done\n- [ ] todo\n\n```sh\n.start\nC:\\dir\n```\n\n[^1]: note\n";
+    let mut parser = StatefulParser::with_renderer(input.as_bytes(), ManWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert!(out.starts_with(
+        ".TH \"MD\\-ENGINE\" \"1\"\n.SH NAME\n.PP\nmd\\-engine \\- render markdown\n.SH SYNOPSIS\n"
+    ));
+    assert!(out.contains(".PP\n\\&.hidden line\n\\&'quoted\n.SS Options\n"));
+    assert!(out.contains(".RS\n.PP\nsee\n.UR http://a.b\nsite\n.UE\n[1]\n.RE\n"));
+    assert!(out.contains(".IP [x] 4\ndone\n.IP \"[ ]\" 4\ntodo\n"));
+    assert!(out.contains(".RS 4\n.nf\n\\&.start\nC:\\edir\n.fi\n.RE\n"));
+    assert!(out.ends_with(".IP [1] 4\nnote\n"));
+}
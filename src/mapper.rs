@@ -1,11 +1,94 @@
+use crate::attr::Attrs;
+use crate::directive::Args;
+use crate::directive::Directive;
+use crate::directive::Directives;
+use crate::footnote::Footnotes;
 use crate::html_writer::HtmlWriter;
+use crate::html_writer::UsedTags;
+use crate::math;
+use crate::parser::Warning;
+use crate::push_parser::TakeOutput;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
 use crate::schema::Schema;
+use crate::tag::AdmonitionKind;
 use crate::tag::Tag;
 use std::io;
 
+// html的Renderer，标签都来自Schema
 pub struct Mapper<S, W> {
     schema: S,
     writer: W,
+    footnotes: Footnotes,
+    directives: Directives,
+    warnings: Vec<Warning>,
+    // 结尾只输出用到的标签的css
+    used_tags: UsedTags,
+    // 标题结尾才知道有没有属性列表，标题的内容先写进这里
+    header: Option<Vec<u8>>,
+    header_attrs: Attrs,
+    // 给每个块的标签加上 data-sourcepos="起始行:列-结尾行:列"
+    sourcepos: bool,
+    header_pos: Pos,
+    last_pos: Pos,
+    // 开始标签要等结尾的位置，先缓存没有结束的块
+    blocks: Vec<OpenBlock>,
+    // 引用的脚注还不知道有没有定义时，从这个引用开始的调用都先记下来
+    pending: Vec<Event>,
+    replaying: bool,
+}
+
+struct OpenBlock {
+    tag: Tag,
+    info: Vec<u8>,
+    attrs: Attrs,
+    pos: Pos,
+    content: Vec<u8>,
+}
+
+// 记下来的Renderer调用，知道脚注有没有定义之后按顺序重新调用一遍
+enum Event {
+    BlockStart(Tag, Vec<u8>, Attrs, Pos),
+    BlockAttrs(Attrs),
+    BlockLastPos(Pos),
+    BlockEnd(Tag, Pos),
+    LinkStart(Vec<u8>, Vec<u8>, Attrs),
+    LinkEnd(Vec<u8>, Vec<u8>),
+    Text(Vec<u8>),
+    Space,
+    Tab,
+    LineBreak,
+    CodeBlock(Vec<u8>, Vec<u8>, Attrs, Pos),
+    Image(Vec<u8>, Vec<u8>, Vec<u8>, Attrs),
+    Math(Vec<u8>, bool, Pos),
+    FootnoteRef(Vec<u8>, Pos),
+    Directive(Vec<u8>, Vec<u8>, Vec<u8>, Pos),
+    Shortcode(Vec<u8>, Vec<u8>, Pos),
+}
+
+// 有等着的脚注引用时，先记下来，不输出
+macro_rules! defer {
+    ($self:ident, $event:expr) => {
+        if !$self.pending.is_empty() {
+            $self.pending.push($event);
+            return Ok(());
+        }
+    };
+}
+
+// 在标题里时，输出先写进标题的缓存，等到标题结束再输出
+// 在脚注定义里时，输出先写进脚注的缓存，等到结尾再输出
+// 要加sourcepos时，块的内容先写进块的缓存
+// 标题和脚注里不会再开始别的块，所以按这个顺序找
+macro_rules! out {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match ($self.header.as_mut(), $self.footnotes.capture(), $self.blocks.last_mut()) {
+            (Some(buf), _, _) | (None, Some(buf), _) => HtmlWriter::$method(buf, $($arg),*),
+            (None, None, Some(block)) => HtmlWriter::$method(&mut block.content, $($arg),*),
+            (None, None, None) => $self.writer.$method($($arg),*),
+        }
+    };
 }
 
 impl<S: Schema, W: HtmlWriter> Mapper<S, W> {
@@ -13,45 +96,711 @@ impl<S: Schema, W: HtmlWriter> Mapper<S, W> {
         Self {
             schema: s,
             writer: w,
+            footnotes: Footnotes::new(),
+            directives: Directives::new(),
+            warnings: Vec::new(),
+            used_tags: UsedTags::new(),
+            header: None,
+            header_attrs: Attrs::new(),
+            sourcepos: false,
+            header_pos: Pos::default(),
+            last_pos: Pos::default(),
+            blocks: Vec::new(),
+            pending: Vec::new(),
+            replaying: false,
+        }
+    }
+
+    // 块的标签带上在源文件里的位置，编辑器可以用来同步滚动
+    pub fn with_sourcepos(s: S, w: W) -> Self {
+        Self {
+            sourcepos: true,
+            ..Self::new(s, w)
+        }
+    }
+
+    pub(crate) fn sourcepos(&self) -> bool {
+        self.sourcepos
+    }
+
+    // 脚注的编号和整个文档有关，IncrementalParser每次从头解析前清掉
+    // 定义都提前扫描出来了，引用不用等
+    pub(crate) fn reset_footnotes(&mut self, labels: &[Vec<u8>]) {
+        self.footnotes = Footnotes::new();
+        for label in labels {
+            self.footnotes.declare(label);
         }
+        self.footnotes.set_complete();
     }
 
-    pub fn write_header_end(&mut self, level: u8) -> io::Result<usize> {
+    // 公式、指令的警告，和脚注的分开取，脚注的要到结尾才知道
+    pub(crate) fn take_block_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub(crate) fn footnote_warnings(&self) -> Vec<Warning> {
+        self.footnotes.warnings()
+    }
+
+    pub fn register_directive<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.directives.register_block(name, d);
+    }
+
+    pub fn register_shortcode<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.directives.register_shortcode(name, d);
+    }
+
+    fn write_header_end(&mut self, level: u8) -> io::Result<usize> {
+        if let Some(content) = self.header.take() {
+            let attrs = std::mem::take(&mut self.header_attrs);
+            let start = self
+                .schema
+                .merge_attrs(Tag::header_start(&self.schema, level), &attrs);
+            out!(self.write(start.as_bytes()))?;
+            out!(self.write(&content))?;
+        }
+
+        out!(self.write(Tag::header_end(&self.schema, level).as_bytes()))?;
+        out!(self.write_ln())
+    }
+
+    fn write_tag_start(&mut self, tag: Tag, attrs: &Attrs) -> io::Result<usize> {
+        if let Tag::Header(_level) = tag {
+            self.header = Some(Vec::new());
+            self.header_attrs = attrs.clone();
+        } else {
+            let start = self.schema.merge_attrs(tag.start_tag(&self.schema), attrs);
+            out!(self.write(start.as_bytes()))?;
+        }
+        out!(self.write_ln())
+    }
+
+    fn write_tag_end(&mut self, tag: Tag) -> io::Result<()> {
+        match tag {
+            Tag::None | Tag::Paragraph => return Ok(()),
+            Tag::Footnote => {
+                self.footnotes.end_def();
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        out!(self.write(tag.end_tag(&self.schema).as_bytes()))?;
+        self.used_tags.set(tag);
+
+        Ok(())
+    }
+
+    // 和cmark一样，结尾是最后一个字符的位置
+    fn sourcepos_attrs(&self, attrs: &Attrs, start: Pos) -> Attrs {
+        let mut all = attrs.clone();
+        all.set(
+            "data-sourcepos",
+            &format!(
+                "{}:{}-{}:{}",
+                start.line_num, start.column_num, self.last_pos.line_num, self.last_pos.column_num
+            ),
+        );
+        all
+    }
+
+    // 块结束了，这时才写开始标签，然后是缓存的内容
+    fn write_open_block(&mut self) -> io::Result<()> {
+        if let Some(block) = self.blocks.pop() {
+            let attrs = self.sourcepos_attrs(&block.attrs, block.pos);
+            match block.tag {
+                Tag::Admonition(kind) => self.write_admonition_start(kind, &block.info, &attrs)?,
+                tag => self.write_tag_start(tag, &attrs)?,
+            };
+            out!(self.write(&block.content))?;
+        }
+        Ok(())
+    }
+
+    // 没有等着合并或者延后的内容时，告诉writer输出可以在这里切开
+    fn flush_point(&mut self) -> io::Result<()> {
+        if self.header.is_none()
+            && self.footnotes.capture().is_none()
+            && self.blocks.is_empty()
+            && self.pending.is_empty()
+        {
+            self.writer.flush_point()?;
+        }
+        Ok(())
+    }
+
+    // 第一个等着的引用知道有没有定义了，从它开始按顺序重新调用
+    // 遇到后面还不知道的引用会再记下来，调用里又读到定义的话，在这里接着重新调用
+    fn replay(&mut self) -> io::Result<()> {
+        if self.replaying {
+            return Ok(());
+        }
+        self.replaying = true;
+        while let Some(Event::FootnoteRef(label, _pos)) = self.pending.first() {
+            if !self.footnotes.is_resolved(label) {
+                break;
+            }
+            for event in std::mem::take(&mut self.pending) {
+                self.dispatch(event)?;
+            }
+        }
+        self.replaying = false;
+        Ok(())
+    }
+
+    fn dispatch(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::BlockStart(tag, info, attrs, pos) => self.block_start(tag, &info, &attrs, pos),
+            Event::BlockAttrs(attrs) => self.block_attrs(&attrs),
+            Event::BlockLastPos(pos) => self.block_last_pos(pos),
+            Event::BlockEnd(tag, pos) => self.block_end(tag, pos),
+            Event::LinkStart(url, title, attrs) => self.inline_start(
+                Inline::Link {
+                    url: &url,
+                    title: &title,
+                },
+                &attrs,
+            ),
+            Event::LinkEnd(url, title) => self.inline_end(Inline::Link {
+                url: &url,
+                title: &title,
+            }),
+            Event::Text(data) => self.text(&data),
+            Event::Space => self.space(),
+            Event::Tab => self.tab(),
+            Event::LineBreak => self.line_break(),
+            Event::CodeBlock(lang, content, attrs, pos) => {
+                self.code_block(&lang, &content, &attrs, pos)
+            }
+            Event::Image(src, alt, title, attrs) => self.image(&src, &alt, &title, &attrs),
+            Event::Math(tex, display, pos) => self.math(&tex, display, pos),
+            Event::FootnoteRef(label, pos) => self.footnote_ref(&label, pos),
+            Event::Directive(name, args, content, pos) => {
+                self.directive(&name, &args, &content, pos)
+            }
+            Event::Shortcode(name, args, pos) => self.shortcode(&name, &args, pos),
+        }
+    }
+
+    // 标题为空时使用默认的标题
+    fn write_admonition_start(
+        &mut self,
+        kind: AdmonitionKind,
+        title: &[u8],
+        attrs: &Attrs,
+    ) -> io::Result<usize> {
+        let title = if title.is_empty() {
+            kind.default_title().as_bytes()
+        } else {
+            title
+        };
+
+        let start = self
+            .schema
+            .merge_attrs(Tag::Admonition(kind).start_tag(&self.schema), attrs);
+        out!(self.write(start.as_bytes()))?;
+        out!(self.write(self.schema.admonition_title_start().as_bytes()))?;
+        out!(self.write(title))?;
+        out!(self.write(self.schema.admonition_title_end().as_bytes()))?;
+        out!(self.write_ln())
+    }
+
+    // 在文档结尾输出所有被引用的脚注，每个脚注后面跟着指回引用处的回链
+    // 这时还在等的引用都是没有定义的
+    pub(crate) fn write_footnotes(&mut self) -> io::Result<()> {
+        self.footnotes.set_complete();
+        self.replay()?;
+
+        let mut entries = self.footnotes.entries().peekable();
+        if entries.peek().is_none() {
+            return Ok(());
+        }
+
+        self.writer.write_ln()?;
         self.writer
-            .write(Tag::header_end(&self.schema, level).as_bytes())?;
-        self.writer.write_ln()
+            .write(self.schema.footnotes_start().as_bytes())?;
+        self.writer.write_ln()?;
+
+        for entry in entries {
+            self.writer
+                .write(format!(r#"<li id="fn-{}">"#, entry.number).as_bytes())?;
+            // 定义的最后一行会带着换行，这里去掉
+            let content = entry
+                .content
+                .strip_suffix(b"<br>\n")
+                .unwrap_or(entry.content);
+            self.writer.write(content)?;
+
+            for nth in 1..=entry.ref_count {
+                let backref = if nth == 1 {
+                    format!(
+                        r##" <a href="#fnref-{}" class="footnote-backref">↩</a>"##,
+                        entry.number
+                    )
+                } else {
+                    format!(
+                        r##" <a href="#fnref-{}-{}" class="footnote-backref">↩</a>"##,
+                        entry.number, nth
+                    )
+                };
+                self.writer.write(backref.as_bytes())?;
+            }
+
+            self.writer.write(b"</li>")?;
+            self.writer.write_ln()?;
+        }
+
+        self.writer.write(self.schema.footnotes_end().as_bytes())?;
+        self.used_tags.set(Tag::Footnote);
+
+        Ok(())
     }
+}
+
+impl<S: Schema, W: HtmlWriter> Renderer for Mapper<S, W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], attrs: &Attrs, pos: Pos) -> io::Result<()> {
+        if let Tag::Footnote = tag {
+            self.footnotes.declare(info);
+            if !self.pending.is_empty() {
+                self.pending
+                    .push(Event::BlockStart(tag, info.to_vec(), attrs.clone(), pos));
+                return self.replay();
+            }
+        }
+        defer!(
+            self,
+            Event::BlockStart(tag, info.to_vec(), attrs.clone(), pos)
+        );
 
-    pub fn write_br(&mut self) -> io::Result<usize> {
-        self.writer.write_br()?;
-        self.writer.write_ln()
+        match tag {
+            Tag::None | Tag::Paragraph => {}
+            Tag::Footnote => {
+                self.footnotes.start_def(info, pos);
+            }
+            Tag::Header(_level) if self.sourcepos => {
+                self.header_pos = pos;
+                self.write_tag_start(tag, attrs)?;
+            }
+            _ if self.sourcepos => self.blocks.push(OpenBlock {
+                tag,
+                info: info.to_vec(),
+                attrs: attrs.clone(),
+                pos,
+                content: Vec::new(),
+            }),
+            Tag::Admonition(kind) => {
+                self.write_admonition_start(kind, info, attrs)?;
+            }
+            _ => {
+                self.write_tag_start(tag, attrs)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn block_attrs(&mut self, attrs: &Attrs) -> io::Result<()> {
+        defer!(self, Event::BlockAttrs(attrs.clone()));
+        self.header_attrs.extend(attrs);
+        Ok(())
+    }
+
+    fn block_last_pos(&mut self, pos: Pos) -> io::Result<()> {
+        defer!(self, Event::BlockLastPos(pos));
+        self.last_pos = pos;
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, pos: Pos) -> io::Result<()> {
+        defer!(self, Event::BlockEnd(tag, pos));
+        match tag {
+            Tag::None | Tag::Paragraph | Tag::Footnote => {}
+            Tag::Header(_level) if self.sourcepos => {
+                self.header_attrs = self.sourcepos_attrs(&self.header_attrs, self.header_pos);
+            }
+            _ if self.sourcepos => self.write_open_block()?,
+            _ => {}
+        }
+
+        match tag {
+            Tag::Header(level) => {
+                self.write_header_end(level)?;
+            }
+            Tag::Admonition(_kind) => {
+                self.write_tag_end(tag)?;
+                out!(self.write_ln())?;
+            }
+            _ => self.write_tag_end(tag)?,
+        }
+        self.flush_point()
+    }
+
+    fn inline_start(&mut self, inline: Inline, attrs: &Attrs) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, title } => {
+                defer!(
+                    self,
+                    Event::LinkStart(url.to_vec(), title.to_vec(), attrs.clone())
+                );
+                let mut all = Attrs::new();
+                all.set("href", &String::from_utf8_lossy(url));
+                if !title.is_empty() {
+                    all.set("title", &String::from_utf8_lossy(title));
+                }
+                all.extend(attrs);
+
+                let start = self.schema.merge_attrs(self.schema.link_start(), &all);
+                out!(self.write(start.as_bytes()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, title } => {
+                defer!(self, Event::LinkEnd(url.to_vec(), title.to_vec()));
+                out!(self.write(self.schema.link_end().as_bytes()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        defer!(self, Event::Text(data.to_vec()));
+        out!(self.write(data))?;
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        defer!(self, Event::Space);
+        out!(self.write_html_space())?;
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        defer!(self, Event::Tab);
+        out!(self.write_html_tab())?;
+        Ok(())
+    }
+
+    fn line_break(&mut self) -> io::Result<()> {
+        defer!(self, Event::LineBreak);
+        out!(self.write_br())?;
+        out!(self.write_ln())?;
+        Ok(())
+    }
+
+    // 代码块的内容原样输出，只转义html的特殊字符
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        attrs: &Attrs,
+        pos: Pos,
+    ) -> io::Result<()> {
+        defer!(
+            self,
+            Event::CodeBlock(lang.to_vec(), content.to_vec(), attrs.clone(), pos)
+        );
+        let mut all = Attrs::new();
+        if !lang.is_empty() {
+            all.set(
+                "class",
+                &format!("language-{}", String::from_utf8_lossy(lang)),
+            );
+        }
+        all.extend(attrs);
+        if self.sourcepos {
+            all = self.sourcepos_attrs(&all, pos);
+        }
+
+        let start = self
+            .schema
+            .merge_attrs(Tag::CodeBlock.start_tag(&self.schema), &all);
+        out!(self.write(start.as_bytes()))?;
+
+        let mut last = 0;
+        for (idx, &b) in content.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'&' => b"&amp;",
+                b'<' => b"&lt;",
+                b'>' => b"&gt;",
+                _ => continue,
+            };
+            out!(self.write(&content[last..idx]))?;
+            out!(self.write(escaped))?;
+            last = idx + 1;
+        }
+        out!(self.write(&content[last..]))?;
+
+        self.write_tag_end(Tag::CodeBlock)?;
+        out!(self.write_ln())?;
+        self.flush_point()
     }
 
-    pub fn write_html_space(&mut self) -> io::Result<usize> {
-        self.writer.write_html_space()
+    fn image(&mut self, src: &[u8], alt: &[u8], title: &[u8], attrs: &Attrs) -> io::Result<()> {
+        defer!(
+            self,
+            Event::Image(src.to_vec(), alt.to_vec(), title.to_vec(), attrs.clone())
+        );
+        let mut all = Attrs::new();
+        all.set("src", &String::from_utf8_lossy(src));
+        all.set("alt", &String::from_utf8_lossy(alt));
+        if !title.is_empty() {
+            all.set("title", &String::from_utf8_lossy(title));
+        }
+        all.extend(attrs);
+
+        let tag = self.schema.merge_attrs(self.schema.image(), &all);
+        out!(self.write(tag.as_bytes()))?;
+        Ok(())
     }
 
-    pub fn write_html_tab(&mut self) -> io::Result<usize> {
-        self.writer.write_html_tab()
+    fn math(&mut self, tex: &[u8], display: bool, pos: Pos) -> io::Result<()> {
+        defer!(self, Event::Math(tex.to_vec(), display, pos));
+        let mut mathml = String::new();
+        let errors = math::tex_to_mathml(&String::from_utf8_lossy(tex), display, &mut mathml);
+        for msg in errors {
+            self.warnings.push(Warning::new(msg, pos));
+        }
+
+        let (start, end) = if display {
+            (
+                self.schema.math_display_start(),
+                self.schema.math_display_end(),
+            )
+        } else {
+            (
+                self.schema.math_inline_start(),
+                self.schema.math_inline_end(),
+            )
+        };
+        // TeX源码放在annotation里，浏览器不显示，html转回markdown时用
+        let tex = String::from_utf8_lossy(tex)
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        out!(self.write(start.as_bytes()))?;
+        out!(self.write(b"<semantics><mrow>"))?;
+        out!(self.write(mathml.as_bytes()))?;
+        out!(self.write(b"</mrow><annotation encoding=\"application/x-tex\">"))?;
+        out!(self.write(tex.as_bytes()))?;
+        out!(self.write(b"</annotation></semantics>"))?;
+        out!(self.write(end.as_bytes()))?;
+        Ok(())
     }
 
-    pub fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-        self.writer.write(data)
+    // 还不知道有没有定义的引用要等，后面的调用也跟着等；没有定义的原样输出
+    fn footnote_ref(&mut self, label: &[u8], pos: Pos) -> io::Result<()> {
+        defer!(self, Event::FootnoteRef(label.to_vec(), pos));
+        if !self.footnotes.is_resolved(label) {
+            self.pending.push(Event::FootnoteRef(label.to_vec(), pos));
+            return Ok(());
+        }
+
+        let Some((number, nth)) = self.footnotes.reference(label, pos) else {
+            out!(self.write(b"[^"))?;
+            out!(self.write(label))?;
+            out!(self.write(b"]"))?;
+            return Ok(());
+        };
+        let anchor = if nth == 1 {
+            format!(r##"<a href="#fn-{0}" id="fnref-{0}">{0}</a>"##, number)
+        } else {
+            format!(
+                r##"<a href="#fn-{0}" id="fnref-{0}-{1}">{0}</a>"##,
+                number, nth
+            )
+        };
+
+        out!(self.write(self.schema.footnote_ref_start().as_bytes()))?;
+        out!(self.write(anchor.as_bytes()))?;
+        out!(self.write(self.schema.footnote_ref_end().as_bytes()))?;
+        Ok(())
     }
 
-    pub fn write_tag_start(&mut self, tag: Tag) -> io::Result<usize> {
-        self.writer.write(tag.start_tag(&self.schema).as_bytes())?;
-        self.writer.write_ln()
+    // 没有注册的指令原样输出
+    fn directive(&mut self, name: &[u8], args: &[u8], content: &[u8], pos: Pos) -> io::Result<()> {
+        defer!(
+            self,
+            Event::Directive(name.to_vec(), args.to_vec(), content.to_vec(), pos)
+        );
+        if let Some(d) = self.directives.block(name) {
+            let args = Args::parse(args);
+            match (
+                self.header.as_mut(),
+                self.footnotes.capture(),
+                self.blocks.last_mut(),
+            ) {
+                (Some(buf), _, _) | (None, Some(buf), _) => d.render(&args, content, buf),
+                (None, None, Some(block)) => d.render(&args, content, &mut block.content),
+                (None, None, None) => d.render(&args, content, &mut self.writer),
+            }?;
+            return self.flush_point();
+        }
+
+        self.warnings.push(Warning::new(
+            format!("unknown directive \"{}\"", String::from_utf8_lossy(name)),
+            pos,
+        ));
+
+        out!(self.write(b":::"))?;
+        out!(self.write(name))?;
+        if !args.is_empty() {
+            out!(self.write(b" "))?;
+            out!(self.write(args))?;
+        }
+        self.line_break()?;
+        if !content.is_empty() {
+            for line in content.split(|&b| b == b'\n') {
+                out!(self.write(line))?;
+                self.line_break()?;
+            }
+        }
+        out!(self.write(b":::"))?;
+        self.line_break()?;
+        self.flush_point()
     }
 
-    pub fn write_tag_end(&mut self, tag: Tag) -> io::Result<()> {
-        self.writer.write(tag.end_tag(&self.schema).as_bytes())?;
-        W::set_used_tag(tag);
+    fn shortcode(&mut self, name: &[u8], args: &[u8], pos: Pos) -> io::Result<()> {
+        defer!(self, Event::Shortcode(name.to_vec(), args.to_vec(), pos));
+        if let Some(d) = self.directives.shortcode(name) {
+            let args = Args::parse(args);
+            return match (
+                self.header.as_mut(),
+                self.footnotes.capture(),
+                self.blocks.last_mut(),
+            ) {
+                (Some(buf), _, _) | (None, Some(buf), _) => d.render(&args, &[], buf),
+                (None, None, Some(block)) => d.render(&args, &[], &mut block.content),
+                (None, None, None) => d.render(&args, &[], &mut self.writer),
+            };
+        }
+
+        self.warnings.push(Warning::new(
+            format!("unknown shortcode \"{}\"", String::from_utf8_lossy(name)),
+            pos,
+        ));
 
+        out!(self.write(b"{{&lt; "))?;
+        out!(self.write(name))?;
+        if !args.is_empty() {
+            out!(self.write(b" "))?;
+            out!(self.write(args))?;
+        }
+        out!(self.write(b" &gt;}}"))?;
         Ok(())
     }
 
-    pub fn write_css(&mut self) -> io::Result<()> {
-        self.writer.write_css(&self.schema)
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_footnotes()?;
+        self.writer.write_css(&self.schema, &self.used_tags)?;
+        self.writer.flush()
+    }
+
+    // 公式里不支持的命令，还有脚注的问题，都在这里取出
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.extend(self.footnotes.warnings());
+        warnings
+    }
+}
+
+// 写在内存里的html，给PushParser用
+impl<S: Schema> TakeOutput for Mapper<S, Vec<u8>> {
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.writer)
+    }
+}
+
+#[test]
+fn test() {
+    use crate::html_writer::HtmlWriterImpl;
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::schema::DefaultSchema;
+
+    let render = |input: &[u8], sourcepos: bool| {
+        let mut html = Vec::new();
+        let writer = HtmlWriterImpl::new(&mut html);
+        let mapper = if sourcepos {
+            Mapper::with_sourcepos(DefaultSchema, writer)
+        } else {
+            Mapper::new(DefaultSchema, writer)
+        };
+        StatefulParser::with_renderer(input, mapper)
+            .parse_and_write()
+            .unwrap();
+        String::from_utf8(html).unwrap()
+    };
+
+    let input = "# 标题 {#x}\n> q\n> r\n\n- [ ] todo\n\n:::tip Hi\ntext [^1]\n> inner\n\n```rs\nfn a() {}\n```\n:::\n\n> [!NOTE]\n> b\n\n[^1]: note\n";
+    let html = render(input.as_bytes(), true);
+    let sourcepos: Vec<&str> = html
+        .split(r#"data-sourcepos=""#)
+        .skip(1)
+        .map(|x| &x[..x.find('"').unwrap()])
+        .collect();
+    assert_eq!(
+        sourcepos,
+        [
+            "1:1-1:9",
+            "2:1-3:3",
+            "5:1-5:10",
+            "7:1-14:3",
+            "9:1-9:7",
+            "11:1-13:3",
+            "16:1-17:3"
+        ]
+    );
+
+    // 提示块从标记的那一行开始，没有内容的时候也在这一行结束
+    let html = render(
+        b"para\n\n> [!TIP]\n\n:::warning Careful\n:::\n\n> [!NOTE]\n> a\n",
+        true,
+    );
+    let sourcepos: Vec<&str> = html
+        .split(r#"data-sourcepos=""#)
+        .skip(1)
+        .map(|x| &x[..x.find('"').unwrap()])
+        .collect();
+    assert_eq!(sourcepos, ["3:1-3:8", "5:1-6:3", "8:1-9:3"]);
+
+    // 去掉sourcepos之后和没有打开时一样
+    for input in [input.as_bytes(), include_bytes!("../README.md")] {
+        let html = render(input, true);
+        let mut stripped = String::new();
+        let mut rest = &html[..];
+        while let Some(start) = rest.find(r#" data-sourcepos=""#) {
+            stripped.push_str(&rest[..start]);
+            rest = &rest[start + 17..];
+            rest = &rest[rest.find('"').unwrap() + 1..];
+        }
+        stripped.push_str(rest);
+        assert_eq!(render(input, false), stripped);
     }
+
+    // 只有有定义的脚注才编号，没有定义的引用原样输出，定义可以在引用的后面
+    let html = render(
+        b"Text[^a] and[^missing] and[^b].\n\n[^b]: bee\n[^a]: ay\n",
+        false,
+    );
+    assert!(html.starts_with(concat!(
+        r##"Text<sup class="footnote-ref"><a href="#fn-1" id="fnref-1">1</a></sup>&nbsp;"##,
+        r##"and[^missing]&nbsp;"##,
+        r##"and<sup class="footnote-ref"><a href="#fn-2" id="fnref-2">2</a></sup>.<br>"##
+    )));
+    assert!(html.contains(concat!(
+        "<li id=\"fn-1\">ay <a href=\"#fnref-1\" class=\"footnote-backref\">↩</a></li>\n",
+        "<li id=\"fn-2\">bee <a href=\"#fnref-2\" class=\"footnote-backref\">↩</a></li>\n"
+    )));
+
+    // 标题结尾和属性列表前面的空白去掉，没匹配上的 {text} 后面的空格照常输出
+    let html = render(b"# T {#id}\n\n# Brace in {text} heading \n", false);
+    assert!(html.starts_with("<h1 id=\"id\">\nT</h1>\n"), "{}", html);
+    assert!(
+        html.contains("<h1>\nBrace&nbsp;in&nbsp;{text}&nbsp;heading</h1>"),
+        "{}",
+        html
+    );
 }
@@ -0,0 +1,744 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::AdmonitionKind;
+use crate::tag::Tag;
+use crate::tokenizer::fence_line;
+use crate::tokenizer::trim;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+// 重新输出成统一格式的markdown，用于 md_engine fmt
+// 标题是 "# " 加内容，任务列表是 "- [ ] "，块之间空一行，连续的任务列表之间不空行
+// 文本只在会被解析成别的语法时才转义，再格式化一次结果不变
+// 内容都按byte处理，不是utf8的byte也原样输出
+pub struct MarkdownWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    // 当前的叶子块，内容在lines里，块结束时一起输出
+    block: Option<Block>,
+    lines: Vec<Vec<u8>>,
+    line: Vec<u8>,
+    // 还没有转义的文本和空白，遇到其他的行内元素或者换行时才知道后面是什么
+    pending: Vec<u8>,
+    // 紧挨着pending前面的行内元素，决定pending开头要不要转义
+    last_inline: LastInline,
+    // 在链接里时，链接的文本和属性
+    link: Option<(Vec<u8>, Attrs)>,
+    header_attrs: Option<Attrs>,
+    // 还不知道是 > [!NOTE] 还是 :::note 的容器，看后面的第一个元素决定
+    admonition: Option<(AdmonitionKind, Vec<u8>, Attrs)>,
+    last_block: LastBlock,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Block {
+    Paragraph,
+    Header(u8),
+    Quote,
+    Alert(AdmonitionKind),
+    Task(bool),
+    Footnote(Vec<u8>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LastBlock {
+    Nothing,
+    Task,
+    // ::: 容器的第一行
+    Opener,
+    Other,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LastInline {
+    None,
+    Math,
+    // 没有属性的链接，后面紧跟着的 { 会被当做它的属性
+    Link,
+    FootnoteRef,
+}
+
+impl<W: Write> MarkdownWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            block: None,
+            lines: Vec::new(),
+            line: Vec::new(),
+            pending: Vec::new(),
+            last_inline: LastInline::None,
+            link: None,
+            header_attrs: None,
+            admonition: None,
+            last_block: LastBlock::Nothing,
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn write(&mut self, s: &[u8]) -> io::Result<()> {
+        self.buf_writer.write_all(s)
+    }
+
+    // 块之间空一行
+    fn separate(&mut self, is_task: bool) -> io::Result<()> {
+        match self.last_block {
+            LastBlock::Nothing | LastBlock::Opener => Ok(()),
+            LastBlock::Task if is_task => Ok(()),
+            _ => self.write(b"\n"),
+        }
+    }
+
+    fn start_block(&mut self, block: Block) -> io::Result<()> {
+        self.end_block()?;
+        self.block = Some(block);
+        Ok(())
+    }
+
+    // inline表示后面跟着的是行内内容，只有 > [!NOTE] 会直接跟着行内内容
+    fn resolve_admonition(&mut self, inline: bool) -> io::Result<()> {
+        let (kind, title, attrs) = match self.admonition.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        if inline && title.is_empty() && attrs.is_empty() {
+            return self.start_block(Block::Alert(kind));
+        }
+
+        self.end_block()?;
+        self.separate(false)?;
+        let mut opener = format!(":::{}", kind.name()).into_bytes();
+        if !title.is_empty() {
+            opener.push(b' ');
+            opener.extend_from_slice(&title);
+        }
+        if !attrs.is_empty() {
+            opener.push(b' ');
+            opener.extend_from_slice(attrs.to_source().as_bytes());
+        }
+        opener.push(b'\n');
+        self.write(&opener)?;
+        self.last_block = LastBlock::Opener;
+        Ok(())
+    }
+
+    // 行内内容之前，确保在一个叶子块里
+    fn before_inline(&mut self) -> io::Result<()> {
+        self.resolve_admonition(true)?;
+        if self.block.is_none() {
+            self.start_block(Block::Paragraph)?;
+        }
+        Ok(())
+    }
+
+    // 转义pending并放到当前行，next是后面紧跟着的元素的第一个字符，None表示行尾
+    fn flush_text(&mut self, next: Option<u8>) {
+        let mut text = std::mem::take(&mut self.pending);
+        if next.is_none() {
+            let len = text
+                .iter()
+                .rposition(|&b| !is_blank(b))
+                .map_or(0, |i| i + 1);
+            text.truncate(len);
+        }
+        let text = if self.line.is_empty() {
+            let start = text
+                .iter()
+                .position(|&b| !is_blank(b))
+                .unwrap_or(text.len());
+            &text[start..]
+        } else {
+            &text[..]
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        let line_start = self.line.is_empty() && self.block == Some(Block::Paragraph);
+        let in_header = matches!(self.block, Some(Block::Header(_)));
+        let escaped = escape(text, line_start, in_header, self.last_inline, next);
+        self.line.extend_from_slice(&escaped);
+        self.last_inline = LastInline::None;
+    }
+
+    fn push_inline(&mut self, first: u8, s: &[u8], kind: LastInline) {
+        self.flush_text(Some(first));
+        self.line.extend_from_slice(s);
+        self.last_inline = kind;
+    }
+
+    fn end_line(&mut self) {
+        self.flush_text(None);
+        let line = std::mem::take(&mut self.line);
+        // 只有空白的行去掉之后是空行，会结束这个块
+        if !line.is_empty() {
+            self.lines.push(line);
+        }
+        self.last_inline = LastInline::None;
+    }
+
+    fn end_block(&mut self) -> io::Result<()> {
+        if self.block.is_none() {
+            return Ok(());
+        }
+        self.end_line();
+        let block = self.block.take().unwrap();
+        let lines = std::mem::take(&mut self.lines);
+        // 只有空白的段落什么也不输出，也不算一个块
+        if block == Block::Paragraph && lines.is_empty() {
+            return Ok(());
+        }
+
+        // 块结束时才知道有没有内容，这时再空行
+        self.separate(matches!(block, Block::Task(_)))?;
+        let mut out = Vec::new();
+        match &block {
+            Block::Paragraph => {
+                for line in &lines {
+                    out.extend_from_slice(line);
+                    out.push(b'\n');
+                }
+            }
+            Block::Header(level) => {
+                out.extend_from_slice(&b"#".repeat(*level as usize));
+                out.push(b' ');
+                out.extend_from_slice(&lines.join(&b' '));
+                if let Some(attrs) = self.header_attrs.take() {
+                    out.push(b' ');
+                    out.extend_from_slice(attrs.to_source().as_bytes());
+                }
+                out.push(b'\n');
+            }
+            Block::Quote | Block::Alert(_) => {
+                if let Block::Alert(kind) = block {
+                    let alert = format!("> [!{}]\n", kind.name().to_ascii_uppercase());
+                    out.extend_from_slice(alert.as_bytes());
+                } else if lines.is_empty() {
+                    out.extend_from_slice(b"> \n");
+                }
+                for line in &lines {
+                    out.extend_from_slice(b"> ");
+                    out.extend_from_slice(line);
+                    out.push(b'\n');
+                }
+            }
+            Block::Task(_) | Block::Footnote(_) => {
+                let indent: &[u8] = match &block {
+                    Block::Task(true) => {
+                        out.extend_from_slice(b"- [x] ");
+                        b"  "
+                    }
+                    Block::Task(false) => {
+                        out.extend_from_slice(b"- [ ] ");
+                        b"  "
+                    }
+                    Block::Footnote(label) => {
+                        out.extend_from_slice(b"[^");
+                        out.extend_from_slice(label);
+                        out.extend_from_slice(b"]: ");
+                        b"    "
+                    }
+                    _ => unreachable!(),
+                };
+                for (idx, line) in lines.iter().enumerate() {
+                    if idx > 0 {
+                        out.extend_from_slice(indent);
+                    }
+                    out.extend_from_slice(line);
+                    out.push(b'\n');
+                }
+                if lines.is_empty() {
+                    out.push(b'\n');
+                }
+            }
+        }
+        self.write(&out)?;
+
+        self.last_block = if matches!(block, Block::Task(_)) {
+            LastBlock::Task
+        } else {
+            LastBlock::Other
+        };
+        Ok(())
+    }
+
+    // 代码块、指令这些原样输出的块
+    fn raw_block(&mut self, s: &[u8]) -> io::Result<()> {
+        self.resolve_admonition(false)?;
+        self.end_block()?;
+        self.separate(false)?;
+        self.write(s)?;
+        self.last_block = LastBlock::Other;
+        Ok(())
+    }
+}
+
+impl<W: Write> Renderer for MarkdownWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        self.resolve_admonition(false)?;
+
+        match tag {
+            Tag::Paragraph => self.start_block(Block::Paragraph),
+            Tag::Header(level) => self.start_block(Block::Header(level)),
+            Tag::BlockQuote => self.start_block(Block::Quote),
+            Tag::TaskList(is_done) => self.start_block(Block::Task(is_done)),
+            Tag::Footnote => self.start_block(Block::Footnote(info.to_vec())),
+            Tag::Admonition(kind) => {
+                self.end_block()?;
+                self.admonition = Some((kind, info.to_vec(), attrs.clone()));
+                Ok(())
+            }
+            Tag::CodeBlock | Tag::None => Ok(()),
+        }
+    }
+
+    fn block_attrs(&mut self, attrs: &Attrs) -> io::Result<()> {
+        self.header_attrs = Some(attrs.clone());
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Admonition(_) => {
+                // 空的容器
+                self.resolve_admonition(false)?;
+                if matches!(self.block, Some(Block::Alert(_))) {
+                    return self.end_block();
+                }
+                self.end_block()?;
+                self.write(b":::\n")?;
+                self.last_block = LastBlock::Other;
+                Ok(())
+            }
+            _ => self.end_block(),
+        }
+    }
+
+    fn inline_start(&mut self, inline: Inline, attrs: &Attrs) -> io::Result<()> {
+        self.before_inline()?;
+        match inline {
+            Inline::Link { .. } => {
+                self.flush_text(Some(b'['));
+                self.link = Some((Vec::new(), attrs.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, title } => {
+                let (text, attrs) = self.link.take().unwrap_or_default();
+                let mut link = b"[".to_vec();
+                link.extend_from_slice(&escape_link_text(&text));
+                link.push(b']');
+                link.extend_from_slice(&link_dest(url, title, &attrs));
+                self.push_inline(b'[', &link, link_kind(&attrs));
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.link {
+            Some((text, _attrs)) => text.extend_from_slice(data),
+            None => {
+                self.before_inline()?;
+                self.pending.extend_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+
+    // 容器开头的空白不决定是哪种容器
+    fn space(&mut self) -> io::Result<()> {
+        if self.admonition.is_some() {
+            return Ok(());
+        }
+        self.before_inline()?;
+        self.pending.push(b' ');
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        if self.admonition.is_some() {
+            return Ok(());
+        }
+        self.before_inline()?;
+        self.pending.push(b'\t');
+        Ok(())
+    }
+
+    // 块外面的空行不保留，块之间统一空一行
+    fn line_break(&mut self) -> io::Result<()> {
+        self.resolve_admonition(false)?;
+        if self.block.is_some() {
+            self.end_line();
+        }
+        Ok(())
+    }
+
+    // 内容里有 ``` 的话用更长的围栏
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        let longest = content
+            .split(|&b| b == b'\n')
+            .map(trim)
+            .filter(|x| !x.is_empty() && x.iter().all(|&b| b == b'`'))
+            .map(|x| x.len())
+            .max()
+            .unwrap_or(0);
+        let fence = b"`".repeat(longest.max(2) + 1);
+
+        let mut s = fence.clone();
+        // 语言以 ` 开头的话要和开头的 ``` 分开
+        if lang.starts_with(b"`") {
+            s.push(b' ');
+        }
+        s.extend_from_slice(lang);
+        if !attrs.is_empty() {
+            s.push(b' ');
+            s.extend_from_slice(attrs.to_source().as_bytes());
+        }
+        s.push(b'\n');
+        if !content.is_empty() {
+            s.extend_from_slice(content);
+            s.push(b'\n');
+        }
+        s.extend_from_slice(&fence);
+        s.push(b'\n');
+        self.raw_block(&s)
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], title: &[u8], attrs: &Attrs) -> io::Result<()> {
+        self.before_inline()?;
+        let mut image = b"![".to_vec();
+        image.extend_from_slice(&escape_link_text(alt));
+        image.push(b']');
+        image.extend_from_slice(&link_dest(src, title, attrs));
+        self.push_inline(b'!', &image, link_kind(attrs));
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], display: bool, _pos: Pos) -> io::Result<()> {
+        self.before_inline()?;
+        let dollar: &[u8] = if display { b"$$" } else { b"$" };
+        let math = [dollar, tex, dollar].concat();
+        self.push_inline(b'$', &math, LastInline::Math);
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        self.before_inline()?;
+        let footnote_ref = [b"[^", label, b"]"].concat();
+        self.push_inline(b'[', &footnote_ref, LastInline::FootnoteRef);
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &[u8], args: &[u8], content: &[u8], _pos: Pos) -> io::Result<()> {
+        let mut s = [b"::: ", name].concat();
+        if !args.is_empty() {
+            s.push(b' ');
+            s.extend_from_slice(args);
+        }
+        s.push(b'\n');
+        // 内容里有没结束的嵌套块的话，指令一定是到文件结尾都没结束，也不能加 :::
+        let mut depth = 1;
+        if !content.is_empty() {
+            s.extend_from_slice(content);
+            s.push(b'\n');
+            for line in content.split(|&b| b == b'\n') {
+                match fence_line(line) {
+                    None if trim(line).starts_with(b":::") => depth -= 1,
+                    Some((name, _rest)) if !name.is_empty() => depth += 1,
+                    _ => {}
+                }
+            }
+        }
+        if depth <= 1 {
+            s.extend_from_slice(b":::\n");
+        }
+        self.raw_block(&s)
+    }
+
+    fn shortcode(&mut self, name: &[u8], args: &[u8], _pos: Pos) -> io::Result<()> {
+        self.before_inline()?;
+        // 紧跟在没有属性的链接后面的话，先写一个空的属性列表
+        let mut s = if self.last_inline == LastInline::Link && self.pending.is_empty() {
+            b"{}".to_vec()
+        } else {
+            Vec::new()
+        };
+        s.extend_from_slice(b"{{< ");
+        s.extend_from_slice(name);
+        if !args.is_empty() {
+            s.push(b' ');
+            s.extend_from_slice(args);
+        }
+        s.extend_from_slice(b" >}}");
+        self.push_inline(b'{', &s, LastInline::None);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.resolve_admonition(true)?;
+        self.end_block()?;
+        self.buf_writer.flush()
+    }
+}
+
+// (url "title"){attrs}
+fn link_kind(attrs: &Attrs) -> LastInline {
+    if attrs.is_empty() {
+        LastInline::Link
+    } else {
+        LastInline::None
+    }
+}
+
+fn link_dest(url: &[u8], title: &[u8], attrs: &Attrs) -> Vec<u8> {
+    let mut s = [b"(", url].concat();
+    if !title.is_empty() {
+        let quote = if title.contains(&b'"') { b'\'' } else { b'"' };
+        s.push(b' ');
+        s.push(quote);
+        s.extend_from_slice(title);
+        s.push(quote);
+    }
+    s.push(b')');
+    if !attrs.is_empty() {
+        s.extend_from_slice(attrs.to_source().as_bytes());
+    }
+    s
+}
+
+// 链接的文本里只有 [ ] \ 有特殊含义
+fn escape_link_text(text: &[u8]) -> Vec<u8> {
+    let mut s = Vec::with_capacity(text.len());
+    for &b in text {
+        if matches!(b, b'[' | b']' | b'\\') {
+            s.push(b'\\');
+        }
+        s.push(b);
+    }
+    s
+}
+
+fn is_blank(b: u8) -> bool {
+    b == b' ' || b == b'\t'
+}
+
+// 只转义原样输出时会被tokenizer解析成别的东西的字符
+// tokenizer匹配失败时只把开头的标记当做文本，后面的重新解析，所以每个字符只看它后面这一行能不能匹配上
+// line_start：在段落的行首，# > - ::: ``` 这些会变成块
+// in_header：标题结尾的 {...} 是属性列表
+// next：text后面紧跟着的字符，None表示后面是行尾
+fn escape(
+    bytes: &[u8],
+    line_start: bool,
+    in_header: bool,
+    after: LastInline,
+    next: Option<u8>,
+) -> Vec<u8> {
+    // 第i个字节，超出text的部分是next
+    let at = |i: usize| {
+        if i < bytes.len() {
+            Some(bytes[i])
+        } else if i == bytes.len() {
+            next
+        } else {
+            None
+        }
+    };
+
+    let mut s = Vec::with_capacity(bytes.len() + 8);
+    for (i, &c) in bytes.iter().enumerate() {
+        let rest = &bytes[i + 1..];
+        let needed = match c {
+            b'\\' => true,
+            // 后面不是空白的 $ 会开始一个公式，这一行后面还有 $ 就可能闭合
+            b'$' => match at(i + 1) {
+                None | Some(b' ' | b'\t') => false,
+                _ => next.is_some() || rest.contains(&b'$'),
+            },
+            // [^ 是脚注，[! 在引用的开头是提示块
+            b'[' => matches!(at(i + 1), Some(b'^' | b'!')) || link_follows(rest, next),
+            b'!' => at(i + 1) == Some(b'['),
+            b'{' => in_header || at(i + 1) == Some(b'{') || attrs_follow(rest, next),
+            _ => false,
+        };
+
+        let needed = needed
+            || (i == 0 && line_start && block_prefix(bytes))
+            || (i == 0 && after == LastInline::Math && c.is_ascii_digit())
+            || (i == 0 && after == LastInline::Link && c == b'{')
+            || (i == 0 && after == LastInline::FootnoteRef && c == b':');
+        if needed {
+            s.push(b'\\');
+        }
+        s.push(c);
+    }
+    s
+}
+
+// 行首的内容会不会被当做块的开头
+fn block_prefix(bytes: &[u8]) -> bool {
+    match bytes[0] {
+        b'#' | b'>' => true,
+        b'-' => bytes.starts_with(b"- ["),
+        b':' => bytes.starts_with(b":::"),
+        b'`' | b'~' => bytes.len() >= 3 && bytes[1] == bytes[0] && bytes[2] == bytes[0],
+        _ => false,
+    }
+}
+
+// [ 后面这一行里第一个 ] 后面是 ( 就可能是链接，中间的 [ 可能被转义了，不算
+// 后面还有别的行内元素的话，] 可能在它们后面
+fn link_follows(rest: &[u8], next: Option<u8>) -> bool {
+    match rest.iter().position(|&b| b == b']') {
+        Some(idx) => rest.get(idx + 1) == Some(&b'('),
+        None => matches!(next, Some(b) if b != b'[' && b != b'!'),
+    }
+}
+
+// { 后面到行尾是 ...} 加空白的话是属性列表，内容会原样读出来
+// 后面还有别的行内元素的话，} 可能在它们后面
+fn attrs_follow(rest: &[u8], next: Option<u8>) -> bool {
+    match rest.iter().position(|&b| b == b'}') {
+        Some(idx) => next.is_none() && rest[idx + 1..].iter().all(|&b| b == b' ' || b == b'\t'),
+        None => next.is_some(),
+    }
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::text_writer::TextWriter;
+
+    let format = |input: &str| {
+        let mut parser =
+            StatefulParser::with_renderer(input.as_bytes(), MarkdownWriter::new(Vec::new()));
+        parser.parse_and_write().unwrap();
+        String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap()
+    };
+    let text = |input: &str| {
+        let mut parser =
+            StatefulParser::with_renderer(input.as_bytes(), TextWriter::new(Vec::new()));
+        parser.parse_and_write().unwrap();
+        String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap()
+    };
+
+    let input = "#   Title   {#top  .a}\n\n\n\ntext  with \\# and \\[x](y) and $5\n  \\# not a header\n> quote\n> more\n\n- [ ] a\n\n- [x] b\n\\- [ ] not a task\n\n> [!NOTE]\n> alert\n\n:::tip Hint {.x}\ninside [link](http://a.b \"T\"){.c}![img](i.png)\n:::\n\n````rs\n```\n````\n\n$$x^2$$ and \\$\\$ and \\{{< a >}} {{< b c >}}\n\nref[^1]\n\n[^1]: note\n";
+    let out = format(input);
+    assert_eq!(
+        "# Title {#top .a}\n\ntext  with # and \\[x](y) and $5\n\\# not a header\n\n> quote\n> more\n\n- [ ] a\n- [x] b\n  - [ ] not a task\n\n> [!NOTE]\n> alert\n\n:::tip Hint {.x}\ninside [link](http://a.b \"T\"){.c}![img](i.png)\n:::\n\n````rs\n```\n````\n\n$$x^2$$ and \\$$ and \\{{< a >}} {{< b c >}}\n\nref[^1]\n\n[^1]: note\n",
+        out
+    );
+    // 再格式化一次不变，内容和原来的一样
+    assert_eq!(out, format(&out));
+    assert_eq!(
+        text(input).split_whitespace().collect::<Vec<_>>(),
+        text(&out).split_whitespace().collect::<Vec<_>>()
+    );
+
+    // 不是utf8的byte原样保留
+    let input = b"a\xff [l\xfe](u\xfd \"t\xfc\")\n\n# \xe4\n\n```\n\xe4\n```\n";
+    let mut parser = StatefulParser::with_renderer(&input[..], MarkdownWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    assert_eq!(&input[..], parser.into_renderer().into_inner().unwrap());
+
+    // 随机拼出来的输入，格式化两次结果一样，html除了空白以外不变
+    let html = |input: &str| {
+        use crate::html_writer::HtmlWriterImpl;
+        use crate::mapper::Mapper;
+        use crate::schema::DefaultSchema;
+
+        let mut out = Vec::new();
+        let mapper = Mapper::new(DefaultSchema, HtmlWriterImpl::new(&mut out));
+        StatefulParser::with_renderer(input.as_bytes(), mapper)
+            .parse_and_write()
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let out = &out[..out.find("<style>").unwrap_or(out.len())];
+        out.replace("&nbsp;", "")
+            .replace("<br>", "")
+            .split_whitespace()
+            .collect::<String>()
+    };
+    let pieces = [
+        "\n",
+        "\n",
+        " ",
+        "\t",
+        "\r\n",
+        "a",
+        "b c",
+        "中",
+        "#",
+        "# ",
+        "## ",
+        ">",
+        "> ",
+        "-",
+        "- ",
+        "- [ ] ",
+        "- [x] ",
+        "[",
+        "]",
+        "(",
+        ")",
+        "[a](b)",
+        "[a](b \"t\")",
+        "![i](s)",
+        "{",
+        "}",
+        "{#id}",
+        "{.c}",
+        "{{< x >}}",
+        "{{",
+        "$",
+        "$$",
+        "$x$",
+        "1",
+        "\\",
+        "\\$",
+        "`",
+        "```",
+        "```rs",
+        "~~",
+        ":",
+        ":::",
+        ":::tip",
+        "::: d",
+        "[^1]",
+        "[^1]: ",
+        "!",
+        "\"",
+        "> [!NOTE]",
+    ];
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut rand = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize
+    };
+    for _ in 0..400 {
+        let len = rand() % 12 + 1;
+        let input: String = (0..len).map(|_| pieces[rand() % pieces.len()]).collect();
+        let out = format(&input);
+        assert_eq!(out, format(&out), "{:?}", input);
+        assert_eq!(html(&input), html(&out), "{:?}", input);
+    }
+}
@@ -0,0 +1,879 @@
+use crate::CowStr;
+
+// 把TeX的一个常用子集（分数、上下标、希腊字母、求和积分、根号、矩阵）转换成MathML
+// 不支持的命令输出成<merror>，同时返回对应的提示信息
+pub fn tex_to_mathml(tex: &str, display: bool, out: &mut String) -> Vec<CowStr> {
+    let mut p = TexParser {
+        src: tex.chars().collect(),
+        pos: 0,
+        display,
+        depth: 0,
+        errors: Vec::new(),
+    };
+
+    let mut items = Vec::new();
+    loop {
+        items.extend(p.parse_row());
+        match p.peek() {
+            None => break,
+            Some('}') => {
+                p.pos += 1;
+                p.error("unmatched '}'");
+            }
+            Some('&') => {
+                p.pos += 1;
+                p.error("'&' outside of a matrix");
+            }
+            Some('\\') => {
+                // \\、\end、\right 出现在了顶层
+                let name = p.read_command();
+                match name.as_str() {
+                    "\\" => items.push(r#"<mspace linebreak="newline"/>"#.to_string()),
+                    _ => {
+                        let msg = format!("unexpected \\{}", name);
+                        p.error(msg);
+                    }
+                }
+            }
+            Some(_) => unreachable!(),
+        }
+    }
+
+    out.push_str(&row(items));
+    p.errors
+}
+
+const MAX_DEPTH: u32 = 64;
+
+struct TexParser {
+    src: Vec<char>,
+    pos: usize,
+    display: bool,
+    depth: u32,
+    errors: Vec<CowStr>,
+}
+
+// 一个带上下标的元素，大型运算符在块级公式里上下标写在正上方和正下方
+struct Atom {
+    mathml: String,
+    limits: bool,
+}
+
+impl TexParser {
+    fn error<S: Into<CowStr>>(&mut self, msg: S) {
+        self.errors.push(msg.into());
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.src.get(self.pos) {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_ws();
+        self.src.get(self.pos).copied()
+    }
+
+    // 不跳过空白，\text{} 里面的空格是有意义的
+    fn peek_raw(&self) -> Option<char> {
+        self.src.get(self.pos).copied()
+    }
+
+    // 当前位置是 '\'，读出命令名，不移动位置
+    fn peek_command(&self) -> String {
+        let start = self.pos + 1;
+        let mut end = start;
+
+        while end < self.src.len() && self.src[end].is_ascii_alphabetic() {
+            end += 1;
+        }
+        if end == start && end < self.src.len() {
+            end += 1;
+        }
+
+        self.src[start..end].iter().collect()
+    }
+
+    fn read_command(&mut self) -> String {
+        let name = self.peek_command();
+        self.pos += 1 + name.chars().count();
+        name
+    }
+
+    // 是否到了当前一行（或者单元格、分组）的结尾
+    fn at_row_end(&mut self) -> bool {
+        match self.peek() {
+            None | Some('}') | Some('&') => true,
+            Some('\\') => matches!(self.peek_command().as_str(), "\\" | "end" | "right"),
+            Some(_) => false,
+        }
+    }
+
+    fn parse_row(&mut self) -> Vec<String> {
+        let mut items = Vec::new();
+
+        while !self.at_row_end() {
+            if let Some(atom) = self.parse_atom(true) {
+                let item = self.parse_scripts(atom);
+                items.push(item);
+            }
+        }
+
+        items
+    }
+
+    fn parse_scripts(&mut self, atom: Atom) -> String {
+        let mut sub = None;
+        let mut sup = None;
+
+        loop {
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_arg());
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_arg());
+                }
+                Some('\'') if sup.is_none() => {
+                    let mut primes = String::new();
+                    while self.peek() == Some('\'') {
+                        self.pos += 1;
+                        primes.push('′');
+                    }
+                    sup = Some(format!("<mo>{}</mo>", primes));
+                }
+                Some('_') | Some('^') => {
+                    self.pos += 1;
+                    self.error("double subscript or superscript");
+                }
+                _ => break,
+            }
+        }
+
+        let under_over = atom.limits && self.display;
+        match (sub, sup) {
+            (None, None) => atom.mathml,
+            (Some(b), None) if under_over => format!("<munder>{}{}</munder>", atom.mathml, b),
+            (Some(b), None) => format!("<msub>{}{}</msub>", atom.mathml, b),
+            (None, Some(p)) if under_over => format!("<mover>{}{}</mover>", atom.mathml, p),
+            (None, Some(p)) => format!("<msup>{}{}</msup>", atom.mathml, p),
+            (Some(b), Some(p)) if under_over => {
+                format!("<munderover>{}{}{}</munderover>", atom.mathml, b, p)
+            }
+            (Some(b), Some(p)) => format!("<msubsup>{}{}{}</msubsup>", atom.mathml, b, p),
+        }
+    }
+
+    // 命令或上下标的参数：一个分组，或者单个字符/命令
+    fn parse_arg(&mut self) -> String {
+        match self.peek() {
+            Some('{') => self.parse_group(),
+            None => {
+                self.error("missing argument");
+                "<mrow></mrow>".to_string()
+            }
+            Some(_) if self.at_row_end() => {
+                self.error("missing argument");
+                "<mrow></mrow>".to_string()
+            }
+            Some(_) => match self.parse_atom(false) {
+                Some(atom) => atom.mathml,
+                None => "<mrow></mrow>".to_string(),
+            },
+        }
+    }
+
+    fn parse_group(&mut self) -> String {
+        debug_assert_eq!(self.peek(), Some('{'));
+        self.pos += 1;
+
+        let mut items = Vec::new();
+        loop {
+            items.extend(self.parse_row());
+            match self.peek() {
+                Some('}') => {
+                    self.pos += 1;
+                    break;
+                }
+                None => {
+                    self.error("missing '}'");
+                    break;
+                }
+                // & 或者 \\ 出现在分组里，跳过
+                Some('&') => {
+                    self.pos += 1;
+                    self.error("'&' outside of a matrix");
+                }
+                Some(_) => {
+                    let name = self.read_command();
+                    let msg = format!("unexpected \\{}", name);
+                    self.error(msg);
+                }
+            }
+        }
+
+        row(items)
+    }
+
+    // 原样读出一个分组的内容，用于 \text{} 和 \begin{}
+    fn parse_raw_group(&mut self) -> String {
+        if self.peek() != Some('{') {
+            self.error("expected '{'");
+            return String::new();
+        }
+        self.pos += 1;
+
+        let mut level = 1;
+        let mut text = String::new();
+        while let Some(c) = self.peek_raw() {
+            self.pos += 1;
+            match c {
+                '{' => level += 1,
+                '}' => {
+                    level -= 1;
+                    if level == 0 {
+                        return text;
+                    }
+                }
+                '\\' => {
+                    // \{ \} 之类的转义
+                    if let Some(next) = self.peek_raw() {
+                        self.pos += 1;
+                        text.push(next);
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            text.push(c);
+        }
+
+        self.error("missing '}'");
+        text
+    }
+
+    fn parse_atom(&mut self, with_number: bool) -> Option<Atom> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            self.error("formula is nested too deeply");
+            self.pos = self.src.len();
+            self.depth -= 1;
+            return None;
+        }
+
+        let atom = self.parse_atom0(with_number);
+        self.depth -= 1;
+        atom
+    }
+
+    // with_number为false时，数字只取一位，比如 x^23 的上标只有2
+    fn parse_atom0(&mut self, with_number: bool) -> Option<Atom> {
+        let c = self.peek()?;
+        let plain = |mathml: String| {
+            Some(Atom {
+                mathml,
+                limits: false,
+            })
+        };
+
+        match c {
+            '{' => plain(self.parse_group()),
+            '\\' => self.parse_command(),
+            '0'..='9' | '.' => {
+                let mut num = String::new();
+                while let Some(d) = self.peek_raw() {
+                    if d.is_ascii_digit() || (d == '.' && with_number) {
+                        num.push(d);
+                        self.pos += 1;
+                        if !with_number {
+                            break;
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                if num.is_empty() {
+                    self.pos += 1;
+                    num.push('.');
+                }
+                plain(format!("<mn>{}</mn>", num))
+            }
+            '_' | '^' => {
+                // 没有底的上下标，比如 ^2
+                plain("<mrow></mrow>".to_string())
+            }
+            _ => {
+                self.pos += 1;
+                if c.is_alphabetic() {
+                    plain(format!("<mi>{}</mi>", escape(c)))
+                } else if c == '~' {
+                    plain(r#"<mspace width="0.333em"/>"#.to_string())
+                } else {
+                    plain(format!("<mo>{}</mo>", escape(c)))
+                }
+            }
+        }
+    }
+
+    fn parse_command(&mut self) -> Option<Atom> {
+        let name = self.read_command();
+        let plain = |mathml: String| {
+            Some(Atom {
+                mathml,
+                limits: false,
+            })
+        };
+
+        if let Some(c) = greek(&name) {
+            return plain(format!("<mi>{}</mi>", c));
+        }
+        if let Some(c) = identifier(&name) {
+            return plain(format!("<mi>{}</mi>", c));
+        }
+        if let Some(op) = operator(&name) {
+            return plain(format!("<mo>{}</mo>", escape_str(op)));
+        }
+        if let Some(op) = big_operator(&name) {
+            return Some(Atom {
+                mathml: format!("<mo>{}</mo>", op),
+                // 积分号的上下标还是写在右边
+                limits: !name.contains("int"),
+            });
+        }
+        if let Some(width) = space(&name) {
+            return plain(format!(r#"<mspace width="{}"/>"#, width));
+        }
+        if FUNCTIONS.contains(&name.as_str()) {
+            return Some(Atom {
+                mathml: format!("<mi>{}</mi>", name),
+                limits: LIMIT_FUNCTIONS.contains(&name.as_str()),
+            });
+        }
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let num = self.parse_arg();
+                let den = self.parse_arg();
+                plain(format!("<mfrac>{}{}</mfrac>", num, den))
+            }
+            "binom" => {
+                let n = self.parse_arg();
+                let k = self.parse_arg();
+                plain(format!(
+                    r#"<mrow><mo>(</mo><mfrac linethickness="0">{}{}</mfrac><mo>)</mo></mrow>"#,
+                    n, k
+                ))
+            }
+            "sqrt" => {
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let mut index = Vec::new();
+                    while !matches!(self.peek(), Some(']') | None) {
+                        if self.at_row_end() {
+                            break;
+                        }
+                        // 和parse_row一样，上下标接在前面的atom上，没有前进的话跳过这个字符
+                        let start = self.pos;
+                        if let Some(atom) = self.parse_atom(true) {
+                            index.push(self.parse_scripts(atom));
+                        }
+                        if self.pos == start {
+                            self.pos += 1;
+                        }
+                    }
+                    if self.peek() == Some(']') {
+                        self.pos += 1;
+                    } else {
+                        self.error("missing ']'");
+                    }
+                    let base = self.parse_arg();
+                    plain(format!("<mroot>{}{}</mroot>", base, row(index)))
+                } else {
+                    let base = self.parse_arg();
+                    plain(format!("<msqrt>{}</msqrt>", base))
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" => {
+                let text = self.parse_raw_group();
+                plain(format!("<mtext>{}</mtext>", escape_str(&text)))
+            }
+            "operatorname" => {
+                let text = self.parse_raw_group();
+                plain(format!("<mi>{}</mi>", escape_str(&text)))
+            }
+            "mathrm" | "mathbf" | "mathit" | "mathbb" | "mathcal" | "mathfrak" | "mathsf"
+            | "mathtt" | "boldsymbol" => {
+                let variant = match name.as_str() {
+                    "mathrm" => "normal",
+                    "mathbf" | "boldsymbol" => "bold",
+                    "mathit" => "italic",
+                    "mathbb" => "double-struck",
+                    "mathcal" => "script",
+                    "mathfrak" => "fraktur",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+                let arg = self.parse_arg();
+                plain(format!(
+                    r#"<mstyle mathvariant="{}">{}</mstyle>"#,
+                    variant, arg
+                ))
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "tilde" | "widetilde" | "dot"
+            | "ddot" | "check" | "breve" | "acute" | "grave" => {
+                let accent = match name.as_str() {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "¯",
+                    "vec" => "→",
+                    "tilde" | "widetilde" => "~",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    "check" => "ˇ",
+                    "breve" => "˘",
+                    "acute" => "´",
+                    _ => "`",
+                };
+                let base = self.parse_arg();
+                plain(format!(
+                    r#"<mover accent="true">{}<mo>{}</mo></mover>"#,
+                    base, accent
+                ))
+            }
+            "underline" => {
+                let base = self.parse_arg();
+                plain(format!(
+                    r#"<munder accentunder="true">{}<mo>_</mo></munder>"#,
+                    base
+                ))
+            }
+            "left" => plain(self.parse_left()),
+            "begin" => plain(self.parse_env()),
+            // 只影响排版大小，MathML里不需要
+            "displaystyle" | "textstyle" | "limits" | "nolimits" | "big" | "Big" | "bigg"
+            | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" => None,
+            _ => {
+                let msg = format!("unsupported command \\{}", name);
+                self.error(msg);
+                plain(format!(
+                    "<merror><mtext>\\{}</mtext></merror>",
+                    escape_str(&name)
+                ))
+            }
+        }
+    }
+
+    // \left 或者 \right 后面跟着的括号，'.' 表示不显示
+    fn parse_delimiter(&mut self) -> String {
+        match self.peek() {
+            Some('\\') => {
+                let name = self.read_command();
+                match operator(&name) {
+                    Some(op) => format!(r#"<mo stretchy="true">{}</mo>"#, escape_str(op)),
+                    None => {
+                        let msg = format!("unsupported delimiter \\{}", name);
+                        self.error(msg);
+                        String::new()
+                    }
+                }
+            }
+            Some('.') => {
+                self.pos += 1;
+                String::new()
+            }
+            Some(c) => {
+                self.pos += 1;
+                format!(r#"<mo stretchy="true">{}</mo>"#, escape(c))
+            }
+            None => {
+                self.error("missing delimiter");
+                String::new()
+            }
+        }
+    }
+
+    fn parse_left(&mut self) -> String {
+        let open = self.parse_delimiter();
+        let mut items = Vec::new();
+
+        loop {
+            items.extend(self.parse_row());
+            match self.peek() {
+                Some('\\') if self.peek_command() == "right" => {
+                    self.read_command();
+                    break;
+                }
+                None => {
+                    self.error("missing \\right");
+                    return format!("<mrow>{}{}</mrow>", open, items.concat());
+                }
+                Some(_) => {
+                    // 不在矩阵里的 & 和 \\，还有不匹配的 }
+                    self.pos += 1;
+                    self.error("unexpected token inside \\left ... \\right");
+                }
+            }
+        }
+
+        let close = self.parse_delimiter();
+        format!("<mrow>{}{}{}</mrow>", open, items.concat(), close)
+    }
+
+    fn parse_env(&mut self) -> String {
+        let env = self.parse_raw_group();
+        let (open, close, align) = match env.as_str() {
+            "matrix" | "smallmatrix" => ("", "", None),
+            "pmatrix" => ("(", ")", None),
+            "bmatrix" => ("[", "]", None),
+            "Bmatrix" => ("{", "}", None),
+            "vmatrix" => ("|", "|", None),
+            "Vmatrix" => ("‖", "‖", None),
+            "cases" => ("{", "", Some("left left")),
+            "aligned" | "align" | "align*" | "split" => ("", "", Some("right left")),
+            "array" => {
+                // 列格式只是简单的跳过
+                self.parse_raw_group();
+                ("", "", None)
+            }
+            _ => {
+                let msg = format!("unsupported environment {}", env);
+                self.error(msg);
+                ("", "", None)
+            }
+        };
+
+        let mut rows = String::new();
+        loop {
+            let mut cells = String::new();
+            let mut is_empty = true;
+            loop {
+                let cell = self.parse_row();
+                is_empty = is_empty && cell.is_empty();
+                cells.push_str("<mtd>");
+                cells.push_str(&row(cell));
+                cells.push_str("</mtd>");
+
+                if self.peek() == Some('&') {
+                    self.pos += 1;
+                    is_empty = false;
+                } else {
+                    break;
+                }
+            }
+
+            let row_end = match self.peek() {
+                Some('\\') => self.read_command(),
+                Some('}') => {
+                    self.pos += 1;
+                    self.error("unmatched '}'");
+                    "\\".to_string()
+                }
+                _ => String::new(),
+            };
+
+            // 最后一行后面多写的 \\ 不产生空行
+            if !is_empty || row_end == "\\" {
+                rows.push_str("<mtr>");
+                rows.push_str(&cells);
+                rows.push_str("</mtr>");
+            }
+
+            match row_end.as_str() {
+                "\\" => continue,
+                "end" => {
+                    let end = self.parse_raw_group();
+                    if end != env {
+                        let msg = format!("\\begin{{{}}} ended by \\end{{{}}}", env, end);
+                        self.error(msg);
+                    }
+                    break;
+                }
+                _ => {
+                    let msg = format!("missing \\end{{{}}}", env);
+                    self.error(msg);
+                    if row_end == "right" {
+                        // 让外层的 \left 处理
+                        self.pos -= "\\right".len();
+                    }
+                    break;
+                }
+            }
+        }
+
+        let table = match align {
+            Some(align) => format!(r#"<mtable columnalign="{}">{}</mtable>"#, align, rows),
+            None => format!("<mtable>{}</mtable>", rows),
+        };
+        let open = if open.is_empty() {
+            String::new()
+        } else {
+            format!("<mo>{}</mo>", escape_str(open))
+        };
+        let close = if close.is_empty() {
+            String::new()
+        } else {
+            format!("<mo>{}</mo>", escape_str(close))
+        };
+
+        format!("<mrow>{}{}{}</mrow>", open, table, close)
+    }
+}
+
+fn row(items: Vec<String>) -> String {
+    if items.len() == 1 {
+        items.into_iter().next().unwrap()
+    } else {
+        format!("<mrow>{}</mrow>", items.concat())
+    }
+}
+
+fn escape(c: char) -> String {
+    match c {
+        '<' => "&lt;".to_string(),
+        '>' => "&gt;".to_string(),
+        '&' => "&amp;".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+fn escape_str(s: &str) -> String {
+    s.chars().map(escape).collect()
+}
+
+fn greek(name: &str) -> Option<char> {
+    Some(match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ϵ',
+        "varepsilon" => 'ε',
+        "zeta" => 'ζ',
+        "eta" => 'η',
+        "theta" => 'θ',
+        "vartheta" => 'ϑ',
+        "iota" => 'ι',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "nu" => 'ν',
+        "xi" => 'ξ',
+        "omicron" => 'ο',
+        "pi" => 'π',
+        "varpi" => 'ϖ',
+        "rho" => 'ρ',
+        "varrho" => 'ϱ',
+        "sigma" => 'σ',
+        "varsigma" => 'ς',
+        "tau" => 'τ',
+        "upsilon" => 'υ',
+        "phi" => 'ϕ',
+        "varphi" => 'φ',
+        "chi" => 'χ',
+        "psi" => 'ψ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Xi" => 'Ξ',
+        "Pi" => 'Π',
+        "Sigma" => 'Σ',
+        "Upsilon" => 'Υ',
+        "Phi" => 'Φ',
+        "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        _ => return None,
+    })
+}
+
+fn identifier(name: &str) -> Option<char> {
+    Some(match name {
+        "infty" => '∞',
+        "partial" => '∂',
+        "nabla" => '∇',
+        "emptyset" | "varnothing" => '∅',
+        "ell" => 'ℓ',
+        "hbar" => 'ℏ',
+        "Re" => 'ℜ',
+        "Im" => 'ℑ',
+        "aleph" => 'ℵ',
+        "%" => '%',
+        "$" => '$',
+        "#" => '#',
+        "_" => '_',
+        _ => return None,
+    })
+}
+
+fn operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "cdot" => "⋅",
+        "times" => "×",
+        "div" => "÷",
+        "pm" => "±",
+        "mp" => "∓",
+        "ast" => "∗",
+        "star" => "⋆",
+        "circ" => "∘",
+        "bullet" => "∙",
+        "oplus" => "⊕",
+        "otimes" => "⊗",
+        "leq" | "le" => "≤",
+        "geq" | "ge" => "≥",
+        "neq" | "ne" => "≠",
+        "ll" => "≪",
+        "gg" => "≫",
+        "approx" => "≈",
+        "equiv" => "≡",
+        "sim" => "∼",
+        "simeq" => "≃",
+        "cong" => "≅",
+        "propto" => "∝",
+        "to" | "rightarrow" => "→",
+        "leftarrow" | "gets" => "←",
+        "leftrightarrow" => "↔",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftarrow" => "⇐",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "subset" => "⊂",
+        "subseteq" => "⊆",
+        "supset" => "⊃",
+        "supseteq" => "⊇",
+        "cup" => "∪",
+        "cap" => "∩",
+        "setminus" => "∖",
+        "forall" => "∀",
+        "exists" => "∃",
+        "neg" | "lnot" => "¬",
+        "wedge" | "land" => "∧",
+        "vee" | "lor" => "∨",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "mid" => "∣",
+        "ldots" | "dots" => "…",
+        "cdots" => "⋯",
+        "vdots" => "⋮",
+        "ddots" => "⋱",
+        "langle" => "⟨",
+        "rangle" => "⟩",
+        "lfloor" => "⌊",
+        "rfloor" => "⌋",
+        "lceil" => "⌈",
+        "rceil" => "⌉",
+        "lvert" | "rvert" | "vert" => "|",
+        "lVert" | "rVert" | "Vert" | "|" => "‖",
+        "{" | "lbrace" => "{",
+        "}" | "rbrace" => "}",
+        "&" => "&",
+        "prime" => "′",
+        _ => return None,
+    })
+}
+
+fn big_operator(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "oint" => "∮",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "bigvee" => "⋁",
+        "bigwedge" => "⋀",
+        _ => return None,
+    })
+}
+
+fn space(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "," => "0.167em",
+        ":" | ">" => "0.222em",
+        ";" => "0.278em",
+        "!" => "-0.167em",
+        " " => "0.333em",
+        "quad" => "1em",
+        "qquad" => "2em",
+        _ => return None,
+    })
+}
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "coth", "log", "ln", "lg", "exp", "max", "min", "sup", "inf", "lim", "liminf", "limsup", "det",
+    "dim", "ker", "deg", "arg", "gcd", "hom", "Pr",
+];
+
+// 这些函数在块级公式里，下标写在正下方
+const LIMIT_FUNCTIONS: &[&str] = &[
+    "max", "min", "sup", "inf", "lim", "liminf", "limsup", "det", "gcd", "Pr",
+];
+
+#[test]
+fn test() {
+    let convert = |tex: &str, display: bool| {
+        let mut out = String::new();
+        let errors = tex_to_mathml(tex, display, &mut out);
+        (out, errors.len())
+    };
+
+    assert_eq!(
+        (
+            "<mrow><msup><mi>x</mi><mn>2</mn></msup><mo>+</mo><mn>1.5</mn></mrow>".to_string(),
+            0
+        ),
+        convert("x^2 + 1.5", false)
+    );
+    assert_eq!(
+        (
+            "<mfrac><mi>α</mi><msqrt><mi>b</mi></msqrt></mfrac>".to_string(),
+            0
+        ),
+        convert(r"\frac{\alpha}{\sqrt b}", false)
+    );
+    assert_eq!(
+        (
+            "<mrow><munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>0</mn></mrow><mi>n</mi></munderover><msub><mi>a</mi><mi>i</mi></msub></mrow>".to_string(),
+            0
+        ),
+        convert(r"\sum_{i=0}^n a_i", true)
+    );
+    assert_eq!(
+        (
+            "<mrow><mo>(</mo><mtable><mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr></mtable><mo>)</mo></mrow>".to_string(),
+            0
+        ),
+        convert(r"\begin{pmatrix} 1 & 0 \\ 0 & 1 \\ \end{pmatrix}", true)
+    );
+    assert_eq!(
+        ("<mroot><mi>x</mi><mn>3</mn></mroot>".to_string(), 0),
+        convert(r"\sqrt[3]{x}", false)
+    );
+    // 根号的次数里没有底的下标不能一直循环
+    let (out, errors) = convert(r"x \sqrt[_ y", false);
+    assert!(out.contains("<mroot>"));
+    assert!(errors > 0);
+
+    let (out, errors) = convert(r"a \foo b", false);
+    assert_eq!(1, errors);
+    assert!(out.contains("<merror><mtext>\\foo</mtext></merror>"));
+    assert_eq!(1, convert("{a", false).1);
+    assert_eq!(1, convert("a}", false).1);
+}
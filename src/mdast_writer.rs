@@ -0,0 +1,543 @@
+use crate::attr::Attrs;
+use crate::json::Json;
+use crate::parser::Parser;
+use crate::parser::StatefulParser;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+// 输出mdast（unified/remark的语法树）格式的json，整个文档结束时一起输出
+// 任务列表是 list > listItem(checked)，容器和指令是remark-directive的containerDirective，
+// 公式是remark-math的math和inlineMath，属性列表放在 data.hProperties 里
+pub struct MdastWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    // 还没有结束的节点，第一个是root
+    stack: Vec<Open>,
+    // 指令的内容单独解析，行号要加上指令所在的行
+    line_offset: u32,
+    // 指令的内容不知道在原文里的offset，这时不输出offset
+    has_offset: bool,
+    // block_last_pos给的最后一个字符的位置
+    last_pos: Pos,
+}
+
+struct Open {
+    // 还没有children的节点
+    node: Json,
+    children: Vec<Json>,
+    // 结束这个节点的块，段落外面的隐式段落、任务列表外面的list和链接是None
+    tag: Option<Tag>,
+    start: Option<Pos>,
+}
+
+impl<W: Write> MdastWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            stack: vec![Open {
+                node: node("root"),
+                children: Vec::new(),
+                tag: None,
+                start: None,
+            }],
+            line_offset: 0,
+            has_offset: true,
+            last_pos: Pos::new(1, 1, 0),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn top_type(&self) -> &str {
+        self.stack
+            .last()
+            .and_then(|x| x.node.get("type"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+    }
+
+    fn point(&self, pos: Pos) -> Json {
+        let mut point = Json::object([
+            (
+                "line",
+                Json::Number((pos.line_num + self.line_offset) as i64),
+            ),
+            ("column", Json::Number(pos.column_num.max(1) as i64)),
+        ]);
+        if self.has_offset {
+            point.set("offset", Json::Number(pos.offset as i64));
+        }
+        point
+    }
+
+    // unist的end是最后一个字符后面的位置，offset本来就是token结尾的
+    fn end(&self) -> Pos {
+        let last = self.last_pos;
+        Pos::new(last.line_num, last.column_num + 1, last.offset)
+    }
+
+    fn position(&self, start: Pos, end: Pos) -> Json {
+        Json::object([("start", self.point(start)), ("end", self.point(end))])
+    }
+
+    fn push(&mut self, node: Json, tag: Option<Tag>, start: Option<Pos>) {
+        self.stack.push(Open {
+            node,
+            children: Vec::new(),
+            tag,
+            start,
+        });
+    }
+
+    fn add(&mut self, child: Json) {
+        self.stack.last_mut().unwrap().children.push(child);
+    }
+
+    // 结束栈顶的节点，放到上一层的children里
+    fn pop(&mut self, end: Option<Pos>) {
+        if self.stack.len() == 1 {
+            return;
+        }
+        let open = self.stack.pop().unwrap();
+        let mut node = open.node;
+        let mut children = open.children;
+
+        let kind = node.get("type").and_then(Json::as_str).unwrap_or("");
+        if kind == "paragraph" || kind == "heading" {
+            // 结尾的换行和空白去掉
+            if let Some(Json::String(value)) = children.last_mut().and_then(|x| x.get_mut("value"))
+            {
+                value.truncate(value.trim_end().len());
+            }
+        }
+        if kind == "paragraph" {
+            children.retain(|x| x.get("value") != Some(&Json::str("")));
+
+            match &children[..] {
+                [] => return,
+                // 单独一行的 $$...$$ 是块级公式，和其他内容在一起的是行内公式
+                [x] if x.get("type") == Some(&Json::str("math")) => {
+                    let math = children.pop().unwrap();
+                    return self.add(math);
+                }
+                _ => {
+                    for child in children.iter_mut() {
+                        if child.get("type") == Some(&Json::str("math")) {
+                            child.set("type", Json::str("inlineMath"));
+                        }
+                    }
+                }
+            }
+        }
+
+        // 隐式结束的list用最后一个listItem的结尾
+        let position = open.start.map(|start| {
+            let end = match end {
+                Some(end) => self.point(end),
+                None => children
+                    .last()
+                    .and_then(|x| x.get("position"))
+                    .and_then(|x| x.get("end"))
+                    .cloned()
+                    .unwrap_or_else(|| self.point(start)),
+            };
+            Json::object([("start", self.point(start)), ("end", end)])
+        });
+        node.set("children", Json::Array(children));
+        if let Some(position) = position {
+            node.set("position", position);
+        }
+        self.add(node);
+    }
+
+    fn close_until(&mut self, tag: Tag) {
+        if !self.stack.iter().any(|x| x.tag == Some(tag)) {
+            return;
+        }
+        while let Some(open) = self.stack.last() {
+            let done = open.tag == Some(tag);
+            self.pop(Some(self.end()));
+            if done {
+                break;
+            }
+        }
+    }
+
+    // 新的块开始之前，结束隐式的段落，keep_list为false时也结束任务列表
+    fn close_implicit(&mut self, keep_list: bool) {
+        while self.stack.len() > 1 && self.stack.last().unwrap().tag.is_none() {
+            match self.top_type() {
+                "paragraph" => self.pop(None),
+                "list" if !keep_list => self.pop(None),
+                _ => break,
+            }
+        }
+    }
+
+    // 行内内容之前，确保在段落或者标题里
+    fn inline_parent(&mut self) {
+        if self.top_type() == "list" {
+            self.close_implicit(false);
+        }
+        if !matches!(self.top_type(), "paragraph" | "heading" | "link") {
+            self.push(node("paragraph"), None, None);
+        }
+    }
+
+    // 和前面的文本合并成一个text节点
+    fn add_text(&mut self, s: &str) {
+        self.inline_parent();
+        let children = &mut self.stack.last_mut().unwrap().children;
+        if let Some(last) = children.last_mut() {
+            if last.get("type") == Some(&Json::str("text")) {
+                if let Some(Json::String(value)) = last.get_mut("value") {
+                    value.push_str(s);
+                    return;
+                }
+            }
+        }
+        children.push(Json::object([
+            ("type", Json::str("text")),
+            ("value", Json::str(s)),
+        ]));
+    }
+
+    // 指令的内容当做markdown解析，结果作为指令的children
+    fn parse_children(&self, content: &[u8], line_num: u32) -> Vec<Json> {
+        let mut writer = MdastWriter::new(io::sink());
+        writer.line_offset = self.line_offset + line_num;
+        writer.has_offset = false;
+        let mut parser = StatefulParser::from_slice(content, writer);
+        if parser.parse_and_write().is_err() {
+            return Vec::new();
+        }
+        let mut writer = parser.into_renderer();
+        std::mem::take(&mut writer.stack[0].children)
+    }
+}
+
+fn node(kind: &str) -> Json {
+    Json::object([("type", Json::str(kind))])
+}
+
+fn null_or_str(data: &[u8]) -> Json {
+    if data.is_empty() {
+        Json::Null
+    } else {
+        Json::bytes(data)
+    }
+}
+
+// 属性列表转成hast的属性，class是className数组，没有值的属性是true
+fn h_properties(attrs: &Attrs) -> Json {
+    let mut props = Json::Object(Vec::new());
+    for (key, value) in attrs.named() {
+        if key == "class" {
+            let classes = value.split_whitespace().map(Json::str).collect();
+            props.set("className", Json::Array(classes));
+        } else {
+            props.set(key.as_str(), Json::str(value.as_str()));
+        }
+    }
+    for key in attrs.positional() {
+        props.set(key.as_str(), Json::Bool(true));
+    }
+    Json::object([("hProperties", props)])
+}
+
+// remark-directive的attributes，值都是字符串
+fn directive_attributes(attrs: &Attrs) -> Json {
+    let mut attributes = Json::Object(Vec::new());
+    for (key, value) in attrs.named() {
+        attributes.set(key.as_str(), Json::str(value.as_str()));
+    }
+    for key in attrs.positional() {
+        attributes.set(key.as_str(), Json::str(""));
+    }
+    attributes
+}
+
+fn with_attrs(mut node: Json, attrs: &Attrs) -> Json {
+    if !attrs.is_empty() {
+        node.set("data", h_properties(attrs));
+    }
+    node
+}
+
+impl<W: Write> Renderer for MdastWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], attrs: &Attrs, pos: Pos) -> io::Result<()> {
+        self.close_implicit(matches!(tag, Tag::TaskList(_)));
+
+        match tag {
+            Tag::Paragraph => self.push(node("paragraph"), Some(tag), Some(pos)),
+            Tag::Header(level) => {
+                let mut heading = node("heading");
+                heading.set("depth", Json::Number(level as i64));
+                self.push(heading, Some(tag), Some(pos));
+            }
+            Tag::BlockQuote => self.push(node("blockquote"), Some(tag), Some(pos)),
+            Tag::TaskList(is_done) => {
+                if self.top_type() != "list" {
+                    let list = Json::object([
+                        ("type", Json::str("list")),
+                        ("ordered", Json::Bool(false)),
+                        ("start", Json::Null),
+                        ("spread", Json::Bool(false)),
+                    ]);
+                    self.push(list, None, Some(pos));
+                }
+                let item = Json::object([
+                    ("type", Json::str("listItem")),
+                    ("spread", Json::Bool(false)),
+                    ("checked", Json::Bool(is_done)),
+                ]);
+                self.push(item, Some(tag), Some(pos));
+            }
+            Tag::Footnote => {
+                let label = String::from_utf8_lossy(info);
+                let definition = Json::object([
+                    ("type", Json::str("footnoteDefinition")),
+                    ("identifier", Json::str(label.to_lowercase())),
+                    ("label", Json::str(label)),
+                ]);
+                self.push(definition, Some(tag), Some(pos));
+            }
+            Tag::Admonition(kind) => {
+                let directive = Json::object([
+                    ("type", Json::str("containerDirective")),
+                    ("name", Json::str(kind.name())),
+                    ("attributes", directive_attributes(attrs)),
+                ]);
+                self.push(directive, Some(tag), Some(pos));
+
+                // 标题是remark-directive的label
+                if !info.is_empty() {
+                    let label = Json::object([
+                        ("type", Json::str("paragraph")),
+                        ("data", Json::object([("directiveLabel", Json::Bool(true))])),
+                        (
+                            "children",
+                            Json::Array(vec![Json::object([
+                                ("type", Json::str("text")),
+                                ("value", Json::bytes(info)),
+                            ])]),
+                        ),
+                    ]);
+                    self.add(label);
+                }
+            }
+            Tag::CodeBlock | Tag::None => {}
+        }
+        Ok(())
+    }
+
+    fn block_attrs(&mut self, attrs: &Attrs) -> io::Result<()> {
+        if let Some(open) = self.stack.last_mut() {
+            open.node.set("data", h_properties(attrs));
+        }
+        Ok(())
+    }
+
+    fn block_last_pos(&mut self, pos: Pos) -> io::Result<()> {
+        self.last_pos = pos;
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        self.close_until(tag);
+        Ok(())
+    }
+
+    fn inline_start(&mut self, inline: Inline, attrs: &Attrs) -> io::Result<()> {
+        self.inline_parent();
+        match inline {
+            Inline::Link { url, title } => {
+                let link = Json::object([
+                    ("type", Json::str("link")),
+                    ("url", Json::bytes(url)),
+                    ("title", null_or_str(title)),
+                ]);
+                self.push(with_attrs(link, attrs), None, None);
+            }
+        }
+        Ok(())
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } if self.top_type() == "link" => self.pop(None),
+            Inline::Link { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.add_text(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.add_text(" ");
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.add_text("\t");
+        Ok(())
+    }
+
+    // 段落里的换行是text里的 \n，块外面的空行不用管
+    fn line_break(&mut self) -> io::Result<()> {
+        if matches!(self.top_type(), "paragraph" | "heading") {
+            self.add_text("\n");
+        }
+        Ok(())
+    }
+
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        attrs: &Attrs,
+        pos: Pos,
+    ) -> io::Result<()> {
+        self.close_implicit(false);
+
+        let meta = if attrs.is_empty() {
+            Json::Null
+        } else {
+            Json::str(attrs.to_source())
+        };
+        let code = Json::object([
+            ("type", Json::str("code")),
+            ("lang", null_or_str(lang)),
+            ("meta", meta),
+            ("value", Json::bytes(content)),
+            ("position", self.position(pos, self.end())),
+        ]);
+        self.add(code);
+        Ok(())
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], title: &[u8], attrs: &Attrs) -> io::Result<()> {
+        self.inline_parent();
+        let image = Json::object([
+            ("type", Json::str("image")),
+            ("url", Json::bytes(src)),
+            ("title", null_or_str(title)),
+            ("alt", Json::bytes(alt)),
+        ]);
+        self.add(with_attrs(image, attrs));
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], display: bool, pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        let math = Json::object([
+            (
+                "type",
+                Json::str(if display { "math" } else { "inlineMath" }),
+            ),
+            ("value", Json::bytes(tex)),
+            ("position", self.position(pos, self.end())),
+        ]);
+        self.add(math);
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        let label = String::from_utf8_lossy(label);
+        let reference = Json::object([
+            ("type", Json::str("footnoteReference")),
+            ("identifier", Json::str(label.to_lowercase())),
+            ("label", Json::str(label)),
+            ("position", self.position(pos, self.end())),
+        ]);
+        self.add(reference);
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &[u8], args: &[u8], content: &[u8], pos: Pos) -> io::Result<()> {
+        self.close_implicit(false);
+        let children = self.parse_children(content, pos.line_num);
+        let directive = Json::object([
+            ("type", Json::str("containerDirective")),
+            ("name", Json::bytes(name)),
+            ("attributes", directive_attributes(&Attrs::parse(args))),
+            ("children", Json::Array(children)),
+            ("position", self.position(pos, self.end())),
+        ]);
+        self.add(directive);
+        Ok(())
+    }
+
+    fn shortcode(&mut self, name: &[u8], args: &[u8], pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        let directive = Json::object([
+            ("type", Json::str("textDirective")),
+            ("name", Json::bytes(name)),
+            ("attributes", directive_attributes(&Attrs::parse(args))),
+            ("children", Json::Array(Vec::new())),
+            ("position", self.position(pos, self.end())),
+        ]);
+        self.add(directive);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        while self.stack.len() > 1 {
+            self.pop(None);
+        }
+
+        // root留在栈里，指令的内容解析完之后还要取出来
+        let root = &self.stack[0];
+        self.buf_writer
+            .write_all(b"{\"type\":\"root\",\"children\":[")?;
+        for (idx, child) in root.children.iter().enumerate() {
+            if idx > 0 {
+                self.buf_writer.write_all(b",")?;
+            }
+            child.write(&mut self.buf_writer)?;
+        }
+        self.buf_writer.write_all(b"]}\n")?;
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    let input = "# Title {#top .a}\n\nsome *text*\nmore $x$\n\n$$y$$\n\n> quote\n\n- [x] done\n- [ ] todo\n\n:::note Hi\nbody[^1]\n:::\n\n```rs {.n}\nfn a() {}\n```\n\n[^1]: note\n";
+    let mut parser = StatefulParser::with_renderer(input.as_bytes(), MdastWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert!(out.starts_with(r#"{"type":"root","children":[{"type":"heading","depth":1,"data":{"hProperties":{"id":"top","className":["a"]}},"children":[{"type":"text","value":"Title"}],"position":{"start":{"line":1,"column":1,"offset":0},"end":{"line":1,"column":18,"offset":17}}}"#));
+    assert!(out.contains(r#"{"type":"paragraph","children":[{"type":"text","value":"some *text*\nmore "},{"type":"inlineMath","value":"x","#));
+    assert!(out.contains(r#"{"type":"math","value":"y","#));
+    assert!(out.contains(r#"{"type":"blockquote","children":[{"type":"paragraph","children":[{"type":"text","value":"quote"}]}]"#));
+    assert!(out.contains(r#"{"type":"list","ordered":false,"start":null,"spread":false,"children":[{"type":"listItem","spread":false,"checked":true,"children":[{"type":"paragraph","children":[{"type":"text","value":"done"}]}]"#));
+    assert!(out.contains(r#"{"type":"listItem","spread":false,"checked":false,"#));
+    assert!(out.contains(r#"{"type":"containerDirective","name":"note","attributes":{},"children":[{"type":"paragraph","data":{"directiveLabel":true},"children":[{"type":"text","value":"Hi"}]},{"type":"paragraph","children":[{"type":"text","value":"body"},{"type":"footnoteReference","identifier":"1","label":"1","#));
+    assert!(out.contains(r#"{"type":"code","lang":"rs","meta":"{.n}","value":"fn a() {}","#));
+    assert!(out.contains(r#"{"type":"footnoteDefinition","identifier":"1","label":"1","children":[{"type":"paragraph","children":[{"type":"text","value":"note"}]}]"#));
+    assert!(out.contains(r#"{"type":"inlineMath","value":"x","position":{"start":{"line":4,"column":6,"offset":36},"end":{"line":4,"column":9,"offset":39}}}"#));
+    assert!(out.contains(r#""checked":false,"children":[{"type":"paragraph","children":[{"type":"text","value":"todo"}]}],"position":{"start":{"line":11,"column":1,"offset":68},"end":{"line":11,"column":11,"offset":78}}}],"position":{"start":{"line":10,"column":1,"offset":57},"end":{"line":11,"column":11,"offset":78}}}"#));
+    assert!(out.contains(r#""value":"fn a() {}","position":{"start":{"line":17,"column":1,"offset":105},"end":{"line":19,"column":4,"offset":129}}}"#));
+    assert!(out.ends_with("]}\n"));
+
+    // 指令的内容单独解析，没有offset
+    let mut parser = StatefulParser::with_renderer(
+        "a\n\n:::foo x=1\nbody\n:::\n".as_bytes(),
+        MdastWriter::new(Vec::new()),
+    );
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+    assert!(out.contains(r#""children":[{"type":"text","value":"body"}],"position":{"start":{"line":4,"column":1},"end":{"line":4,"column":5}}}],"position":{"start":{"line":3,"column":1,"offset":3},"end":{"line":5,"column":4,"offset":22}}}"#));
+}
@@ -0,0 +1,530 @@
+use crate::attr::Attrs;
+use crate::json::Json;
+use crate::parser::Parser;
+use crate::parser::StatefulParser;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+// pandoc 3.x 的 pandoc-types 版本
+pub const PANDOC_API_VERSION: [i64; 3] = [1, 23, 1];
+
+// 输出pandoc的json格式，可以接着 pandoc -f json 转成docx、epub
+// 任务列表是BulletList，开头是 ☐ / ☒，和pandoc的task_lists一样
+// 容器和指令是Div，脚注的定义放到引用的位置变成Note
+pub struct PandocWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    stack: Vec<Open>,
+    // 脚注的label和内容，文档结束时替换掉引用
+    footnotes: Vec<Footnote>,
+}
+
+// (label, 块)
+type Footnote = (Vec<u8>, Vec<Json>);
+
+struct Open {
+    kind: Kind,
+    children: Vec<Json>,
+    // 结束这个节点的块，隐式的段落、列表和链接是None
+    tag: Option<Tag>,
+}
+
+enum Kind {
+    Root,
+    Para,
+    Header(u8, Json),
+    BlockQuote,
+    BulletList,
+    Item(bool),
+    Footnote(Vec<u8>),
+    Div(Json),
+    Link(Json, Json),
+}
+
+impl<W: Write> PandocWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            stack: vec![Open {
+                kind: Kind::Root,
+                children: Vec::new(),
+                tag: None,
+            }],
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn top(&self) -> &Kind {
+        &self.stack.last().unwrap().kind
+    }
+
+    fn push(&mut self, kind: Kind, tag: Option<Tag>) {
+        self.stack.push(Open {
+            kind,
+            children: Vec::new(),
+            tag,
+        });
+    }
+
+    fn add(&mut self, child: Json) {
+        self.stack.last_mut().unwrap().children.push(child);
+    }
+
+    fn pop(&mut self) {
+        if self.stack.len() == 1 {
+            return;
+        }
+        let open = self.stack.pop().unwrap();
+        let mut children = open.children;
+
+        let node = match open.kind {
+            Kind::Root => unreachable!(),
+            Kind::Para => {
+                trim_end(&mut children);
+                if children.is_empty() {
+                    return;
+                }
+                element("Para", Json::Array(children))
+            }
+            Kind::Header(level, attr) => {
+                trim_end(&mut children);
+                element(
+                    "Header",
+                    Json::Array(vec![
+                        Json::Number(level as i64),
+                        attr,
+                        Json::Array(children),
+                    ]),
+                )
+            }
+            Kind::BlockQuote => element("BlockQuote", Json::Array(children)),
+            Kind::BulletList => element("BulletList", Json::Array(children)),
+            Kind::Item(is_done) => {
+                // 第一个段落变成Plain，前面加上 ☐ / ☒
+                let check = Json::Array(vec![
+                    element("Str", Json::str(if is_done { "☒" } else { "☐" })),
+                    space(),
+                ]);
+                match children.first_mut() {
+                    Some(Json::Object(fields)) if fields[0].1 == Json::str("Para") => {
+                        fields[0].1 = Json::str("Plain");
+                        if let Some(Json::Array(inlines)) = fields.get_mut(1).map(|x| &mut x.1) {
+                            if let Json::Array(check) = check {
+                                inlines.splice(0..0, check);
+                            }
+                        }
+                    }
+                    _ => children.insert(0, element("Plain", check)),
+                }
+                Json::Array(children)
+            }
+            Kind::Footnote(label) => {
+                self.footnotes.push((label, children));
+                return;
+            }
+            Kind::Div(attr) => element("Div", Json::Array(vec![attr, Json::Array(children)])),
+            Kind::Link(attr, target) => element(
+                "Link",
+                Json::Array(vec![attr, Json::Array(children), target]),
+            ),
+        };
+        self.add(node);
+    }
+
+    fn close_until(&mut self, tag: Tag) {
+        if !self.stack.iter().any(|x| x.tag == Some(tag)) {
+            return;
+        }
+        while let Some(open) = self.stack.last() {
+            let done = open.tag == Some(tag);
+            self.pop();
+            if done {
+                break;
+            }
+        }
+    }
+
+    // 新的块开始之前，结束隐式的段落，keep_list为false时也结束任务列表
+    fn close_implicit(&mut self, keep_list: bool) {
+        while self.stack.len() > 1 && self.stack.last().unwrap().tag.is_none() {
+            match self.top() {
+                Kind::Para => self.pop(),
+                Kind::BulletList if !keep_list => self.pop(),
+                _ => break,
+            }
+        }
+    }
+
+    fn inline_parent(&mut self) {
+        if matches!(self.top(), Kind::BulletList) {
+            self.close_implicit(false);
+        }
+        if !matches!(self.top(), Kind::Para | Kind::Header(..) | Kind::Link(..)) {
+            self.push(Kind::Para, None);
+        }
+    }
+
+    // 按空白拆成Str和Space，连续的文本合并成一个Str
+    fn add_text(&mut self, s: &str) {
+        self.inline_parent();
+        let children = &mut self.stack.last_mut().unwrap().children;
+
+        for (idx, word) in s.split([' ', '\t']).enumerate() {
+            if idx > 0 && !children.is_empty() && children.last() != Some(&space()) {
+                children.push(space());
+            }
+            if word.is_empty() {
+                continue;
+            }
+            match children.last_mut() {
+                Some(Json::Object(fields)) if fields[0].1 == Json::str("Str") => {
+                    if let Json::String(value) = &mut fields[1].1 {
+                        value.push_str(word);
+                    }
+                }
+                _ => children.push(element("Str", Json::str(word))),
+            }
+        }
+    }
+
+    // 指令的内容当做markdown解析
+    fn parse_blocks(content: &[u8]) -> (Vec<Json>, Vec<Footnote>) {
+        let mut parser = StatefulParser::from_slice(content, PandocWriter::new(io::sink()));
+        if parser.parse_and_write().is_err() {
+            return (Vec::new(), Vec::new());
+        }
+        let mut writer = parser.into_renderer();
+        (
+            std::mem::take(&mut writer.stack[0].children),
+            std::mem::take(&mut writer.footnotes),
+        )
+    }
+}
+
+fn element(t: &str, c: Json) -> Json {
+    Json::object([("t", Json::str(t)), ("c", c)])
+}
+
+fn space() -> Json {
+    Json::object([("t", Json::str("Space"))])
+}
+
+fn soft_break() -> Json {
+    Json::object([("t", Json::str("SoftBreak"))])
+}
+
+// 去掉结尾的Space和SoftBreak
+fn trim_end(inlines: &mut Vec<Json>) {
+    while matches!(inlines.last(), Some(x) if *x == space() || *x == soft_break()) {
+        inlines.pop();
+    }
+}
+
+// [id, [class], [[key, value]]]
+fn attr(attrs: &Attrs) -> Json {
+    let mut id = Json::str("");
+    let mut classes = Vec::new();
+    let mut pairs = Vec::new();
+
+    for (key, value) in attrs.named() {
+        match key.as_str() {
+            "id" => id = Json::str(value.as_str()),
+            "class" => classes.extend(value.split_whitespace().map(Json::str)),
+            _ => pairs.push(Json::Array(vec![
+                Json::str(key.as_str()),
+                Json::str(value.as_str()),
+            ])),
+        }
+    }
+    for key in attrs.positional() {
+        pairs.push(Json::Array(vec![Json::str(key.as_str()), Json::str("")]));
+    }
+
+    Json::Array(vec![id, Json::Array(classes), Json::Array(pairs)])
+}
+
+fn target(url: &[u8], title: &[u8]) -> Json {
+    Json::Array(vec![Json::bytes(url), Json::bytes(title)])
+}
+
+// 把 Note 里的label换成脚注的内容，没有定义的脚注原样输出
+// expanding是正在展开的脚注，脚注里引用自己时也原样输出
+fn resolve_notes(json: &mut Json, footnotes: &[Footnote], expanding: &mut Vec<String>) {
+    match json {
+        Json::Object(fields) if fields[0].1 == Json::str("Note") => {
+            if let Json::String(label) = &fields[1].1 {
+                let label = label.clone();
+                *json = match footnotes.iter().find(|(x, _)| x == label.as_bytes()) {
+                    Some((_label, blocks)) if !expanding.contains(&label) => {
+                        let mut blocks = Json::Array(blocks.clone());
+                        expanding.push(label);
+                        resolve_notes(&mut blocks, footnotes, expanding);
+                        expanding.pop();
+                        element("Note", blocks)
+                    }
+                    _ => element("Str", Json::str(format!("[^{}]", label))),
+                };
+            }
+        }
+        Json::Object(fields) => {
+            for (_key, value) in fields.iter_mut() {
+                resolve_notes(value, footnotes, expanding);
+            }
+        }
+        Json::Array(items) => {
+            for item in items.iter_mut() {
+                resolve_notes(item, footnotes, expanding);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl<W: Write> Renderer for PandocWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        self.close_implicit(matches!(tag, Tag::TaskList(_)));
+
+        match tag {
+            Tag::Paragraph => self.push(Kind::Para, Some(tag)),
+            Tag::Header(level) => self.push(Kind::Header(level, attr(attrs)), Some(tag)),
+            Tag::BlockQuote => self.push(Kind::BlockQuote, Some(tag)),
+            Tag::TaskList(is_done) => {
+                if !matches!(self.top(), Kind::BulletList) {
+                    self.push(Kind::BulletList, None);
+                }
+                self.push(Kind::Item(is_done), Some(tag));
+            }
+            Tag::Footnote => self.push(Kind::Footnote(info.to_vec()), Some(tag)),
+            // 和pandoc读GitHub的alert一样：Div(class=kind) 里面第一个是 Div(class=title)
+            Tag::Admonition(kind) => {
+                let mut all = Attrs::new();
+                all.set("class", kind.name());
+                all.extend(attrs);
+                self.push(Kind::Div(attr(&all)), Some(tag));
+
+                let title = if info.is_empty() {
+                    kind.default_title().to_string()
+                } else {
+                    String::from_utf8_lossy(info).into_owned()
+                };
+                let mut title_attrs = Attrs::new();
+                title_attrs.set("class", "title");
+                self.push(Kind::Div(attr(&title_attrs)), None);
+                self.push(Kind::Para, None);
+                self.add_text(&title);
+                self.pop();
+                self.pop();
+            }
+            Tag::CodeBlock | Tag::None => {}
+        }
+        Ok(())
+    }
+
+    fn block_attrs(&mut self, attrs: &Attrs) -> io::Result<()> {
+        if let Some(Open {
+            kind: Kind::Header(_level, header_attr),
+            ..
+        }) = self.stack.last_mut()
+        {
+            *header_attr = attr(attrs);
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        self.close_until(tag);
+        Ok(())
+    }
+
+    fn inline_start(&mut self, inline: Inline, attrs: &Attrs) -> io::Result<()> {
+        self.inline_parent();
+        match inline {
+            Inline::Link { url, title } => {
+                self.push(Kind::Link(attr(attrs), target(url, title)), None)
+            }
+        }
+        Ok(())
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } if matches!(self.top(), Kind::Link(..)) => self.pop(),
+            Inline::Link { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.add_text(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.add_text(" ");
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.add_text(" ");
+        Ok(())
+    }
+
+    fn line_break(&mut self) -> io::Result<()> {
+        if matches!(self.top(), Kind::Para | Kind::Header(..)) {
+            let children = &mut self.stack.last_mut().unwrap().children;
+            trim_end(children);
+            if !children.is_empty() {
+                children.push(soft_break());
+            }
+        }
+        Ok(())
+    }
+
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.close_implicit(false);
+
+        let mut all = Attrs::new();
+        if !lang.is_empty() {
+            all.set("class", &String::from_utf8_lossy(lang));
+        }
+        all.extend(attrs);
+        let code = element(
+            "CodeBlock",
+            Json::Array(vec![attr(&all), Json::bytes(content)]),
+        );
+        self.add(code);
+        Ok(())
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], title: &[u8], attrs: &Attrs) -> io::Result<()> {
+        self.inline_parent();
+        let alt = String::from_utf8_lossy(alt);
+        let mut inlines = Vec::new();
+        for (idx, word) in alt.split_whitespace().enumerate() {
+            if idx > 0 {
+                inlines.push(space());
+            }
+            inlines.push(element("Str", Json::str(word)));
+        }
+        let image = element(
+            "Image",
+            Json::Array(vec![attr(attrs), Json::Array(inlines), target(src, title)]),
+        );
+        self.add(image);
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], display: bool, _pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        let math_type = Json::object([(
+            "t",
+            Json::str(if display { "DisplayMath" } else { "InlineMath" }),
+        )]);
+        self.add(element(
+            "Math",
+            Json::Array(vec![math_type, Json::bytes(tex)]),
+        ));
+        Ok(())
+    }
+
+    // 先放label，文档结束时换成脚注的内容
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        self.add(element("Note", Json::bytes(label)));
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &[u8], args: &[u8], content: &[u8], _pos: Pos) -> io::Result<()> {
+        self.close_implicit(false);
+
+        let mut attrs = Attrs::new();
+        attrs.set("class", &String::from_utf8_lossy(name));
+        attrs.extend(&Attrs::parse(args));
+        let (blocks, footnotes) = Self::parse_blocks(content);
+        self.footnotes.extend(footnotes);
+        self.add(element(
+            "Div",
+            Json::Array(vec![attr(&attrs), Json::Array(blocks)]),
+        ));
+        Ok(())
+    }
+
+    fn shortcode(&mut self, name: &[u8], args: &[u8], _pos: Pos) -> io::Result<()> {
+        self.inline_parent();
+        let mut attrs = Attrs::new();
+        attrs.set("class", &String::from_utf8_lossy(name));
+        attrs.extend(&Attrs::parse(args));
+        self.add(element(
+            "Span",
+            Json::Array(vec![attr(&attrs), Json::Array(Vec::new())]),
+        ));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        while self.stack.len() > 1 {
+            self.pop();
+        }
+
+        let mut blocks = Json::Array(std::mem::take(&mut self.stack[0].children));
+        resolve_notes(&mut blocks, &self.footnotes, &mut Vec::new());
+        let doc = Json::object([
+            (
+                "pandoc-api-version",
+                Json::Array(
+                    PANDOC_API_VERSION
+                        .iter()
+                        .map(|&x| Json::Number(x))
+                        .collect(),
+                ),
+            ),
+            ("meta", Json::Object(Vec::new())),
+            ("blocks", blocks),
+        ]);
+
+        doc.write(&mut self.buf_writer)?;
+        self.buf_writer.write_all(b"\n")?;
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    let input = "# Hello world {#top}\n\nsome text\nmore $x$[^1]\n\n> quote\n\n- [x] done\n- [ ] todo\n\n> [!NOTE]\n> careful\n\n```rs\nfn a() {}\n```\n\n[site](http://a.b \"T\")\n\n[^1]: the note\n";
+    let mut parser = StatefulParser::with_renderer(input.as_bytes(), PandocWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert!(out.starts_with(r#"{"pandoc-api-version":[1,23,1],"meta":{},"blocks":[{"t":"Header","c":[1,["top",[],[]],[{"t":"Str","c":"Hello"},{"t":"Space"},{"t":"Str","c":"world"}]]}"#));
+    assert!(out.contains(r#"{"t":"Para","c":[{"t":"Str","c":"some"},{"t":"Space"},{"t":"Str","c":"text"},{"t":"SoftBreak"},{"t":"Str","c":"more"},{"t":"Space"},{"t":"Math","c":[{"t":"InlineMath"},"x"]},{"t":"Note","c":[{"t":"Para","c":[{"t":"Str","c":"the"},{"t":"Space"},{"t":"Str","c":"note"}]}]}]}"#));
+    assert!(out.contains(r#"{"t":"BlockQuote","c":[{"t":"Para","c":[{"t":"Str","c":"quote"}]}]}"#));
+    assert!(out.contains(r#"{"t":"BulletList","c":[[{"t":"Plain","c":[{"t":"Str","c":"☒"},{"t":"Space"},{"t":"Str","c":"done"}]}],[{"t":"Plain","c":[{"t":"Str","c":"☐"},{"t":"Space"},{"t":"Str","c":"todo"}]}]]}"#));
+    assert!(out.contains(r#"{"t":"Div","c":[["",["note"],[]],[{"t":"Div","c":[["",["title"],[]],[{"t":"Para","c":[{"t":"Str","c":"Note"}]}]]},{"t":"Para","c":[{"t":"Str","c":"careful"}]}]]}"#));
+    assert!(out.contains(r#"{"t":"CodeBlock","c":[["",["rs"],[]],"fn a() {}"]}"#));
+    assert!(out
+        .contains(r#"{"t":"Link","c":[["",[],[]],[{"t":"Str","c":"site"}],["http://a.b","T"]]}"#));
+    assert!(out.ends_with("]}\n"));
+
+    // 脚注引用自己时，里面的引用原样输出
+    let input = "x[^1]\n\n[^1]: a[^1]\n";
+    let mut parser = StatefulParser::with_renderer(input.as_bytes(), PandocWriter::new(Vec::new()));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+    assert!(out.contains(
+        r#"{"t":"Note","c":[{"t":"Para","c":[{"t":"Str","c":"a"},{"t":"Str","c":"[^1]"}]}]}"#
+    ));
+}
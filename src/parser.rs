@@ -1,16 +1,36 @@
+use crate::attr::Attrs;
+use crate::directive::Directive;
 use crate::html_writer::HtmlWriter;
 use crate::mapper::Mapper;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
 use crate::schema::Schema;
+use crate::slice_tokenizer::SliceTokenizer;
+use crate::tag::AdmonitionKind;
 use crate::tag::Tag;
+use crate::tokenizer::chr_count;
 use crate::tokenizer::Token;
+use crate::tokenizer::TokenSource;
 use crate::tokenizer::Tokenizer;
 use crate::CowStr;
+use chr::InvalidByte;
+use chr::Utf8Policy;
 use std::fmt::Debug;
 use std::io;
 use std::io::Read;
+use std::ops::Range;
 
 pub trait Parser {
     fn parse_and_write(&mut self) -> Result<(), ParseError>;
+
+    // 不合法的utf8返回错误、换成U+FFFD或者原样保留，在parse_and_write之前设置
+    fn set_utf8_policy(&mut self, _policy: Utf8Policy) {}
+
+    // 不影响输出的问题，比如未定义的脚注，在parse_and_write之后取出
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        Vec::new()
+    }
 }
 
 #[derive(Debug)]
@@ -21,22 +41,44 @@ pub enum ParseError {
 
 pub struct SyntaxError {
     msg: CowStr,
-    line_num: u32,
-    column_num: u32,
+    pos: Pos,
+}
+
+pub struct Warning {
+    msg: CowStr,
+    pos: Pos,
 }
 
 struct Record {
     line_num: u32,
     column_num: u32,
+    // 当前token在输入里的byte范围
+    span: Range<u64>,
+    // 最后一个不是换行的字符，块的结尾位置
+    last: Pos,
+}
+
+pub struct StatefulParser<M, T> {
+    tokenizer: T,
+    context: Context<M>,
+    // tokenizer已经读到、还没有报告的不合法的byte，可能在下一个token里
+    invalid: Vec<InvalidByte>,
 }
 
-pub struct StatefulParser<'a, S, W, R: Read> {
-    tokenizer: Tokenizer<'a, R>,
-    mapper: Mapper<S, W>,
+// 和tokenizer分开，这样token借用tokenizer的时候也能修改
+pub(crate) struct Context<M> {
+    renderer: M,
     /* CONTEXT: */
     state: State,
     last_tag: Tag,
     record: Record,
+    // 连续的文本行组成一个段落，遇到空行或者其他的块结束
+    in_paragraph: bool,
+    // 还没闭合的 ::: 容器
+    containers: Vec<Tag>,
+    // 标题里还没输出的空白，后面还有内容才输出
+    header_blank: Vec<Token<'static>>,
+    warnings: Vec<Warning>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -55,6 +97,8 @@ impl Record {
         Self {
             line_num: 1,
             column_num: 0,
+            span: 0..0,
+            last: Pos::default(),
         }
     }
 
@@ -74,38 +118,188 @@ impl From<io::Error> for ParseError {
     }
 }
 impl SyntaxError {
-    fn new<S: Into<CowStr>>(msg: S, line_num: u32, column_num: u32) -> Self {
+    pub fn new<S: Into<CowStr>>(msg: S, pos: Pos) -> Self {
+        Self {
+            msg: msg.into(),
+            pos,
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn pos(&self) -> Pos {
+        self.pos
+    }
+}
+
+impl Warning {
+    pub fn new<S: Into<CowStr>>(msg: S, pos: Pos) -> Self {
         Self {
             msg: msg.into(),
-            line_num,
-            column_num,
+            pos,
         }
     }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn pos(&self) -> Pos {
+        self.pos
+    }
+}
+
+impl Debug for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!(
+            "[Warning] at line {} column {}, message: \"{}\"",
+            self.pos.line_num, self.pos.column_num, self.msg
+        ))
+    }
 }
 
 impl Debug for SyntaxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!(
             "[Syntax Error] at line {} column {}, message: \"{}\"",
-            self.line_num, self.column_num, self.msg
+            self.pos.line_num, self.pos.column_num, self.msg
         ))
     }
 }
 
-impl<'a, S: Schema, W: HtmlWriter, R: Read + 'a> StatefulParser<'a, S, W, R> {
+impl<'a, S: Schema, W: HtmlWriter, R: Read + 'a> StatefulParser<Mapper<S, W>, Tokenizer<'a, R>> {
     pub fn new(r: R, s: S, w: W) -> Self {
+        Self::with_renderer(r, Mapper::new(s, w))
+    }
+}
+
+impl<S: Schema, W: HtmlWriter, T> StatefulParser<Mapper<S, W>, T> {
+    // 注册 ::: name {attrs} 块级指令的处理器
+    pub fn register_directive<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.context.renderer.register_directive(name, d);
+    }
+
+    // 注册 {{< name args >}} 行内短代码的处理器
+    pub fn register_shortcode<D: Directive + 'static>(&mut self, name: &str, d: D) {
+        self.context.renderer.register_shortcode(name, d);
+    }
+}
+
+impl<'a, M: Renderer, R: Read + 'a> StatefulParser<M, Tokenizer<'a, R>> {
+    // 输出到html以外的格式
+    pub fn with_renderer(r: R, m: M) -> Self {
+        Self::with_tokenizer(Tokenizer::new(r), m)
+    }
+}
+
+impl<'a, M: Renderer> StatefulParser<M, SliceTokenizer<'a>> {
+    // 输入已经在内存里（或者mmap的文件），纯文本直接借用输入，不用一个字一个字地复制
+    pub fn from_slice(data: &'a [u8], m: M) -> Self {
+        Self::with_tokenizer(SliceTokenizer::new(data), m)
+    }
+}
+
+impl<M: Renderer, T: TokenSource> StatefulParser<M, T> {
+    pub fn with_tokenizer(tokenizer: T, m: M) -> Self {
+        Self {
+            tokenizer,
+            context: Context::new(m),
+            invalid: Vec::new(),
+        }
+    }
+
+    // end之前的不合法的byte作为警告，start是它所在的token开头的位置
+    fn report_invalid(&mut self, start: Pos, end: u64) {
+        self.invalid.extend(self.tokenizer.take_invalid());
+        let n = self.invalid.partition_point(|x| x.offset < end);
+        for invalid in self.invalid.drain(..n) {
+            let (line_num, column_num) = match self.tokenizer.locate(invalid.offset) {
+                Some((0, chars)) => (start.line_num, start.column_num + chars),
+                Some((lines, chars)) => (start.line_num + lines, chars + 1),
+                None => (start.line_num, start.column_num),
+            };
+            let pos = Pos::new(line_num, column_num, invalid.offset);
+            self.context
+                .warnings
+                .push(Warning::new(invalid.to_string(), pos));
+        }
+    }
+}
+
+impl<M, T> StatefulParser<M, T> {
+    pub fn renderer(&self) -> &M {
+        &self.context.renderer
+    }
+
+    pub fn into_renderer(self) -> M {
+        self.context.renderer
+    }
+}
+
+impl<M: Renderer> Context<M> {
+    pub(crate) fn new(m: M) -> Self {
         Self {
             record: Record::new(),
-            tokenizer: Tokenizer::new(r),
             state: State::Start,
             last_tag: Tag::None,
-            mapper: Mapper::new(s, w),
+            in_paragraph: false,
+            renderer: m,
+            containers: Vec::new(),
+            header_blank: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
+    pub(crate) fn renderer_mut(&mut self) -> &mut M {
+        &mut self.renderer
+    }
+
+    pub(crate) fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    // 当前token开头的位置，char_len是0的时候是当前token结尾的位置
+    fn pos(&self, char_len: u32) -> Pos {
+        let offset = if char_len == 0 {
+            self.record.span.end
+        } else {
+            self.record.span.start
+        };
+        Pos::new(
+            self.record.line_num,
+            self.record.column_num + 1 - char_len,
+            offset,
+        )
+    }
+
+    // 已经把换行读掉了的块级token，开头在lines_back行之前的行首
+    fn line_start_pos(&self, lines_back: u32) -> Pos {
+        Pos::new(self.record.line_num - lines_back, 1, self.record.span.start)
+    }
+
+    // 代码块和指令读完之后，结尾在上一行；结尾的栅栏有多长不知道，和mdast一样按三个字符算
+    // offset不算读掉的换行
+    fn fenced_end(&self, content: &[u8], closed: bool) -> Pos {
+        let line_num = self.record.line_num - 1;
+        let column_num = match content.rsplit(|&b| b == b'\n').next() {
+            Some(last) if !closed && !content.is_empty() => chr_count(last).max(1),
+            _ => 3,
+        };
+        Pos::new(line_num, column_num, self.record.span.end.saturating_sub(1))
+    }
+
+    // 先告诉renderer块的最后一个字符在哪里
+    fn end_block(&mut self, tag: Tag, pos: Pos) -> io::Result<()> {
+        self.header_blank.clear();
+        self.renderer.block_last_pos(self.record.last)?;
+        self.renderer.block_end(tag, pos)
+    }
+
     fn try_solve_header_end(&mut self) -> io::Result<bool> {
         if let Tag::Header(level) = self.last_tag {
-            self.mapper.write_header_end(level)?;
+            self.end_block(Tag::Header(level), self.pos(0))?;
             self.state = State::Ln;
             self.last_tag = Tag::None;
             Ok(true)
@@ -114,128 +308,486 @@ impl<'a, S: Schema, W: HtmlWriter, R: Read + 'a> StatefulParser<'a, S, W, R> {
         }
     }
 
-    fn solve_end_and_start(&mut self, tag: Tag) -> io::Result<()> {
+    fn solve_end_and_start(&mut self, tag: Tag, char_len: u32) -> io::Result<()> {
         self.solve_end()?;
-        self.solve_start(tag)
+        self.solve_start(tag, char_len)
     }
 
-    fn solve_start(&mut self, tag: Tag) -> io::Result<()> {
-        self.mapper.write_tag_start(tag)?;
+    fn solve_start(&mut self, tag: Tag, char_len: u32) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+        self.renderer
+            .block_start(tag, &[], &Attrs::new(), self.pos(char_len))?;
         self.state = State::Tag(tag);
         self.last_tag = tag;
         Ok(())
     }
 
+    fn solve_paragraph_start(&mut self, char_len: u32) -> io::Result<()> {
+        if !self.in_paragraph {
+            self.renderer
+                .block_start(Tag::Paragraph, &[], &Attrs::new(), self.pos(char_len))?;
+            self.in_paragraph = true;
+        }
+        Ok(())
+    }
+
+    fn solve_paragraph_end(&mut self) -> io::Result<()> {
+        if self.in_paragraph {
+            self.end_block(Tag::Paragraph, self.pos(0))?;
+            self.in_paragraph = false;
+        }
+        Ok(())
+    }
+
+    fn solve_footnote_start(&mut self, label: &[u8], char_len: u32) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+        self.renderer
+            .block_start(Tag::Footnote, label, &Attrs::new(), self.pos(char_len))?;
+        self.state = State::Tag(Tag::Footnote);
+        self.last_tag = Tag::Footnote;
+        Ok(())
+    }
+
+    fn write_math(&mut self, data: &[u8], display: bool, char_len: u32) -> io::Result<()> {
+        let pos = self.pos(char_len);
+
+        // 跨行的块级公式
+        if let Some(last_ln) = data.iter().rposition(|&b| b == b'\n') {
+            self.record.line_num += data.iter().filter(|&&b| b == b'\n').count() as u32;
+            self.record.column_num = (data.len() - last_ln - 1) as u32 + 2;
+        }
+        self.renderer.block_last_pos(self.token_last_pos())?;
+        self.renderer.math(data, display, pos)
+    }
+
+    // 当前token最后一个字符的位置
+    fn token_last_pos(&self) -> Pos {
+        Pos::new(
+            self.record.line_num,
+            self.record.column_num,
+            self.record.span.end,
+        )
+    }
+
+    // ::: 容器里面可以有其他的块，> [!NOTE] 和引用一样，遇到空行结束
+    fn solve_admonition_start(
+        &mut self,
+        kind: AdmonitionKind,
+        title: &[u8],
+        attrs: &[u8],
+        fenced: bool,
+    ) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+        // 两种写法的token都已经把这一行的换行读掉了
+        let pos = self.line_start_pos(1);
+        // 没有内容的时候结尾就是开头这一行，> [!NOTE] 的长度是固定的
+        self.record.last = if fenced {
+            pos
+        } else {
+            let column_num = kind.name().len() as u32 + 5;
+            Pos::new(pos.line_num, column_num, pos.offset + column_num as u64)
+        };
+        self.renderer
+            .block_start(Tag::Admonition(kind), title, &Attrs::parse(attrs), pos)?;
+
+        if fenced {
+            self.containers.push(Tag::Admonition(kind));
+            self.state = State::Ln;
+        } else {
+            self.last_tag = Tag::Admonition(kind);
+            self.state = State::BlockValueLn;
+        }
+        Ok(())
+    }
+
+    fn solve_container_end(&mut self) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+        match self.containers.pop() {
+            Some(tag) => {
+                self.record.last = self.fenced_end(&[], true);
+                self.end_block(tag, self.line_start_pos(1))?;
+            }
+            None => self.warnings.push(Warning::new(
+                "unmatched closing :::",
+                self.line_start_pos(1),
+            )),
+        }
+
+        self.state = State::Ln;
+        Ok(())
+    }
+
+    fn solve_directive(
+        &mut self,
+        name: &[u8],
+        args: &[u8],
+        content: &[u8],
+        closed: bool,
+    ) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+
+        // 指令的token已经把结尾的换行读掉了
+        let pos = self.line_start_pos(1);
+        if !closed {
+            self.warnings.push(Warning::new(
+                format!("unclosed directive \"{}\"", String::from_utf8_lossy(name)),
+                pos,
+            ));
+        }
+
+        // 内容和结尾的 ::: 占的行数
+        if !content.is_empty() {
+            self.record.line_num += content.iter().filter(|&&b| b == b'\n').count() as u32 + 1;
+        }
+        if closed {
+            self.record.line_num += 1;
+        }
+        self.record.last = self.fenced_end(content, closed);
+
+        self.renderer.block_last_pos(self.record.last)?;
+        self.renderer.directive(name, args, content, pos)?;
+        self.state = State::Ln;
+        Ok(())
+    }
+
+    fn solve_code_block(
+        &mut self,
+        lang: &[u8],
+        attrs: &[u8],
+        content: &[u8],
+        closed: bool,
+    ) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+
+        // 代码块的token已经把开头这一行的换行读掉了
+        let pos = self.line_start_pos(1);
+        if !closed {
+            self.warnings.push(Warning::new("unclosed code block", pos));
+        }
+
+        if !content.is_empty() {
+            self.record.line_num += content.iter().filter(|&&b| b == b'\n').count() as u32 + 1;
+        }
+        if closed {
+            self.record.line_num += 1;
+        }
+        self.record.last = self.fenced_end(content, closed);
+
+        self.renderer.block_last_pos(self.record.last)?;
+        self.renderer
+            .code_block(lang, content, &Attrs::parse(attrs), pos)?;
+        self.state = State::Ln;
+        Ok(())
+    }
+
+    // 各个状态里都一样处理的行内token
+    fn write_inline(&mut self, token: Token) -> io::Result<()> {
+        let char_len = token.char_len();
+
+        // 标题结尾和属性列表前面的空白不输出
+        if let Tag::Header(_level) = self.last_tag {
+            match token {
+                Token::Space | Token::Tab => {
+                    self.header_blank.push(if token == Token::Tab {
+                        Token::Tab
+                    } else {
+                        Token::Space
+                    });
+                    return Ok(());
+                }
+                Token::Attrs { .. } => self.header_blank.clear(),
+                _ => {
+                    for blank in std::mem::take(&mut self.header_blank) {
+                        match blank {
+                            Token::Tab => self.renderer.tab()?,
+                            _ => self.renderer.space()?,
+                        }
+                    }
+                }
+            }
+        }
+
+        match token {
+            Token::Space => self.renderer.space(),
+            Token::Tab => self.renderer.tab(),
+            Token::PureText { data, .. } => self.renderer.text(data),
+            Token::FootnoteRef { label } => {
+                self.renderer.block_last_pos(self.token_last_pos())?;
+                self.renderer.footnote_ref(label, self.pos(char_len))
+            }
+            Token::Math { data, display } => self.write_math(data, display, char_len),
+            Token::Shortcode { name, args } => {
+                self.renderer.block_last_pos(self.token_last_pos())?;
+                self.renderer.shortcode(name, args, self.pos(char_len))
+            }
+            // 标题结尾的属性列表合并到标题的标签里，其他地方的原样输出
+            Token::Attrs { data } => match self.last_tag {
+                Tag::Header(_level) => self.renderer.block_attrs(&Attrs::parse(data)),
+                _ => self.renderer.text(data),
+            },
+            Token::Link {
+                text,
+                url,
+                title,
+                attrs,
+                image: true,
+            } => self.renderer.image(url, text, title, &Attrs::parse(attrs)),
+            Token::Link {
+                text,
+                url,
+                title,
+                attrs,
+                image: false,
+            } => {
+                let link = Inline::Link { url, title };
+                self.renderer.inline_start(link, &Attrs::parse(attrs))?;
+                self.renderer.text(text)?;
+                self.renderer.inline_end(link)
+            }
+            Token::Ln
+            | Token::Tag(_)
+            | Token::FootnoteDef { .. }
+            | Token::Admonition { .. }
+            | Token::ContainerEnd
+            | Token::Directive { .. }
+            | Token::CodeBlock { .. } => unreachable!(),
+        }
+    }
+
     fn solve_end(&mut self) -> io::Result<()> {
-        self.mapper.write_tag_end(self.last_tag)?;
+        if self.last_tag != Tag::None {
+            self.end_block(self.last_tag, self.pos(0))?;
+        }
         self.last_tag = Tag::None;
         Ok(())
     }
-}
 
-impl<'a, S: Schema, W: HtmlWriter, R: Read + 'a> Parser for StatefulParser<'a, S, W, R> {
-    fn parse_and_write(&mut self) -> Result<(), ParseError> {
-        loop {
-            match self.tokenizer.next() {
-                Some(res) => match res {
-                    Ok(token) => {
-                        // println!("token: {:?} state: {:?}", token, self.state);
-                        self.record.update_nums(token.char_len());
-
-                        match self.state {
-                            State::Start | State::Ln => match token {
-                                Token::Space => {
-                                    self.mapper.write_html_space()?;
-                                    self.state = State::PureText;
-                                }
-                                Token::Tab => {
-                                    self.mapper.write_html_tab()?;
-                                    self.state = State::PureText;
-                                }
-                                Token::Ln => {
-                                    self.mapper.write_br()?;
-                                    self.state = State::Ln;
-                                }
-                                Token::Tag(tag) => {
-                                    self.solve_start(tag)?;
-                                }
-                                Token::PureText {
-                                    data,
-                                    char_len: _char_len,
-                                } => {
-                                    self.mapper.write(data)?;
-                                    self.state = State::PureText;
-                                }
-                            },
-                            State::PureText => match token {
-                                Token::Space => {
-                                    self.mapper.write_html_space()?;
-                                }
-                                Token::Tab => {
-                                    self.mapper.write_html_tab()?;
-                                }
-                                Token::Ln => {
-                                    self.mapper.write_br()?;
-                                    self.state = State::Ln;
-                                }
-                                Token::Tag(tag) => unreachable!(),
-                                Token::PureText { data, char_len } => {
-                                    self.mapper.write(data)?;
-                                }
-                            },
-                            State::Tag(_) | State::Value => match token {
-                                Token::Space => {
-                                    self.mapper.write_html_space()?;
-                                    self.state = State::Value;
-                                }
-                                Token::Tab => {
-                                    self.mapper.write_html_tab()?;
-                                    self.state = State::Value;
-                                }
-                                Token::Ln => {
-                                    let is_header = self.try_solve_header_end()?;
-
-                                    if !is_header {
-                                        self.mapper.write_br()?;
-                                        self.state = State::BlockValueLn;
-                                    }
-                                }
-                                Token::Tag(_) => unreachable!(),
-                                Token::PureText { data, char_len } => {
-                                    self.mapper.write(data)?;
-                                    self.state = State::Value;
-                                }
-                            },
-
-                            State::BlockValueLn => match token {
-                                Token::Space => {
-                                    self.mapper.write_html_space()?;
-                                }
-                                Token::Tab => {
-                                    self.mapper.write_html_tab()?;
-                                }
-                                Token::Ln => {
-                                    self.solve_end()?;
-                                    self.state = State::Ln;
-                                }
-                                Token::Tag(tag) => {
-                                    self.solve_end_and_start(tag)?;
-                                }
-                                Token::PureText { data, char_len } => {
-                                    self.mapper.write(data)?;
-                                    self.state = State::Value;
-                                }
-                            },
-                        }
+    pub(crate) fn solve_token(&mut self, token: Token, span: Range<u64>) -> io::Result<()> {
+        // println!("token: {:?} state: {:?}", token, self.state);
+        let char_len = token.char_len();
+        self.record.update_nums(char_len);
+        self.record.span = span;
+
+        match self.state {
+            State::Start | State::Ln => match token {
+                Token::Ln => {
+                    // 空行结束段落
+                    self.solve_paragraph_end()?;
+                    self.renderer.line_break()?;
+                    self.state = State::Ln;
+                }
+                Token::Tag(tag) => {
+                    self.solve_start(tag, char_len)?;
+                }
+                Token::FootnoteDef { label, .. } => {
+                    self.solve_footnote_start(label, char_len)?;
+                }
+                Token::Admonition {
+                    kind,
+                    title,
+                    attrs,
+                    fenced,
+                } => {
+                    self.solve_admonition_start(kind, title, attrs, fenced)?;
+                }
+                Token::ContainerEnd => {
+                    self.solve_container_end()?;
+                }
+                Token::Directive {
+                    name,
+                    args,
+                    content,
+                    closed,
+                } => {
+                    self.solve_directive(name, args, content, closed)?;
+                }
+                Token::CodeBlock {
+                    lang,
+                    attrs,
+                    content,
+                    closed,
+                } => {
+                    self.solve_code_block(lang, attrs, content, closed)?;
+                }
+                _ => {
+                    self.solve_paragraph_start(char_len)?;
+                    self.write_inline(token)?;
+                    self.state = State::PureText;
+                }
+            },
+            State::PureText => match token {
+                Token::Ln => {
+                    self.renderer.line_break()?;
+                    self.state = State::Ln;
+                }
+                _ => self.write_inline(token)?,
+            },
+            State::Tag(_) | State::Value => match token {
+                Token::Ln => {
+                    let is_header = self.try_solve_header_end()?;
+
+                    if !is_header {
+                        self.renderer.line_break()?;
+                        self.state = State::BlockValueLn;
                     }
-                    Err(e) => return Err(ParseError::IoError(e)),
-                },
-                None => {
+                }
+                _ => {
+                    self.write_inline(token)?;
+                    self.state = State::Value;
+                }
+            },
+
+            State::BlockValueLn => match token {
+                Token::Space => {
+                    self.renderer.space()?;
+                }
+                Token::Tab => {
+                    self.renderer.tab()?;
+                }
+                Token::Ln => {
                     self.solve_end()?;
-                    self.mapper.write_css()?;
-                    return Ok(());
+                    self.state = State::Ln;
                 }
-            }
+                Token::Tag(Tag::BlockQuote)
+                    if matches!(self.last_tag, Tag::BlockQuote | Tag::Admonition(_)) =>
+                {
+                    // 连续的 > 属于同一个引用
+                    self.state = State::Value;
+                }
+                Token::Tag(tag) => {
+                    self.solve_end_and_start(tag, char_len)?;
+                }
+                Token::FootnoteDef { label, .. } => {
+                    self.solve_end()?;
+                    self.solve_footnote_start(label, char_len)?;
+                }
+                Token::Admonition {
+                    kind,
+                    title,
+                    attrs,
+                    fenced,
+                } => {
+                    self.solve_end()?;
+                    self.solve_admonition_start(kind, title, attrs, fenced)?;
+                }
+                Token::ContainerEnd => {
+                    self.solve_end()?;
+                    self.solve_container_end()?;
+                }
+                Token::Directive {
+                    name,
+                    args,
+                    content,
+                    closed,
+                } => {
+                    self.solve_end()?;
+                    self.solve_directive(name, args, content, closed)?;
+                }
+                Token::CodeBlock {
+                    lang,
+                    attrs,
+                    content,
+                    closed,
+                } => {
+                    self.solve_end()?;
+                    self.solve_code_block(lang, attrs, content, closed)?;
+                }
+                _ => {
+                    self.write_inline(token)?;
+                    self.state = State::Value;
+                }
+            },
+        }
+
+        // 跨行的公式会改掉行号和列号，所以放在最后
+        if char_len > 0 {
+            self.record.last = self.token_last_pos();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn solve_eof(&mut self) -> io::Result<()> {
+        self.close_all()?;
+        self.renderer.finish()?;
+        self.warnings.extend(self.renderer.take_warnings());
+        Ok(())
+    }
+
+    // 结尾还没有结束的块都结束掉，不输出脚注这些延后的内容
+    pub(crate) fn close_all(&mut self) -> io::Result<()> {
+        self.close_blocks()?;
+        while let Some(tag) = self.containers.pop() {
+            self.warnings.push(Warning::new(
+                "unclosed ::: container",
+                Pos::new(
+                    self.record.line_num,
+                    self.record.column_num,
+                    self.record.span.end,
+                ),
+            ));
+            self.end_block(tag, self.pos(0))?;
         }
+        Ok(())
+    }
+
+    // 容器外面的段落、引用这些在下一个块开始之前结束
+    pub(crate) fn close_blocks(&mut self) -> io::Result<()> {
+        self.solve_paragraph_end()?;
+        self.solve_end()?;
+        self.state = State::Ln;
+        Ok(())
+    }
+
+    // 空行或者标题后面，什么都没有打开，从这里用新的Context解析结果也一样
+    pub(crate) fn is_clean(&self) -> bool {
+        self.state == State::Ln
+            && !self.in_paragraph
+            && self.last_tag == Tag::None
+            && self.containers.is_empty()
+    }
+
+    // 在行首，而且不在 ::: 容器里，下一个标题可以结束前面的块重新开始
+    pub(crate) fn at_line_start(&self) -> bool {
+        matches!(self.state, State::Start | State::Ln | State::BlockValueLn)
+            && self.containers.is_empty()
+    }
+
+    // 从第line_num行的行首重新开始
+    pub(crate) fn restart(&mut self, line_num: u32) {
+        self.record = Record::new();
+        self.record.line_num = line_num;
+        self.state = State::Start;
+        self.last_tag = Tag::None;
+        self.in_paragraph = false;
+        self.containers.clear();
+        self.header_blank.clear();
+    }
+
+    pub(crate) fn line_num(&self) -> u32 {
+        self.record.line_num
+    }
+}
+
+impl<M: Renderer, T: TokenSource> Parser for StatefulParser<M, T> {
+    fn parse_and_write(&mut self) -> Result<(), ParseError> {
+        while let Some(res) = self.tokenizer.next_token() {
+            let start = self.context.pos(0);
+            let (token, span) = res?;
+            let end = span.end;
+            self.context.solve_token(token, span)?;
+            self.report_invalid(start, end);
+        }
+        self.report_invalid(self.context.pos(0), u64::MAX);
+        self.context.solve_eof()?;
+        Ok(())
+    }
+
+    // 默认原样保留，不管哪种，不合法的byte的位置都会作为警告报告
+    fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.tokenizer.set_utf8_policy(policy);
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        self.context.take_warnings()
     }
 }
@@ -0,0 +1,171 @@
+use crate::parser::Context;
+use crate::parser::Warning;
+use crate::renderer::Renderer;
+use crate::slice_tokenizer::BlockEnd;
+use crate::slice_tokenizer::SliceTokenizer;
+use crate::tokenizer::Token;
+use crate::tokenizer::TokenSource;
+use std::io;
+
+// 输出写在内存里的renderer，每次feed之后把已经写好的部分取走
+pub trait TakeOutput {
+    fn take_output(&mut self) -> Vec<u8>;
+}
+
+// 输入一块一块地推进来，不用阻塞的Read，在async里收到一块就feed一块：
+// while let Some(bytes) = body.next().await { send(parser.feed(&bytes)?).await }
+// 只解析到最后一个空行为止，后面的留到下次，空行前后的token互不影响
+pub struct PushParser<M> {
+    context: Context<M>,
+    // 还没有解析的输入
+    buf: Vec<u8>,
+    // buf开头在整个输入里的byte位置
+    offset: u64,
+    // 没有闭合的代码块或者指令：buf里还没检查过的第一行和检查到这里的状态
+    // 闭合之前不用重新切token，只检查新来的行
+    open_block: Option<(usize, BlockEnd)>,
+}
+
+impl<M: Renderer + TakeOutput> PushParser<M> {
+    pub fn new(m: M) -> Self {
+        Self {
+            context: Context::new(m),
+            buf: Vec::new(),
+            offset: 0,
+            open_block: None,
+        }
+    }
+
+    // 返回这次能输出的部分，可能是空的
+    pub fn feed(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let old_len = self.buf.len();
+        self.buf.extend_from_slice(data);
+
+        // 新来的数据里没有空行的话，可以解析的地方不会变
+        let tail = &self.buf[old_len.saturating_sub(2)..];
+        let has_blank_line =
+            tail.windows(2).any(|w| w == b"\n\n") || tail.windows(3).any(|w| w == b"\n\r\n");
+        if has_blank_line && self.block_closed() {
+            let end = self.last_blank_line();
+            if end > 0 {
+                self.solve(end)?;
+            }
+        }
+        Ok(self.context.renderer_mut().take_output())
+    }
+
+    // 输入结束，输出剩下的全部，包括脚注
+    pub fn finish(&mut self) -> io::Result<Vec<u8>> {
+        self.solve(self.buf.len())?;
+        self.context.solve_eof()?;
+        Ok(self.context.renderer_mut().take_output())
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.context.take_warnings()
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut M {
+        self.context.renderer_mut()
+    }
+
+    // 空行结尾的位置，后面还要有数据，不然不知道"\r"后面是不是"\n"
+    // 没有读完的代码块、指令会一直延伸到结尾，里面的空行不算
+    // 结尾没有闭合的块记在open_block里，位置是solve(end)之后的
+    fn last_blank_line(&mut self) -> usize {
+        let mut tokenizer = SliceTokenizer::new(&self.buf);
+        let mut last_ln = true;
+        let mut end = 0;
+        while let Some(Ok((token, span))) = tokenizer.next_token() {
+            let is_ln = matches!(token, Token::Ln);
+            if is_ln && last_ln && (span.end as usize) < self.buf.len() {
+                end = span.end as usize;
+            }
+            last_ln = is_ln;
+        }
+        self.open_block = tokenizer
+            .open_block()
+            .map(|(line_start, block_end)| (line_start - end, block_end));
+        end
+    }
+
+    // 没有闭合的块，检查新的完整的行里有没有它的结尾，没有的话不用重新切token
+    fn block_closed(&mut self) -> bool {
+        let (mut pos, mut block_end) = match self.open_block {
+            Some(x) => x,
+            None => return true,
+        };
+        while let Some(len) = self.buf[pos..]
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+        {
+            if block_end.is_end(&self.buf[pos..pos + len]) {
+                self.open_block = None;
+                return true;
+            }
+            pos += len + 1;
+            if self.buf[pos - 1] == b'\r' && self.buf.get(pos) == Some(&b'\n') {
+                pos += 1;
+            }
+        }
+        self.open_block = Some((pos, block_end));
+        false
+    }
+
+    fn solve(&mut self, end: usize) -> io::Result<()> {
+        let mut tokenizer = SliceTokenizer::new(&self.buf[..end]);
+        while let Some(res) = tokenizer.next_token() {
+            let (token, span) = res?;
+            let span = span.start + self.offset..span.end + self.offset;
+            self.context.solve_token(token, span)?;
+        }
+        self.buf.drain(..end);
+        self.offset += end as u64;
+        Ok(())
+    }
+}
+
+#[test]
+fn test() {
+    use crate::mapper::Mapper;
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::schema::DefaultSchema;
+
+    let readme = include_bytes!("../README.md");
+    let input =
+        b"# T\n\n```\na\n\nb\n```\n\n:::tip\nx\n\n> y [^1]\n:::\n\r\n$$\na\n\n$$\n\n[^1]: note\n";
+    let nested = b"::: outer\r\na\r\n\r\n::: inner\n\n:::\n\n~~~~\n\n~~~\n\n:::\r\rb\n\n````x\n\n```\n````\n\nc\n";
+    for input in [&readme[..], &input[..], &nested[..]] {
+        let mut parser = StatefulParser::from_slice(input, Mapper::new(DefaultSchema, Vec::new()));
+        parser.parse_and_write().unwrap();
+        let warnings = format!("{:?}", parser.take_warnings());
+        let html = parser.into_renderer().take_output();
+
+        for size in [1, 2, 3, 7, 64, input.len()] {
+            let mut push = PushParser::new(Mapper::new(DefaultSchema, Vec::new()));
+            let mut out = Vec::new();
+            let mut chunks = 0;
+            for chunk in input.chunks(size) {
+                let part = push.feed(chunk).unwrap();
+                chunks += !part.is_empty() as usize;
+                out.extend(part);
+            }
+            out.extend(push.finish().unwrap());
+            assert_eq!(html, out);
+            assert_eq!(warnings, format!("{:?}", push.take_warnings()));
+            // 一块一块地输出，不是等到最后
+            assert!(size == input.len() || chunks > 0);
+        }
+    }
+
+    // 没有闭合的代码块里的空行不会让前面的行重新切token
+    let mut push = PushParser::new(Mapper::new(DefaultSchema, Vec::new()));
+    assert!(push.feed(b"a\n\n```\n").unwrap().starts_with(b"a"));
+    for _ in 0..100 {
+        assert!(push.feed(b"x\n\n").unwrap().is_empty());
+        assert_eq!(push.open_block.unwrap().0, push.buf.len());
+    }
+    assert!(!push.feed(b"```\n\n\nz").unwrap().is_empty());
+    assert!(push.open_block.is_none());
+}
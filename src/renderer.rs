@@ -0,0 +1,115 @@
+use crate::attr::Attrs;
+use crate::parser::Warning;
+use crate::tag::Tag;
+use std::io;
+
+// 元素在源文件里的位置，用于警告和对应回源文件
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Pos {
+    pub line_num: u32,
+    pub column_num: u32,
+    // 在输入里的byte位置
+    pub offset: u64,
+}
+
+impl Pos {
+    pub fn new(line_num: u32, column_num: u32, offset: u64) -> Self {
+        Self {
+            line_num,
+            column_num,
+            offset,
+        }
+    }
+}
+
+// 行内元素，start和end之间是它的文本
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Inline<'a> {
+    Link { url: &'a [u8], title: &'a [u8] },
+}
+
+// 和输出格式无关的渲染器，parser只通过它输出
+// Mapper<S, HtmlWriterImpl<W>> 是html的实现
+pub trait Renderer {
+    // info：容器的标题、脚注的label，其他的块为空
+    fn block_start(&mut self, tag: Tag, info: &[u8], attrs: &Attrs, pos: Pos) -> io::Result<()>;
+
+    // 标题结尾的 {#id .class key=value}，在block_end之前调用
+    fn block_attrs(&mut self, _attrs: &Attrs) -> io::Result<()> {
+        Ok(())
+    }
+
+    // 块的最后一个字符的位置（cmark的sourcepos），在block_end、code_block、directive
+    // 和公式、脚注引用、短代码之前调用
+    fn block_last_pos(&mut self, _pos: Pos) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, pos: Pos) -> io::Result<()>;
+
+    fn inline_start(&mut self, inline: Inline, attrs: &Attrs) -> io::Result<()>;
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()>;
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()>;
+
+    fn space(&mut self) -> io::Result<()>;
+
+    fn tab(&mut self) -> io::Result<()>;
+
+    // 源文件里的换行，段落里的和空行都会调用
+    fn line_break(&mut self) -> io::Result<()>;
+
+    // content是原始的内容，没有转义
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        attrs: &Attrs,
+        pos: Pos,
+    ) -> io::Result<()>;
+
+    fn image(&mut self, src: &[u8], alt: &[u8], title: &[u8], attrs: &Attrs) -> io::Result<()>;
+
+    fn math(&mut self, tex: &[u8], display: bool, pos: Pos) -> io::Result<()>;
+
+    fn footnote_ref(&mut self, label: &[u8], pos: Pos) -> io::Result<()>;
+
+    // 默认把源码原样当做文本输出
+    fn directive(&mut self, name: &[u8], args: &[u8], content: &[u8], _pos: Pos) -> io::Result<()> {
+        self.text(b":::")?;
+        self.text(name)?;
+        if !args.is_empty() {
+            self.space()?;
+            self.text(args)?;
+        }
+        self.line_break()?;
+        if !content.is_empty() {
+            for line in content.split(|&b| b == b'\n') {
+                self.text(line)?;
+                self.line_break()?;
+            }
+        }
+        self.text(b":::")?;
+        self.line_break()
+    }
+
+    fn shortcode(&mut self, name: &[u8], args: &[u8], _pos: Pos) -> io::Result<()> {
+        self.text(b"{{< ")?;
+        self.text(name)?;
+        if !args.is_empty() {
+            self.space()?;
+            self.text(args)?;
+        }
+        self.text(b" >}}")
+    }
+
+    // 文档结束，输出延后的内容，比如脚注
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn take_warnings(&mut self) -> Vec<Warning> {
+        Vec::new()
+    }
+}
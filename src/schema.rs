@@ -1,3 +1,6 @@
+use crate::attr::Attrs;
+use std::borrow::Cow;
+
 pub trait Schema {
     fn h1_start(&self) -> &str;
     fn h1_end(&self) -> &str;
@@ -19,6 +22,29 @@ pub trait Schema {
     fn task_list_todo_end(&self) -> &str;
     fn code_block_start(&self) -> &str;
     fn code_block_end(&self) -> &str;
+    fn footnote_ref_start(&self) -> &str;
+    fn footnote_ref_end(&self) -> &str;
+    fn footnotes_start(&self) -> &str;
+    fn footnotes_end(&self) -> &str;
+    fn math_inline_start(&self) -> &str;
+    fn math_inline_end(&self) -> &str;
+    fn math_display_start(&self) -> &str;
+    fn math_display_end(&self) -> &str;
+    fn admonition_note_start(&self) -> &str;
+    fn admonition_note_end(&self) -> &str;
+    fn admonition_tip_start(&self) -> &str;
+    fn admonition_tip_end(&self) -> &str;
+    fn admonition_important_start(&self) -> &str;
+    fn admonition_important_end(&self) -> &str;
+    fn admonition_warning_start(&self) -> &str;
+    fn admonition_warning_end(&self) -> &str;
+    fn admonition_caution_start(&self) -> &str;
+    fn admonition_caution_end(&self) -> &str;
+    fn admonition_title_start(&self) -> &str;
+    fn admonition_title_end(&self) -> &str;
+    fn link_start(&self) -> &str;
+    fn link_end(&self) -> &str;
+    fn image(&self) -> &str;
 
     fn h1_css(&self) -> &str;
     fn h2_css(&self) -> &str;
@@ -30,6 +56,23 @@ pub trait Schema {
     fn task_list_done_css(&self) -> &str;
     fn task_list_todo_css(&self) -> &str;
     fn code_block_css(&self) -> &str;
+    fn footnotes_css(&self) -> &str;
+    // 所有种类共用的样式，用到任意一种时输出
+    fn admonition_css(&self) -> &str;
+    fn admonition_note_css(&self) -> &str;
+    fn admonition_tip_css(&self) -> &str;
+    fn admonition_important_css(&self) -> &str;
+    fn admonition_warning_css(&self) -> &str;
+    fn admonition_caution_css(&self) -> &str;
+
+    // 把 {#id .class key=value} 合并到上面的开始标签里
+    fn merge_attrs<'s>(&self, start: &'s str, attrs: &Attrs) -> Cow<'s, str> {
+        if attrs.is_empty() {
+            Cow::Borrowed(start)
+        } else {
+            Cow::Owned(attrs.merge_into(start))
+        }
+    }
 
     fn css_tag_start(&self) -> &str {
         "<style>"
@@ -183,9 +226,176 @@ impl Schema for DefaultSchema {
         "</div>"
     }
 
+    fn link_start(&self) -> &str {
+        "<a>"
+    }
+
+    fn link_end(&self) -> &str {
+        "</a>"
+    }
+
+    fn image(&self) -> &str {
+        "<img>"
+    }
+
     fn code_block_css(&self) -> &str {
         r#".code-block {
-			background-color: #999999
+			background-color: #999999;
+			white-space: pre
+		}"#
+    }
+
+    fn footnote_ref_start(&self) -> &str {
+        r#"<sup class="footnote-ref">"#
+    }
+
+    fn footnote_ref_end(&self) -> &str {
+        "</sup>"
+    }
+
+    fn footnotes_start(&self) -> &str {
+        r#"<section class="footnotes"><hr><ol>"#
+    }
+
+    fn footnotes_end(&self) -> &str {
+        "</ol></section>"
+    }
+
+    fn footnotes_css(&self) -> &str {
+        r#".footnotes {
+			font-size: 14px;
+			color: #666666
+		}
+
+		.footnote-backref {
+			text-decoration: none
+		}"#
+    }
+
+    fn math_inline_start(&self) -> &str {
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#
+    }
+
+    fn math_inline_end(&self) -> &str {
+        "</math>"
+    }
+
+    fn math_display_start(&self) -> &str {
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="block">"#
+    }
+
+    fn math_display_end(&self) -> &str {
+        "</math>"
+    }
+
+    fn admonition_note_start(&self) -> &str {
+        r#"<div class="admonition admonition-note">"#
+    }
+
+    fn admonition_note_end(&self) -> &str {
+        "</div>"
+    }
+
+    fn admonition_tip_start(&self) -> &str {
+        r#"<div class="admonition admonition-tip">"#
+    }
+
+    fn admonition_tip_end(&self) -> &str {
+        "</div>"
+    }
+
+    fn admonition_important_start(&self) -> &str {
+        r#"<div class="admonition admonition-important">"#
+    }
+
+    fn admonition_important_end(&self) -> &str {
+        "</div>"
+    }
+
+    fn admonition_warning_start(&self) -> &str {
+        r#"<div class="admonition admonition-warning">"#
+    }
+
+    fn admonition_warning_end(&self) -> &str {
+        "</div>"
+    }
+
+    fn admonition_caution_start(&self) -> &str {
+        r#"<div class="admonition admonition-caution">"#
+    }
+
+    fn admonition_caution_end(&self) -> &str {
+        "</div>"
+    }
+
+    fn admonition_title_start(&self) -> &str {
+        r#"<p class="admonition-title">"#
+    }
+
+    fn admonition_title_end(&self) -> &str {
+        "</p>"
+    }
+
+    fn admonition_css(&self) -> &str {
+        r#".admonition {
+			margin-top: 5px;
+			border-left: 4px solid #666666;
+			padding: 4px 10px
+		}
+
+		.admonition-title {
+			margin: 0;
+			font-weight: bold
+		}"#
+    }
+
+    fn admonition_note_css(&self) -> &str {
+        r#".admonition-note {
+			border-left-color: #0969da
+		}
+
+		.admonition-note .admonition-title {
+			color: #0969da
+		}"#
+    }
+
+    fn admonition_tip_css(&self) -> &str {
+        r#".admonition-tip {
+			border-left-color: #1a7f37
+		}
+
+		.admonition-tip .admonition-title {
+			color: #1a7f37
+		}"#
+    }
+
+    fn admonition_important_css(&self) -> &str {
+        r#".admonition-important {
+			border-left-color: #8250df
+		}
+
+		.admonition-important .admonition-title {
+			color: #8250df
+		}"#
+    }
+
+    fn admonition_warning_css(&self) -> &str {
+        r#".admonition-warning {
+			border-left-color: #9a6700
+		}
+
+		.admonition-warning .admonition-title {
+			color: #9a6700
+		}"#
+    }
+
+    fn admonition_caution_css(&self) -> &str {
+        r#".admonition-caution {
+			border-left-color: #cf222e
+		}
+
+		.admonition-caution .admonition-title {
+			color: #cf222e
 		}"#
     }
 }
@@ -0,0 +1,1062 @@
+use crate::attr::split_trailing_attrs;
+use crate::tag::AdmonitionKind;
+use crate::tag::Tag;
+use crate::tokenizer::chr_count;
+use crate::tokenizer::fence_line;
+use crate::tokenizer::trim;
+use crate::tokenizer::State;
+use crate::tokenizer::Token;
+use crate::tokenizer::TokenSource;
+use crate::tokenizer::ARRAY_CAP;
+use crate::tokenizer::FOOTNOTE_PREFIX_LEN;
+use crate::tokenizer::TEXT_STOP;
+use crate::BLOCK_QUOTE_TAG;
+use crate::HEADER_TAG;
+use chr::decode;
+use chr::plain_len;
+use chr::Decoded;
+use chr::InvalidByte;
+use chr::Utf8Policy;
+use chr::REPLACEMENT;
+use std::io;
+use std::ops::Range;
+
+// 输入已经整个在内存里时用的Tokenizer，切出来的token和Tokenizer一样
+// 纯文本一直读到下一个特殊的byte为止，整段借用输入，不用一个字一个字地复制
+pub struct SliceTokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+    state: State,
+    // 内容要改写的时候才用，比如链接文字里的转义和 \r\n 换行的代码块
+    text_buf: Vec<u8>,
+    // 这次的token有一段内容在text_buf里，先空着，next_token再填进去
+    buffered: bool,
+    policy: Utf8Policy,
+    // 输入里所有不合法的byte，前invalid_taken个已经取走了
+    invalid: Vec<InvalidByte>,
+    invalid_taken: usize,
+    // 上一个token的开头
+    token_start: usize,
+    // Replace的时候，有不合法的byte的token换成U+FFFD之后的内容
+    replaced: Vec<u8>,
+    // 读到结尾还没有闭合的代码块或者指令：最后一行的开头和读这一行之前的状态
+    open_block: Option<(usize, BlockEnd)>,
+}
+
+// 代码块和指令的结尾，PushParser用它只检查新来的行
+#[derive(Clone, Copy)]
+pub(crate) enum BlockEnd {
+    Code { fence: u8, len: usize },
+    // 指令可以嵌套，depth是还没有闭合的层数
+    Directive { depth: usize },
+}
+
+impl BlockEnd {
+    pub(crate) fn is_end(&mut self, line: &[u8]) -> bool {
+        match self {
+            BlockEnd::Code { fence, len } => {
+                let line = trim(line);
+                line.len() >= *len && line.iter().all(|b| b == fence)
+            }
+            BlockEnd::Directive { depth } => {
+                if trim(line).starts_with(b":::") {
+                    match fence_line(line) {
+                        None => *depth -= 1,
+                        Some((name, _rest)) if !name.is_empty() => *depth += 1,
+                        _ => {}
+                    }
+                }
+                *depth == 0
+            }
+        }
+    }
+}
+
+impl<'a> SliceTokenizer<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_utf8_policy(data, Utf8Policy::PassThrough)
+    }
+
+    // 不合法的utf8返回错误、换成U+FFFD或者原样保留，和Tokenizer一样
+    pub fn with_utf8_policy(data: &'a [u8], policy: Utf8Policy) -> Self {
+        Self {
+            data,
+            pos: 0,
+            state: State::Start,
+            text_buf: Vec::new(),
+            buffered: false,
+            policy,
+            invalid: find_invalid(data),
+            invalid_taken: 0,
+            token_start: 0,
+            replaced: Vec::new(),
+            open_block: None,
+        }
+    }
+
+    pub(crate) fn open_block(&self) -> Option<(usize, BlockEnd)> {
+        self.open_block
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    fn peek_is(&self, b: u8) -> bool {
+        self.peek() == Some(b)
+    }
+
+    // at开始的这个字符结束的位置，不合法的byte和ChrIter默认的PassThrough一样一个一个算
+    fn chr_end(&self, at: usize) -> usize {
+        match decode(&self.data[at..]) {
+            Decoded::Chr(len) => at + len,
+            Decoded::Invalid(_) | Decoded::Incomplete => at + 1,
+        }
+    }
+
+    fn text(&self, range: Range<usize>) -> Token<'a> {
+        let data = &self.data[range];
+        Token::PureText {
+            data,
+            char_len: chr_count(data),
+        }
+    }
+
+    // 这一行的结尾，不包括换行
+    fn line_end(&self) -> usize {
+        self.data[self.pos..]
+            .iter()
+            .position(|&b| b == b'\n' || b == b'\r')
+            .map_or(self.data.len(), |i| self.pos + i)
+    }
+
+    // 如果下一个是换行就读掉，返回是否到了行尾
+    fn take_line_end(&mut self) -> bool {
+        match self.peek() {
+            None => true,
+            Some(b'\r') => {
+                self.pos += 1;
+                if self.peek_is(b'\n') {
+                    self.pos += 1;
+                }
+                self.state = State::LineFeed;
+                true
+            }
+            Some(b'\n') => {
+                self.pos += 1;
+                self.state = State::LineFeed;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    // 读到end为止（包括end），不读换行，返回是否读到了end
+    fn read_until(&mut self, end: u8) -> bool {
+        while let Some(b) = self.peek() {
+            if b == b'\n' || b == b'\r' {
+                return false;
+            }
+            self.pos += 1;
+            if b == end {
+                return true;
+            }
+        }
+        false
+    }
+
+    // 链接的 (url "title")，url里的括号要配对，和CommonMark一样
+    fn read_link_inner(&mut self) -> bool {
+        let mut depth = 0;
+        let mut in_url = true;
+        while let Some(b) = self.peek() {
+            if b == b'\n' || b == b'\r' {
+                return false;
+            }
+            self.pos += 1;
+            match b {
+                b' ' | b'\t' if depth == 0 => in_url = false,
+                b'(' if in_url => depth += 1,
+                b')' if depth > 0 => depth -= 1,
+                b')' => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // 连续的纯文本，读过一个字符就不在行首了，后面只在行内的特殊byte前面断开
+    fn pure_text(&mut self) -> Token<'a> {
+        let start = self.pos;
+        self.pos = self.chr_end(self.pos);
+
+        loop {
+            self.pos += plain_len(&self.data[self.pos..], TEXT_STOP);
+            match self.peek() {
+                None => break,
+                Some(b) if b < 0x80 => {
+                    if is_special(b, self.data.get(self.pos + 1).copied(), false) {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                Some(_) => self.pos = self.chr_end(self.pos),
+            }
+        }
+
+        self.state = State::Other;
+        self.text(start..self.pos)
+    }
+
+    // 已经读到了 '$'，规则和Tokenizer::math一样，公式的内容就是原文
+    // 没有闭合时只返回开头的 $ 或者 $$，从它后面重新解析
+    fn math(&mut self, start: usize) -> Token<'a> {
+        let display = self.peek_is(b'$');
+        if display {
+            self.pos += 1;
+        } else if matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r') | None) {
+            return self.text(start..self.pos);
+        }
+        let content_start = self.pos;
+
+        let mut last = b'$';
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => return self.unmatched(start, content_start - start),
+            };
+
+            if display {
+                if (c == b'\n' && last == b'\n') || (c == b'\r' && matches!(last, b'\n' | b'\r')) {
+                    return self.unmatched(start, content_start - start);
+                }
+            } else if c == b'\n' || c == b'\r' {
+                return self.unmatched(start, content_start - start);
+            }
+
+            let content_end = self.pos;
+            self.pos = self.chr_end(self.pos);
+
+            if c == b'$' {
+                if display {
+                    if self.peek_is(b'$') {
+                        self.pos += 1;
+                        return Token::Math {
+                            data: &self.data[content_start..content_end],
+                            display,
+                        };
+                    }
+                } else if !matches!(last, b' ' | b'\t') && !matches!(self.peek(), Some(b'0'..=b'9'))
+                {
+                    return Token::Math {
+                        data: &self.data[content_start..content_end],
+                        display,
+                    };
+                }
+            } else if c == b'\\' {
+                // \$ 不会结束公式
+                if let Some(b'$' | b'\\') = self.peek() {
+                    self.pos += 1;
+                    last = 0;
+                } else {
+                    last = b'\\';
+                }
+                continue;
+            }
+
+            last = if c < 0x80 { c } else { 0 };
+        }
+    }
+
+    // 没有匹配上，只有开头的len个byte是文本，后面的重新解析
+    fn unmatched(&mut self, start: usize, len: usize) -> Token<'a> {
+        self.pos = start + len;
+        self.text(start..self.pos)
+    }
+
+    // 已经读到了行首的 '#'，后面不是空格的话这些 # 是文本
+    fn header(&mut self, start: usize) -> Token<'a> {
+        let mut head_level = 1;
+        while let Some(b) = self.peek() {
+            if b == HEADER_TAG {
+                self.pos += 1;
+                head_level += 1;
+
+                // 如果有7个#了，就把这7个#当做纯文本对待
+                if head_level == 7 {
+                    return self.text(start..self.pos);
+                }
+            } else if b == b' ' {
+                self.pos += 1;
+                break;
+            } else {
+                return self.text(start..self.pos);
+            }
+        }
+
+        Token::Tag(Tag::Header(head_level))
+    }
+
+    // 已经读到了行首的 '>'
+    fn block_quote(&mut self, start: usize) -> Token<'a> {
+        match self.peek() {
+            Some(b' ') => {
+                self.pos += 1;
+                if self.peek_is(b'[') {
+                    return self.alert();
+                }
+                Token::Tag(Tag::BlockQuote)
+            }
+            _ => self.text(start..self.pos),
+        }
+    }
+
+    // 已经读到了 "> "，下一个是 '['，尝试解析 > [!NOTE]
+    // 不匹配的话先返回BlockQuote，从 '[' 开始重新解析
+    fn alert(&mut self) -> Token<'a> {
+        let quote = Token::Tag(Tag::BlockQuote);
+        let start = self.pos;
+        self.pos += 1;
+
+        if !self.peek_is(b'!') {
+            self.pos = start;
+            return quote;
+        }
+        self.pos += 1;
+
+        while matches!(self.peek(), Some(b) if b.is_ascii_alphabetic())
+            && self.pos - start < ARRAY_CAP
+        {
+            self.pos += 1;
+        }
+
+        let kind = AdmonitionKind::from_alert_name(&self.data[start + 2..self.pos]);
+        if kind.is_none() || !self.peek_is(b']') {
+            self.pos = start;
+            return quote;
+        }
+        self.pos += 1;
+
+        while self.peek_is(b' ') {
+            self.pos += 1;
+        }
+
+        if self.take_line_end() {
+            Token::Admonition {
+                kind: kind.unwrap(),
+                title: &[],
+                attrs: &[],
+                fenced: false,
+            }
+        } else {
+            self.pos = start;
+            quote
+        }
+    }
+
+    // 代码块和指令的内容，一直读到is_end返回true的那一行（这一行不算在内容里）
+    // 返回内容和是否读到了结尾，\r\n 和 \r 换成 \n
+    fn read_block(&mut self, mut end: BlockEnd) -> (Range<usize>, bool) {
+        let content_start = self.pos;
+        let mut content_end = content_start;
+
+        // 最后一行可能还没有读完，接着读的时候从这一行重新检查
+        self.open_block = Some((self.pos, end));
+        while self.pos < self.data.len() {
+            let line_start = self.pos;
+            let line_end = self.line_end();
+            self.open_block = Some((line_start, end));
+            self.pos = line_end;
+            self.take_line_end();
+
+            if end.is_end(&self.data[line_start..line_end]) {
+                self.open_block = None;
+                return (content_start..content_end, true);
+            }
+            content_end = line_end;
+        }
+        (content_start..content_end, false)
+    }
+
+    // \r\n 换成 \n，有 \r 的话内容放到text_buf里
+    fn block_content(&mut self, range: Range<usize>) -> &'a [u8] {
+        let data = self.data;
+        let content = &data[range];
+        if !content.contains(&b'\r') {
+            return content;
+        }
+
+        self.text_buf.clear();
+        let mut iter = content.iter().peekable();
+        while let Some(&b) = iter.next() {
+            if b == b'\r' {
+                iter.next_if_eq(&&b'\n');
+                self.text_buf.push(b'\n');
+            } else {
+                self.text_buf.push(b);
+            }
+        }
+        self.buffered = true;
+        &[]
+    }
+
+    // 已经读到了行首的 ':'，尝试解析 :::kind Title、::: name {attrs} 和单独一行的 :::
+    fn fence(&mut self, start: usize) -> Token<'a> {
+        for _ in 0..2 {
+            if !self.peek_is(b':') {
+                return self.text(start..self.pos);
+            }
+            self.pos += 1;
+        }
+
+        self.pos = self.line_end();
+        let data = self.data;
+        let line = &data[start..self.pos];
+        let (name, rest) = match fence_line(line) {
+            Some(x) => x,
+            None => {
+                self.take_line_end();
+                return Token::ContainerEnd;
+            }
+        };
+
+        // 不是容器的话只有 ::: 是文本，后面的重新解析
+        if name.is_empty() || !name[0].is_ascii_alphabetic() {
+            return self.unmatched(start, 3);
+        }
+
+        self.take_line_end();
+        if let Some(kind) = AdmonitionKind::from_name(name) {
+            let (title, attrs) = split_trailing_attrs(rest);
+            return Token::Admonition {
+                kind,
+                title,
+                attrs,
+                fenced: true,
+            };
+        }
+
+        // 其他的名字都是指令，直到对应的 ::: 为止，中间可以嵌套
+        let (range, closed) = self.read_block(BlockEnd::Directive { depth: 1 });
+
+        Token::Directive {
+            name,
+            args: rest,
+            content: self.block_content(range),
+            closed,
+        }
+    }
+
+    // 已经读到了行首的 '`' 或者 '~'，至少3个才是代码块
+    fn code_block(&mut self, start: usize, fence: u8) -> Token<'a> {
+        while self.peek_is(fence) && self.pos - start < ARRAY_CAP {
+            self.pos += 1;
+        }
+        let fence_len = self.pos - start;
+        if fence_len < 3 {
+            return self.text(start..self.pos);
+        }
+
+        let data = self.data;
+        let info_start = self.pos;
+        self.pos = self.line_end();
+        let info = trim(&data[info_start..self.pos]);
+        self.take_line_end();
+
+        let (range, closed) = self.read_block(BlockEnd::Code {
+            fence,
+            len: fence_len,
+        });
+
+        let lang_len = info
+            .iter()
+            .take_while(|&&b| b != b' ' && b != b'\t' && b != b'{')
+            .count();
+        Token::CodeBlock {
+            lang: &info[..lang_len],
+            attrs: trim(&info[lang_len..]),
+            content: self.block_content(range),
+            closed,
+        }
+    }
+
+    // 已经读到了 '{'，{{< 开头的是短代码，否则尝试解析行尾的属性列表
+    fn brace(&mut self, start: usize) -> Token<'a> {
+        if self.peek_is(b'{') {
+            return self.shortcode(start);
+        }
+
+        if !self.read_until(b'}') {
+            return self.unmatched(start, 1);
+        }
+        let attrs_end = self.pos;
+
+        while matches!(self.peek(), Some(b' ' | b'\t')) {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'\n' | b'\r') | None => Token::Attrs {
+                data: &self.data[start..attrs_end],
+            },
+            Some(_) => self.unmatched(start, 1),
+        }
+    }
+
+    // 已经读到了 "{{"，尝试解析 {{< name args >}}，不能跨行
+    fn shortcode(&mut self, start: usize) -> Token<'a> {
+        for b in [b'{', b'<'] {
+            if !self.peek_is(b) {
+                return self.unmatched(start, 1);
+            }
+            self.pos += 1;
+        }
+
+        loop {
+            match self.peek() {
+                Some(b'\n' | b'\r') | None => return self.unmatched(start, 1),
+                Some(_) => {
+                    self.pos += 1;
+
+                    let data = &self.data[start..self.pos];
+                    if data.len() > 6 && data.ends_with(b">}}") {
+                        let inner = trim(&data[3..data.len() - 3]);
+                        let name_len = inner
+                            .iter()
+                            .take_while(|&&b| b != b' ' && b != b'\t')
+                            .count();
+
+                        if name_len == 0 {
+                            return self.unmatched(start, 1);
+                        }
+                        return Token::Shortcode {
+                            name: &inner[..name_len],
+                            args: trim(&inner[name_len..]),
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    // 链接文字里的 \x 去掉反斜杠，加到text_buf的后面
+    fn unescape_text(&mut self, text: Range<usize>) {
+        let mut i = text.start;
+        while i < text.end {
+            if self.data[i] == b'\\'
+                && i + 1 < text.end
+                && !matches!(self.data[i + 1], b'\n' | b'\r')
+            {
+                i += 1;
+            }
+            let end = self.chr_end(i).min(text.end);
+            self.text_buf.extend_from_slice(&self.data[i..end]);
+            i = end;
+        }
+    }
+
+    // 已经读到了 '[' 或者 "!["，尝试解析 [text](url "title"){attrs}，不能跨行
+    // 不匹配的话只有开头的 [ 或者 ! 是文本
+    fn link(&mut self, start: usize, image: bool) -> Token<'a> {
+        let text_start = self.pos;
+        let mut escaped = false;
+
+        loop {
+            match self.peek() {
+                Some(b']') => break,
+                Some(b'\n' | b'\r' | b'[') | None => return self.unmatched(start, 1),
+                Some(b) => {
+                    self.pos = self.chr_end(self.pos);
+                    // 转义的字符原样放进去
+                    if b == b'\\' && !matches!(self.peek(), Some(b'\n' | b'\r') | None) {
+                        escaped = true;
+                        self.pos = self.chr_end(self.pos);
+                    }
+                }
+            }
+        }
+        let text = text_start..self.pos;
+        self.pos += 1;
+
+        if !self.peek_is(b'(') {
+            return self.unmatched(start, 1);
+        }
+        self.pos += 1;
+
+        let inner_start = self.pos;
+        if !self.read_link_inner() {
+            return self.unmatched(start, 1);
+        }
+        let inner_end = self.pos - 1;
+
+        let mut attrs = self.pos..self.pos;
+        if self.peek_is(b'{') {
+            self.pos += 1;
+            // 属性没有结束的话链接没有属性，{ 重新解析
+            if self.read_until(b'}') {
+                attrs.end = self.pos;
+            } else {
+                self.pos = attrs.start;
+            }
+        }
+
+        let data = self.data;
+        let inner = trim(&data[inner_start..inner_end]);
+        let url_len = inner
+            .iter()
+            .take_while(|&&b| b != b' ' && b != b'\t')
+            .count();
+        let title = trim(&inner[url_len..]);
+        let title = match title {
+            [q @ (b'"' | b'\''), rest @ .., end] if q == end => rest,
+            _ => title,
+        };
+
+        let text = if escaped {
+            self.text_buf.clear();
+            self.unescape_text(text);
+            self.buffered = true;
+            &[]
+        } else {
+            &data[text]
+        };
+        Token::Link {
+            text,
+            url: &inner[..url_len],
+            title,
+            attrs: &data[attrs],
+            image,
+        }
+    }
+
+    // 已经读到了 '['，[^label] 是脚注，行首并且后面跟着 ':' 就是脚注的定义，不是 [^ 的话当做链接
+    // 不匹配的话只有 [ 是文本
+    fn footnote(&mut self, start: usize, line_start: bool) -> Token<'a> {
+        if !self.peek_is(b'^') {
+            return self.link(start, false);
+        }
+        self.pos += 1;
+
+        loop {
+            match self.peek() {
+                Some(b']') => {
+                    // [^] 不是脚注
+                    if self.pos - start == FOOTNOTE_PREFIX_LEN {
+                        return self.unmatched(start, 1);
+                    }
+                    self.pos += 1;
+                    break;
+                }
+                Some(b) => {
+                    let end = self.chr_end(self.pos);
+                    if matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'[') || end - start > ARRAY_CAP {
+                        return self.unmatched(start, 1);
+                    }
+                    self.pos = end;
+                }
+                None => return self.unmatched(start, 1),
+            }
+        }
+
+        let label = &self.data[start + FOOTNOTE_PREFIX_LEN..self.pos - 1];
+        if line_start && self.peek_is(b':') {
+            self.pos += 1;
+            let space = self.peek_is(b' ');
+            self.pos += space as usize;
+            return Token::FootnoteDef { label, space };
+        }
+
+        Token::FootnoteRef { label }
+    }
+
+    // 已经读到了行首的 '-'，尝试解析 - [ ] 和 - [x]，不匹配的话只有 - 是文本
+    fn task_list(&mut self, start: usize) -> Token<'a> {
+        let mut is_done_task = false;
+
+        for counter in *b" [?] " {
+            match (counter, self.peek()) {
+                (b'?', Some(b' ')) => {}
+                (b'?', Some(b'x')) => is_done_task = true,
+                (_, Some(b)) if counter != b'?' && b == counter => {}
+                _ => return self.unmatched(start, 1),
+            }
+            self.pos += 1;
+        }
+
+        Token::Tag(Tag::TaskList(is_done_task))
+    }
+
+    fn token(&mut self) -> Option<Token<'a>> {
+        let start = self.pos;
+        let b = *self.data.get(start)?;
+        let line_start = matches!(self.state, State::LineFeed | State::Start);
+        if !is_special(b, self.data.get(start + 1).copied(), line_start) {
+            return Some(self.pure_text());
+        }
+        self.pos += 1;
+
+        Some(match b {
+            b'\\' => {
+                // 结尾的 \ 什么也不返回，行尾的 \ 是普通文本，不转义换行
+                self.state = State::Other;
+                if let b'\n' | b'\r' = self.peek()? {
+                    return Some(self.text(start..self.pos));
+                }
+                self.pos = self.chr_end(self.pos);
+                self.text(start + 1..self.pos)
+            }
+            b' ' => {
+                self.state = State::Other;
+                Token::Space
+            }
+            b'\t' => {
+                self.state = State::Tab(if let State::Tab(n) = self.state {
+                    n + 1
+                } else {
+                    1
+                });
+                Token::Tab
+            }
+            b'\n' | b'\r' => {
+                if b == b'\r' && self.peek_is(b'\n') {
+                    self.pos += 1;
+                }
+                self.state = State::LineFeed;
+                Token::Ln
+            }
+            b => {
+                self.state = State::Other;
+                match b {
+                    b'$' => self.math(start),
+                    b'{' => self.brace(start),
+                    b'!' => {
+                        self.pos += 1;
+                        self.link(start, true)
+                    }
+                    b'[' => self.footnote(start, line_start),
+                    HEADER_TAG => self.header(start),
+                    BLOCK_QUOTE_TAG => self.block_quote(start),
+                    b':' => self.fence(start),
+                    b'`' | b'~' => self.code_block(start, b),
+                    b'-' => self.task_list(start),
+                    _ => unreachable!(),
+                }
+            }
+        })
+    }
+}
+
+impl TokenSource for SliceTokenizer<'_> {
+    fn next_token(&mut self) -> Option<io::Result<(Token<'_>, Range<u64>)>> {
+        let start = self.pos;
+        self.token_start = start;
+        let mut token = self.token()?;
+        let end = self.pos;
+        if self.buffered {
+            self.buffered = false;
+            match &mut token {
+                Token::Link { text: data, .. }
+                | Token::CodeBlock { content: data, .. }
+                | Token::Directive { content: data, .. } => *data = &self.text_buf,
+                _ => unreachable!(),
+            }
+        }
+
+        // 这个token里第一个不合法的byte
+        let idx = self.invalid.partition_point(|x| x.offset < start as u64);
+        match self.invalid.get(idx) {
+            Some(&invalid) if invalid.offset < end as u64 => match self.policy {
+                Utf8Policy::Error => {
+                    return Some(Err(io::Error::new(io::ErrorKind::InvalidData, invalid)));
+                }
+                Utf8Policy::Replace => replace_invalid(&mut token, &mut self.replaced),
+                Utf8Policy::PassThrough => {}
+            },
+            _ => {}
+        }
+        Some(Ok((token, start as u64..end as u64)))
+    }
+
+    fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.policy = policy;
+    }
+
+    fn take_invalid(&mut self) -> Vec<InvalidByte> {
+        let rest = &self.invalid[self.invalid_taken..];
+        let n = rest.partition_point(|x| x.offset < self.pos as u64);
+        self.invalid_taken += n;
+        rest[..n].to_vec()
+    }
+
+    // 和ChrIter一样数字符：Replace时一段不合法的byte是一个U+FFFD，否则每个byte是一个
+    fn locate(&self, offset: u64) -> Option<(u32, u32)> {
+        let offset = offset as usize;
+        if offset < self.token_start || offset >= self.pos {
+            return None;
+        }
+        let before = &self.data[self.token_start..offset];
+        let line_start = match before.iter().rposition(|&b| b == b'\n') {
+            Some(idx) => self.token_start + idx + 1,
+            None => self.token_start,
+        };
+        let lines = before.iter().filter(|&&b| b == b'\n').count() as u32;
+
+        let mut chars = 0;
+        let mut i = line_start;
+        while i < offset {
+            let len = match (decode(&self.data[i..]), self.policy) {
+                (Decoded::Chr(len), _) => len,
+                (Decoded::Invalid(len), Utf8Policy::Replace) => len,
+                (Decoded::Incomplete, Utf8Policy::Replace) => self.data.len() - i,
+                _ => 1,
+            };
+            // offset在这个字符中间的时候就是这个字符的位置
+            if i + len > offset {
+                break;
+            }
+            chars += 1;
+            i += len;
+        }
+        Some((lines, chars))
+    }
+}
+
+// 和ChrIter一样，不合法的byte每个记一次
+fn find_invalid(data: &[u8]) -> Vec<InvalidByte> {
+    let mut invalid = Vec::new();
+    let mut i = 0;
+    while let Err(e) = std::str::from_utf8(&data[i..]) {
+        i += e.valid_up_to();
+        let len = match decode(&data[i..]) {
+            Decoded::Invalid(len) => len,
+            Decoded::Chr(_) | Decoded::Incomplete => data.len() - i,
+        };
+        for (j, &byte) in data[i..i + len].iter().enumerate() {
+            invalid.push(InvalidByte {
+                offset: (i + j) as u64,
+                byte,
+            });
+        }
+        i += len;
+    }
+    invalid
+}
+
+// 把token里的内容换成U+FFFD之后的，每段不合法的byte换成一个，和ChrIter的Replace一样
+fn replace_invalid<'t>(token: &mut Token<'t>, replaced: &'t mut Vec<u8>) {
+    let mut fields: Vec<&mut &'t [u8]> = match token {
+        Token::PureText { data, .. } | Token::Math { data, .. } | Token::Attrs { data } => {
+            vec![data]
+        }
+        Token::FootnoteRef { label } | Token::FootnoteDef { label, .. } => vec![label],
+        Token::Admonition { title, attrs, .. } => vec![title, attrs],
+        Token::Shortcode { name, args } => vec![name, args],
+        Token::Directive {
+            name,
+            args,
+            content,
+            ..
+        } => vec![name, args, content],
+        Token::Link {
+            text,
+            url,
+            title,
+            attrs,
+            ..
+        } => vec![text, url, title, attrs],
+        Token::CodeBlock {
+            lang,
+            attrs,
+            content,
+            ..
+        } => vec![lang, attrs, content],
+        Token::Space | Token::Tab | Token::Ln | Token::Tag(_) | Token::ContainerEnd => Vec::new(),
+    };
+
+    replaced.clear();
+    let mut ranges = Vec::with_capacity(fields.len());
+    for field in &fields {
+        let start = replaced.len();
+        let mut rest = **field;
+        while !rest.is_empty() {
+            let len = match decode(rest) {
+                Decoded::Chr(len) => {
+                    replaced.extend_from_slice(&rest[..len]);
+                    rest = &rest[len..];
+                    continue;
+                }
+                Decoded::Invalid(len) => len,
+                Decoded::Incomplete => rest.len(),
+            };
+            replaced.extend_from_slice((&REPLACEMENT).into());
+            rest = &rest[len..];
+        }
+        ranges.push(start..replaced.len());
+    }
+
+    let replaced: &'t Vec<u8> = replaced;
+    for (field, range) in fields.iter_mut().zip(ranges) {
+        **field = &replaced[range];
+    }
+    if let Token::PureText { data, char_len } = token {
+        *char_len = chr_count(data);
+    }
+}
+
+// 纯文本在这些byte前面断开，行首还要加上块的标记
+fn is_special(b: u8, next: Option<u8>, line_start: bool) -> bool {
+    match b {
+        b'\\' | b' ' | b'\t' | b'\n' | b'\r' | b'$' | b'{' | b'[' => true,
+        b'!' => next == Some(b'['),
+        HEADER_TAG | BLOCK_QUOTE_TAG | b':' | b'`' | b'~' | b'-' => line_start,
+        _ => false,
+    }
+}
+
+#[test]
+fn test() {
+    use crate::html_writer::HtmlWriterImpl;
+    use crate::mapper::Mapper;
+    use crate::parser::ParseError;
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::schema::DefaultSchema;
+    use crate::tokenizer::Tokenizer;
+
+    // 相邻的纯文本合在一起比较，Tokenizer是一个字符一个token，byte范围要首尾相接
+    fn tokens<T: TokenSource>(mut t: T) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut text = Vec::new();
+        let mut char_len = 0;
+        let mut text_start = 0;
+        let mut last_end = 0;
+        while let Some(token) = t.next_token() {
+            let (token, span) = token.unwrap();
+            assert_eq!(last_end, span.start);
+            if let Token::PureText { data, char_len: n } = token {
+                if text.is_empty() {
+                    text_start = span.start;
+                }
+                text.extend_from_slice(data);
+                char_len += n;
+                last_end = span.end;
+                continue;
+            }
+            if !text.is_empty() {
+                let text = std::mem::take(&mut text);
+                out.push(format!(
+                    "{:?} {} {:?}",
+                    String::from_utf8_lossy(&text),
+                    char_len,
+                    text_start..last_end
+                ));
+                char_len = 0;
+            }
+            last_end = span.end;
+            let lossy = |x: &[u8]| String::from_utf8_lossy(x).into_owned();
+            out.push(match token {
+                Token::Directive {
+                    content, closed, ..
+                }
+                | Token::CodeBlock {
+                    content, closed, ..
+                } => format!("{:?} {:?} {} {:?}", token, lossy(content), closed, span),
+                Token::Link { title, attrs, .. } => {
+                    format!(
+                        "{:?} {:?} {:?} {:?}",
+                        token,
+                        lossy(title),
+                        lossy(attrs),
+                        span
+                    )
+                }
+                Token::Admonition { attrs, .. } => {
+                    format!("{:?} {:?} {:?}", token, lossy(attrs), span)
+                }
+                _ => format!("{:?} {} {:?}", token, token.char_len(), span),
+            });
+        }
+        if !text.is_empty() {
+            out.push(format!(
+                "{:?} {} {:?}",
+                String::from_utf8_lossy(&text),
+                char_len,
+                text_start..last_end
+            ));
+        }
+        out
+    }
+
+    let readme = std::fs::read("README.md").unwrap();
+    let test_md = std::fs::read("test.md").unwrap();
+    let edge_cases = "中文 text\t中\n- [ ] task\n- [X] no\n-\n> [!NOTE] \n> [!NOPE] x\n> [link](u)\n#######\n#x\n\\# \\\n[a\\]b](u \"t\"){.c} [p](u(v)w) [q](x(y) [x\\\n{.a}\t y\n{.a}\t\n{{< sc a >}} {{<>}} {{x\n$x$ $ x$ $x $1 $$\na\n\nb$$ $a\\$b$\n![i](s) ![n] [^] [^l] [^a b]\n[^d]: def\n```rs {.x}\r\nline\r\n\r\n```\r\n:::tip Title {#t}\n:::\n::: box a=1\r\ninner\r\n::: nested\r\n:::\r\n:::\n::: 1\n~~~\nunclosed\n\\";
+    let unclosed = "a $$ b\n\nc $$ $x$\n# h\n\n$$ [^d]\n> [!TIP]\n\n$a [l](u)\n";
+    let render = |input: &[u8], from_slice: bool, policy: Utf8Policy| {
+        let mut html = Vec::new();
+        let mapper = Mapper::new(DefaultSchema, HtmlWriterImpl::new(&mut html));
+        let (res, warnings) = if from_slice {
+            let mut parser = StatefulParser::from_slice(input, mapper);
+            parser.set_utf8_policy(policy);
+            (parser.parse_and_write(), parser.take_warnings())
+        } else {
+            let mut parser = StatefulParser::with_renderer(input, mapper);
+            parser.set_utf8_policy(policy);
+            (parser.parse_and_write(), parser.take_warnings())
+        };
+        let res = res.map_err(|e| match e {
+            ParseError::IoError(e) => e.to_string(),
+            ParseError::SytaxError(e) => format!("{:?}", e),
+        });
+        let warnings: Vec<String> = warnings
+            .iter()
+            .map(|x| format!("{}:{} {}", x.pos().line_num, x.pos().column_num, x.msg()))
+            .collect();
+        (res, html, warnings)
+    };
+    let invalid =
+        b"bad \xff\xe4\xb8 x\n- \x80[\xc0\xaf](u)\n```\nok\n\xfe\xfd\n```\n# \xed\xa0\xe4";
+    for input in [
+        &readme[..],
+        &test_md[..],
+        edge_cases.as_bytes(),
+        unclosed.as_bytes(),
+        invalid,
+    ] {
+        for policy in [Utf8Policy::PassThrough, Utf8Policy::Replace] {
+            assert_eq!(
+                tokens(Tokenizer::with_utf8_policy(input, policy)),
+                tokens(SliceTokenizer::with_utf8_policy(input, policy))
+            );
+        }
+
+        // 不合法的byte都作为警告报告，Error的时候在同一个位置出错
+        for policy in [
+            Utf8Policy::PassThrough,
+            Utf8Policy::Replace,
+            Utf8Policy::Error,
+        ] {
+            assert_eq!(render(input, false, policy), render(input, true, policy));
+        }
+    }
+
+    let (res, html, warnings) = render(invalid, true, Utf8Policy::Replace);
+    assert!(res.is_ok());
+    assert!(html.starts_with("bad&nbsp;\u{fffd}\u{fffd}&nbsp;x<br>".as_bytes()));
+    // 列是不合法的byte所在的列，一段换成一个U+FFFD的在同一列
+    assert_eq!(
+        warnings[..8],
+        [
+            "1:5 invalid utf-8 byte 0xff at offset 4",
+            "1:6 invalid utf-8 byte 0xe4 at offset 5",
+            "1:6 invalid utf-8 byte 0xb8 at offset 6",
+            "2:3 invalid utf-8 byte 0x80 at offset 12",
+            "2:5 invalid utf-8 byte 0xc0 at offset 14",
+            "2:6 invalid utf-8 byte 0xaf at offset 15",
+            "5:1 invalid utf-8 byte 0xfe at offset 28",
+            "5:2 invalid utf-8 byte 0xfd at offset 29",
+        ]
+    );
+    let (_res, _html, warnings) = render(invalid, true, Utf8Policy::PassThrough);
+    assert_eq!(warnings[2], "1:7 invalid utf-8 byte 0xb8 at offset 6");
+    let (res, _html, _warnings) = render(invalid, true, Utf8Policy::Error);
+    assert_eq!(res.unwrap_err(), "invalid utf-8 byte 0xff at offset 4");
+}
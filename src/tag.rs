@@ -10,15 +10,81 @@ pub enum Tag {
     Header(Level),
     BlockQuote,
     TaskList(IsDone),
+    Footnote,
+    Admonition(AdmonitionKind),
+    CodeBlock,
+    // 连续的文本行，html里不输出标签
+    Paragraph,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AdmonitionKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AdmonitionKind {
+    pub const ALL: [AdmonitionKind; 5] = [
+        AdmonitionKind::Note,
+        AdmonitionKind::Tip,
+        AdmonitionKind::Important,
+        AdmonitionKind::Warning,
+        AdmonitionKind::Caution,
+    ];
+
+    // GitHub 的 > [!NOTE] 只有这五种
+    pub fn from_alert_name(name: &[u8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().as_bytes().eq_ignore_ascii_case(name))
+    }
+
+    // ::: 容器额外支持一些常见的别名
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        Self::from_alert_name(name).or_else(|| {
+            let name = name.to_ascii_lowercase();
+            match &name[..] {
+                b"info" => Some(AdmonitionKind::Note),
+                b"hint" => Some(AdmonitionKind::Tip),
+                b"danger" | b"error" => Some(AdmonitionKind::Caution),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AdmonitionKind::Note => "note",
+            AdmonitionKind::Tip => "tip",
+            AdmonitionKind::Important => "important",
+            AdmonitionKind::Warning => "warning",
+            AdmonitionKind::Caution => "caution",
+        }
+    }
+
+    pub fn default_title(self) -> &'static str {
+        match self {
+            AdmonitionKind::Note => "Note",
+            AdmonitionKind::Tip => "Tip",
+            AdmonitionKind::Important => "Important",
+            AdmonitionKind::Warning => "Warning",
+            AdmonitionKind::Caution => "Caution",
+        }
+    }
 }
 
 impl Tag {
     pub fn char_len(self) -> u32 {
         match self {
-            Tag::None => 0,
+            Tag::None | Tag::Paragraph => 0,
             Tag::Header(level) => level as u32 + 1,
             Tag::BlockQuote => 2,
             Tag::TaskList(_x) => 6,
+            // 脚注、容器和代码块的长度不固定，见Token::char_len
+            Tag::Footnote | Tag::Admonition(_) | Tag::CodeBlock => unreachable!(),
         }
     }
 
@@ -34,13 +100,20 @@ impl Tag {
             Tag::BlockQuote => 6,
             Tag::TaskList(true) => 7,
             Tag::TaskList(false) => 8,
+            Tag::Footnote => 9,
+            Tag::Admonition(AdmonitionKind::Note) => 10,
+            Tag::Admonition(AdmonitionKind::Tip) => 11,
+            Tag::Admonition(AdmonitionKind::Important) => 12,
+            Tag::Admonition(AdmonitionKind::Warning) => 13,
+            Tag::Admonition(AdmonitionKind::Caution) => 14,
+            Tag::CodeBlock => 15,
             _ => unreachable!(),
         }
     }
 
     pub fn start_tag<S: Schema>(self, s: &S) -> &str {
         match self {
-            Tag::None => unreachable!(),
+            Tag::None | Tag::Footnote | Tag::Paragraph => unreachable!(),
             Tag::Header(level) => Self::header_start(s, level),
             Tag::BlockQuote => s.block_quote_start(),
             Tag::TaskList(is_done) => {
@@ -50,12 +123,21 @@ impl Tag {
                     s.task_list_todo_start()
                 }
             }
+            Tag::Admonition(kind) => match kind {
+                AdmonitionKind::Note => s.admonition_note_start(),
+                AdmonitionKind::Tip => s.admonition_tip_start(),
+                AdmonitionKind::Important => s.admonition_important_start(),
+                AdmonitionKind::Warning => s.admonition_warning_start(),
+                AdmonitionKind::Caution => s.admonition_caution_start(),
+            },
+            Tag::CodeBlock => s.code_block_start(),
         }
     }
 
     pub fn end_tag<S: Schema>(self, s: &S) -> &str {
         match self {
-            Tag::None => "",
+            Tag::None | Tag::Paragraph => "",
+            Tag::Footnote => unreachable!(),
             Tag::Header(level) => Self::header_end(s, level),
             Tag::BlockQuote => s.block_quote_end(),
             Tag::TaskList(is_done) => {
@@ -65,6 +147,14 @@ impl Tag {
                     s.task_list_todo_end()
                 }
             }
+            Tag::Admonition(kind) => match kind {
+                AdmonitionKind::Note => s.admonition_note_end(),
+                AdmonitionKind::Tip => s.admonition_tip_end(),
+                AdmonitionKind::Important => s.admonition_important_end(),
+                AdmonitionKind::Warning => s.admonition_warning_end(),
+                AdmonitionKind::Caution => s.admonition_caution_end(),
+            },
+            Tag::CodeBlock => s.code_block_end(),
         }
     }
 
@@ -0,0 +1,440 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::AdmonitionKind;
+use crate::tag::Tag;
+use crate::text_writer::str_width;
+use crate::text_writer::wrap;
+use crate::text_writer::DEFAULT_WIDTH;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const UNDERLINE_BLUE: &str = "\x1b[4;34m";
+
+// 在终端里预览：标题加粗上色，引用前面是暗色的竖线，代码块加边框
+// color为false时不输出任何转义序列
+pub struct TermWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    width: usize,
+    color: bool,
+    // 当前块里的行内内容，块结束时折行输出
+    line: String,
+    // 引用和容器每一行前面的竖线，可能带着颜色
+    prefix: Vec<String>,
+    after_item: bool,
+    footnote: Option<usize>,
+    footnotes: Vec<(Vec<u8>, String)>,
+}
+
+// 终端的宽度，先问终端，问不到的话取环境变量COLUMNS，都没有的话是80
+// 大部分shell不会export COLUMNS
+pub fn terminal_width() -> usize {
+    tty_width()
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|x| x.trim().parse().ok())
+                .filter(|&x| x > 0)
+        })
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn tty_width() -> Option<usize> {
+    use std::os::raw::c_int;
+    use std::os::raw::c_ulong;
+
+    // sys/ioctl.h的struct winsize
+    #[repr(C)]
+    #[derive(Default)]
+    struct WinSize {
+        row: u16,
+        col: u16,
+        xpixel: u16,
+        ypixel: u16,
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const TIOCGWINSZ: c_ulong = 0x5413;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const TIOCGWINSZ: c_ulong = 0x40087468;
+
+    extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    // stdout被重定向的时候问stderr
+    [1, 2].into_iter().find_map(|fd| {
+        let mut size = WinSize::default();
+        // SAFETY: TIOCGWINSZ只往传进去的winsize里写
+        let res = unsafe { ioctl(fd, TIOCGWINSZ, &mut size as *mut WinSize) };
+        (res == 0 && size.col > 0).then_some(size.col as usize)
+    })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn tty_width() -> Option<usize> {
+    None
+}
+
+impl<W: Write> TermWriter<W> {
+    pub fn new(w: W, width: usize, color: bool) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            width,
+            color,
+            line: String::new(),
+            prefix: Vec::new(),
+            after_item: false,
+            footnote: None,
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn paint(&self, style: &str, s: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, s, RESET)
+        } else {
+            s.to_string()
+        }
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.buf_writer.write_all(s.as_bytes())
+    }
+
+    fn write_prefix(&mut self) -> io::Result<()> {
+        let prefix = self.prefix.concat();
+        self.write_str(&prefix)
+    }
+
+    fn write_blank_line(&mut self) -> io::Result<()> {
+        let prefix = self.prefix.concat();
+        self.write_str(prefix.trim_end())?;
+        self.write_str("\n")
+    }
+
+    fn before_block(&mut self, is_item: bool) -> io::Result<()> {
+        if self.after_item && !is_item {
+            self.write_blank_line()?;
+        }
+        self.after_item = false;
+        Ok(())
+    }
+
+    fn avail_width(&self, extra: usize) -> usize {
+        self.width
+            .saturating_sub(str_width(&self.prefix.concat()) + extra)
+            .max(1)
+    }
+
+    // 把line折行输出，每一行都单独加上样式，这样前缀不会被染色
+    fn flush_line(&mut self, first: &str, style: &str) -> io::Result<usize> {
+        let text = std::mem::take(&mut self.line);
+        let indent = " ".repeat(str_width(first));
+        let lines = wrap(&text, self.avail_width(indent.len()));
+
+        let max_width = lines.iter().map(|x| str_width(x)).max().unwrap_or(0);
+        for (idx, line) in lines.iter().enumerate() {
+            self.write_prefix()?;
+            self.write_str(if idx == 0 { first } else { &indent })?;
+            let line = if style.is_empty() {
+                line.to_string()
+            } else {
+                self.paint(style, line)
+            };
+            self.write_str(&line)?;
+            self.write_str("\n")?;
+        }
+        Ok(max_width)
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|(x, _)| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push((label.to_vec(), String::new()));
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+fn admonition_color(kind: AdmonitionKind) -> &'static str {
+    match kind {
+        AdmonitionKind::Note => "\x1b[34m",
+        AdmonitionKind::Tip => "\x1b[32m",
+        AdmonitionKind::Important => "\x1b[35m",
+        AdmonitionKind::Warning => "\x1b[33m",
+        AdmonitionKind::Caution => "\x1b[31m",
+    }
+}
+
+impl<W: Write> Renderer for TermWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Footnote => {
+                self.footnote = Some(self.footnote_number(info));
+                return Ok(());
+            }
+            Tag::TaskList(_) => return self.before_block(true),
+            _ => self.before_block(false)?,
+        }
+
+        match tag {
+            Tag::BlockQuote => {
+                let bar = self.paint(DIM, "│");
+                self.prefix.push(bar + " ");
+            }
+            Tag::Admonition(kind) => {
+                let color = admonition_color(kind);
+                let bar = self.paint(color, "│");
+                self.prefix.push(bar + " ");
+
+                self.line = if info.is_empty() {
+                    kind.default_title().to_string()
+                } else {
+                    String::from_utf8_lossy(info).into_owned()
+                };
+                self.flush_line("", &format!("{}{}", BOLD, color))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Paragraph => {
+                self.flush_line("", "")?;
+                self.write_blank_line()?;
+            }
+            Tag::Header(level) => {
+                let style = match level {
+                    1 => "\x1b[1;4;35m",
+                    2 => "\x1b[1;36m",
+                    _ => "\x1b[1;33m",
+                };
+                let width = self.flush_line("", style)?;
+                // 没有颜色时用下划线区分标题
+                if !self.color {
+                    self.write_prefix()?;
+                    self.write_str(&if level == 1 { "=" } else { "-" }.repeat(width))?;
+                    self.write_str("\n")?;
+                }
+                self.write_blank_line()?;
+            }
+            Tag::BlockQuote | Tag::Admonition(_) => {
+                if !self.line.trim().is_empty() {
+                    self.flush_line("", "")?;
+                }
+                self.line.clear();
+                self.prefix.pop();
+                self.write_blank_line()?;
+            }
+            Tag::TaskList(is_done) => {
+                let glyph = match (is_done, self.color) {
+                    (true, true) => self.paint("\x1b[32m", "☑") + " ",
+                    (false, true) => "☐ ".to_string(),
+                    (true, false) => "[x] ".to_string(),
+                    (false, false) => "[ ] ".to_string(),
+                };
+                self.flush_line(&glyph, if is_done { DIM } else { "" })?;
+                self.after_item = true;
+            }
+            Tag::Footnote => {
+                if let Some(number) = self.footnote.take() {
+                    self.footnotes[number - 1].1 = std::mem::take(&mut self.line);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn inline_start(&mut self, inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        match inline {
+            Inline::Link { .. } if self.color => self.line.push_str(UNDERLINE_BLUE),
+            Inline::Link { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, .. } => {
+                let url = format!(" ({})", String::from_utf8_lossy(url));
+                if self.color {
+                    self.line.push_str(RESET);
+                }
+                let url = self.paint(DIM, &url);
+                self.line.push_str(&url);
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.line.push_str(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.line.push(' ');
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.line.push(' ');
+        Ok(())
+    }
+
+    fn line_break(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        Ok(())
+    }
+
+    // 代码不折行，加上边框，语言写在上边框里
+    fn code_block(
+        &mut self,
+        lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.before_block(false)?;
+
+        let content = String::from_utf8_lossy(content);
+        let lang = String::from_utf8_lossy(lang);
+        let lang_width = if lang.is_empty() {
+            0
+        } else {
+            str_width(&lang) + 1
+        };
+        let inner = content
+            .lines()
+            .map(str_width)
+            .max()
+            .unwrap_or(0)
+            .max(lang_width);
+
+        let top = if lang.is_empty() {
+            format!("┌{}┐", "─".repeat(inner + 2))
+        } else {
+            format!("┌─ {} {}┐", lang, "─".repeat(inner + 2 - lang_width - 2))
+        };
+        let top = self.paint(DIM, &top);
+        self.write_prefix()?;
+        self.write_str(&top)?;
+        self.write_str("\n")?;
+
+        let bar = self.paint(DIM, "│");
+        for line in content.lines() {
+            let padding = " ".repeat(inner - str_width(line));
+            self.write_prefix()?;
+            self.write_str(&format!("{} {}{} {}\n", bar, line, padding, bar))?;
+        }
+
+        let bottom = self.paint(DIM, &format!("└{}┘", "─".repeat(inner + 2)));
+        self.write_prefix()?;
+        self.write_str(&bottom)?;
+        self.write_str("\n")?;
+        self.write_blank_line()
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        let image = format!(
+            "[image: {}] ({})",
+            String::from_utf8_lossy(alt),
+            String::from_utf8_lossy(src)
+        );
+        let image = self.paint(DIM, &image);
+        self.line.push_str(&image);
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], _display: bool, _pos: Pos) -> io::Result<()> {
+        let tex = self.paint("\x1b[3m", &String::from_utf8_lossy(tex));
+        self.line.push_str(&tex);
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        let number = self.footnote_number(label);
+        let mark = self.paint(DIM, &format!("[{}]", number));
+        self.line.push_str(&mark);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.line.trim().is_empty() {
+            self.flush_line("", "")?;
+        }
+
+        let footnotes = std::mem::take(&mut self.footnotes);
+        if footnotes.iter().any(|(_, content)| !content.is_empty()) {
+            let rule = self.paint(DIM, &"─".repeat(self.width.min(20)));
+            self.write_str(&rule)?;
+            self.write_str("\n")?;
+            for (idx, (_label, content)) in footnotes.into_iter().enumerate() {
+                self.line = content;
+                self.flush_line(&format!("[{}] ", idx + 1), "")?;
+            }
+        }
+
+        self.buf_writer.flush()
+    }
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    let input = "# Title\n\n> quote\n\n- [x] done\n\n```rs\nfn a() {}\n```\n";
+    let render = |color: bool| {
+        let mut parser =
+            StatefulParser::with_renderer(input.as_bytes(), TermWriter::new(Vec::new(), 40, color));
+        parser.parse_and_write().unwrap();
+        String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap()
+    };
+
+    assert_eq!(
+        "Title\n=====\n\n│ quote\n\n[x] done\n\n┌─ rs ──────┐\n│ fn a() {} │\n└───────────┘\n\n",
+        render(false)
+    );
+
+    let out = render(true);
+    assert!(out.contains("\x1b[1;4;35mTitle\x1b[0m\n"));
+    assert!(out.contains("\x1b[2m│\x1b[0m quote"));
+    assert!(out.contains("☑"));
+}
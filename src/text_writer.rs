@@ -0,0 +1,364 @@
+use crate::attr::Attrs;
+use crate::renderer::Inline;
+use crate::renderer::Pos;
+use crate::renderer::Renderer;
+use crate::tag::Tag;
+use chr::Chr;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+// 纯文本：去掉所有标记，段落按宽度折行，CJK字符算两个宽度
+pub struct TextWriter<W: Write> {
+    buf_writer: BufWriter<W>,
+    width: usize,
+    // 当前块里的行内内容，块结束时折行输出
+    line: String,
+    // 引用和容器每一行前面的 "> "
+    prefix: String,
+    // 连续的任务列表之间不空行
+    after_item: bool,
+    // 在脚注定义里时的脚注编号
+    footnote: Option<usize>,
+    // 脚注的label和内容，下标+1是编号，最后统一输出
+    footnotes: Vec<(Vec<u8>, String)>,
+}
+
+impl<W: Write> TextWriter<W> {
+    pub fn new(w: W) -> Self {
+        Self::with_width(w, DEFAULT_WIDTH)
+    }
+
+    pub fn with_width(w: W, width: usize) -> Self {
+        Self {
+            buf_writer: BufWriter::new(w),
+            width,
+            line: String::new(),
+            prefix: String::new(),
+            after_item: false,
+            footnote: None,
+            footnotes: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.buf_writer.into_inner().map_err(|e| e.into_error())
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.buf_writer.write_all(s.as_bytes())
+    }
+
+    fn write_blank_line(&mut self) -> io::Result<()> {
+        let prefix = self.prefix.trim_end().to_string();
+        self.write_str(&prefix)?;
+        self.write_str("\n")
+    }
+
+    fn before_block(&mut self, is_item: bool) -> io::Result<()> {
+        if self.after_item && !is_item {
+            self.write_blank_line()?;
+        }
+        self.after_item = false;
+        Ok(())
+    }
+
+    // 把line折行输出，first是第一行额外的前缀，其他行用同样宽度的空格对齐
+    fn flush_line(&mut self, first: &str) -> io::Result<usize> {
+        let text = std::mem::take(&mut self.line);
+        let indent = " ".repeat(str_width(first));
+        let avail = self
+            .width
+            .saturating_sub(str_width(&self.prefix) + indent.len())
+            .max(1);
+
+        let lines = wrap(&text, avail);
+        let max_width = lines.iter().map(|x| str_width(x)).max().unwrap_or(0);
+        for (idx, line) in lines.iter().enumerate() {
+            let prefix = self.prefix.clone();
+            self.write_str(&prefix)?;
+            self.write_str(if idx == 0 { first } else { &indent })?;
+            self.write_str(line)?;
+            self.write_str("\n")?;
+        }
+        Ok(max_width)
+    }
+
+    fn footnote_number(&mut self, label: &[u8]) -> usize {
+        match self.footnotes.iter().position(|(x, _)| x == label) {
+            Some(idx) => idx + 1,
+            None => {
+                self.footnotes.push((label.to_vec(), String::new()));
+                self.footnotes.len()
+            }
+        }
+    }
+}
+
+impl<W: Write> Renderer for TextWriter<W> {
+    fn block_start(&mut self, tag: Tag, info: &[u8], _attrs: &Attrs, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Footnote => {
+                self.footnote = Some(self.footnote_number(info));
+                return Ok(());
+            }
+            Tag::TaskList(_) => return self.before_block(true),
+            _ => self.before_block(false)?,
+        }
+
+        match tag {
+            Tag::BlockQuote => self.prefix.push_str("> "),
+            Tag::Admonition(kind) => {
+                self.prefix.push_str("> ");
+                self.line = if info.is_empty() {
+                    kind.default_title().to_string()
+                } else {
+                    String::from_utf8_lossy(info).into_owned()
+                };
+                self.flush_line("")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn block_end(&mut self, tag: Tag, _pos: Pos) -> io::Result<()> {
+        match tag {
+            Tag::Paragraph => {
+                self.flush_line("")?;
+                self.write_blank_line()?;
+            }
+            Tag::Header(level) => {
+                let width = self.flush_line("")?;
+                let prefix = self.prefix.clone();
+                self.write_str(&prefix)?;
+                self.write_str(&if level == 1 { "=" } else { "-" }.repeat(width))?;
+                self.write_str("\n")?;
+                self.write_blank_line()?;
+            }
+            Tag::BlockQuote | Tag::Admonition(_) => {
+                if !self.line.trim().is_empty() {
+                    self.flush_line("")?;
+                }
+                self.line.clear();
+                self.prefix.truncate(self.prefix.len() - 2);
+                self.write_blank_line()?;
+            }
+            Tag::TaskList(is_done) => {
+                self.flush_line(if is_done { "[x] " } else { "[ ] " })?;
+                self.after_item = true;
+            }
+            Tag::Footnote => {
+                if let Some(number) = self.footnote.take() {
+                    self.footnotes[number - 1].1 = std::mem::take(&mut self.line);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn inline_start(&mut self, _inline: Inline, _attrs: &Attrs) -> io::Result<()> {
+        Ok(())
+    }
+
+    // 链接的文本后面跟着地址
+    fn inline_end(&mut self, inline: Inline) -> io::Result<()> {
+        match inline {
+            Inline::Link { url, .. } => {
+                let url = String::from_utf8_lossy(url);
+                if !self.line.ends_with(&*url) {
+                    self.line.push_str(&format!(" ({})", url));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn text(&mut self, data: &[u8]) -> io::Result<()> {
+        self.line.push_str(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    fn space(&mut self) -> io::Result<()> {
+        self.line.push(' ');
+        Ok(())
+    }
+
+    fn tab(&mut self) -> io::Result<()> {
+        self.line.push(' ');
+        Ok(())
+    }
+
+    // 段落里的换行会重新折行
+    fn line_break(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        Ok(())
+    }
+
+    // 代码不折行，缩进4个空格
+    fn code_block(
+        &mut self,
+        _lang: &[u8],
+        content: &[u8],
+        _attrs: &Attrs,
+        _pos: Pos,
+    ) -> io::Result<()> {
+        self.before_block(false)?;
+        for line in String::from_utf8_lossy(content).lines() {
+            let prefix = self.prefix.clone();
+            self.write_str(&prefix)?;
+            self.write_str("    ")?;
+            self.write_str(line)?;
+            self.write_str("\n")?;
+        }
+        self.write_blank_line()
+    }
+
+    fn image(&mut self, src: &[u8], alt: &[u8], _title: &[u8], _attrs: &Attrs) -> io::Result<()> {
+        self.line.push_str(&String::from_utf8_lossy(alt));
+        self.line
+            .push_str(&format!(" ({})", String::from_utf8_lossy(src)));
+        Ok(())
+    }
+
+    fn math(&mut self, tex: &[u8], _display: bool, _pos: Pos) -> io::Result<()> {
+        self.line.push_str(&String::from_utf8_lossy(tex));
+        Ok(())
+    }
+
+    fn footnote_ref(&mut self, label: &[u8], _pos: Pos) -> io::Result<()> {
+        let number = self.footnote_number(label);
+        self.line.push_str(&format!("[{}]", number));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.line.trim().is_empty() {
+            self.flush_line("")?;
+        }
+
+        let footnotes = std::mem::take(&mut self.footnotes);
+        if footnotes.iter().any(|(_, content)| !content.is_empty()) {
+            self.write_str("---\n")?;
+            for (idx, (_label, content)) in footnotes.into_iter().enumerate() {
+                self.line = content;
+                self.flush_line(&format!("[{}] ", idx + 1))?;
+            }
+        }
+
+        self.buf_writer.flush()
+    }
+}
+
+// 按显示宽度折行，空白处可以断开，CJK字符之间也可以断开
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+
+    for (word, space_before) in words(text) {
+        let word_width = str_width(word);
+        let space = (space_before && !line.is_empty()) as usize;
+
+        if !line.is_empty() && line_width + space + word_width > width {
+            lines.push(std::mem::take(&mut line));
+            line_width = 0;
+        } else if space == 1 {
+            line.push(' ');
+            line_width += 1;
+        }
+        line.push_str(word);
+        line_width += word_width;
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// 切成不能再断开的词，bool表示前面有没有空白，CJK字符单独算一个词
+fn words(text: &str) -> Vec<(&str, bool)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut space_before = false;
+
+    for (idx, c) in text.char_indices() {
+        if c.is_whitespace() || char_width(c) == 2 {
+            if let Some(start) = start.take() {
+                words.push((&text[start..idx], space_before));
+                space_before = false;
+            }
+
+            if c.is_whitespace() {
+                space_before = true;
+            } else {
+                words.push((&text[idx..idx + c.len_utf8()], space_before));
+                space_before = false;
+            }
+        } else if start.is_none() {
+            start = Some(idx);
+        }
+    }
+    if let Some(start) = start {
+        words.push((&text[start..], space_before));
+    }
+
+    words
+}
+
+// ANSI的转义序列不占宽度
+pub fn str_width(s: &str) -> usize {
+    let mut width = 0;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // ESC [ 参数 结束字符
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            continue;
+        }
+        width += char_width(c);
+    }
+
+    width
+}
+
+// 东亚宽字符算两个宽度，组合用的符号不占宽度
+pub fn char_width(c: char) -> usize {
+    Chr::from(c).width()
+}
+
+#[test]
+fn test() {
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+
+    assert_eq!(vec!["aaa bb", "cccc"], wrap("aaa  bb cccc", 7));
+    assert_eq!(vec!["中文中", "文 ab"], wrap("中文中文 ab", 6));
+    assert_eq!(4, str_width("中a "));
+    assert_eq!(2, str_width("\x1b[1;35mab\x1b[0m"));
+    assert_eq!(6, str_width("cafe\u{301}😀"));
+
+    let input = "# Title\n\nsome words that need wrapping here\n\n> quoted text\n\n- [x] done\n- [ ] todo\n\nsee [site](http://a.b)[^1]\n\n[^1]: a note\n";
+    let mut parser =
+        StatefulParser::with_renderer(input.as_bytes(), TextWriter::with_width(Vec::new(), 20));
+    parser.parse_and_write().unwrap();
+    let out = String::from_utf8(parser.into_renderer().into_inner().unwrap()).unwrap();
+
+    assert_eq!(
+        "Title\n=====\n\nsome words that need\nwrapping here\n\n> quoted text\n\n[x] done\n[ ] todo\n\nsee site\n(http://a.b)[1]\n\n---\n[1] a note\n",
+        out
+    );
+}
@@ -1,12 +1,19 @@
+use crate::attr::split_trailing_attrs;
+use crate::tag::AdmonitionKind;
 use crate::tag::Tag;
 use crate::BLOCK_QUOTE_TAG;
 use crate::HEADER_TAG;
+use chr::decode;
 use chr::Chr;
 use chr::ChrIter;
+use chr::Decoded;
+use chr::InvalidByte;
+use chr::Utf8Policy;
+use chr::REPLACEMENT;
 use std::io;
 use std::io::Read;
-use std::iter::Peekable;
 use std::marker::PhantomData;
+use std::ops::Range;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Token<'a> {
@@ -15,7 +22,64 @@ pub enum Token<'a> {
     Tab,
     Ln,
     Tag(Tag),
-    PureText { data: &'a [u8], char_len: u32 },
+    PureText {
+        data: &'a [u8],
+        char_len: u32,
+    },
+    // [^label]
+    FootnoteRef {
+        label: &'a [u8],
+    },
+    // 行首的 [^label]:，space是 ':' 后面有没有一个空格，空格也一起读掉了
+    FootnoteDef {
+        label: &'a [u8],
+        space: bool,
+    },
+    // $...$ 或者 $$...$$，data是不带$的原始内容
+    Math {
+        data: &'a [u8],
+        display: bool,
+    },
+    // 行首的 > [!NOTE] 或者 :::warning Title {attrs}，title为空时使用默认的标题
+    Admonition {
+        kind: AdmonitionKind,
+        title: &'a [u8],
+        attrs: &'a [u8],
+        fenced: bool,
+    },
+    // 单独一行的 :::
+    ContainerEnd,
+    // ::: name {attrs} 到对应的 ::: 为止，content是原始的内容
+    Directive {
+        name: &'a [u8],
+        args: &'a [u8],
+        content: &'a [u8],
+        closed: bool,
+    },
+    // {{< name args >}}
+    Shortcode {
+        name: &'a [u8],
+        args: &'a [u8],
+    },
+    // 行尾的 {#id .class key=value}，包括大括号
+    Attrs {
+        data: &'a [u8],
+    },
+    // [text](url "title"){attrs} 或者 ![alt](src "title"){attrs}
+    Link {
+        text: &'a [u8],
+        url: &'a [u8],
+        title: &'a [u8],
+        attrs: &'a [u8],
+        image: bool,
+    },
+    // 行首的 ```lang {attrs} 到对应的 ``` 为止，content是原始的内容
+    CodeBlock {
+        lang: &'a [u8],
+        attrs: &'a [u8],
+        content: &'a [u8],
+        closed: bool,
+    },
 }
 
 impl std::fmt::Debug for Token<'_> {
@@ -31,6 +95,58 @@ impl std::fmt::Debug for Token<'_> {
             } => {
                 write!(f, "{:?}", std::str::from_utf8(arg0))
             }
+            Self::FootnoteRef { label } => {
+                write!(f, "FootnoteRef({:?})", std::str::from_utf8(label))
+            }
+            Self::FootnoteDef { label, .. } => {
+                write!(f, "FootnoteDef({:?})", std::str::from_utf8(label))
+            }
+            Self::Math { data, display } => {
+                write!(f, "Math({:?}, {})", std::str::from_utf8(data), display)
+            }
+            Self::Admonition {
+                kind,
+                title,
+                fenced,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Admonition({:?}, {:?}, {})",
+                    kind,
+                    std::str::from_utf8(title),
+                    fenced
+                )
+            }
+            Self::ContainerEnd => write!(f, "ContainerEnd"),
+            Self::Directive { name, args, .. } => write!(
+                f,
+                "Directive({:?}, {:?})",
+                std::str::from_utf8(name),
+                std::str::from_utf8(args)
+            ),
+            Self::Shortcode { name, args } => write!(
+                f,
+                "Shortcode({:?}, {:?})",
+                std::str::from_utf8(name),
+                std::str::from_utf8(args)
+            ),
+            Self::Attrs { data } => write!(f, "Attrs({:?})", std::str::from_utf8(data)),
+            Self::Link {
+                text, url, image, ..
+            } => write!(
+                f,
+                "Link({:?}, {:?}, {})",
+                std::str::from_utf8(text),
+                std::str::from_utf8(url),
+                image
+            ),
+            Self::CodeBlock { lang, attrs, .. } => write!(
+                f,
+                "CodeBlock({:?}, {:?})",
+                std::str::from_utf8(lang),
+                std::str::from_utf8(attrs)
+            ),
         }
     }
 }
@@ -55,32 +171,220 @@ impl<'a> From<&'a CharCache> for Token<'a> {
     }
 }
 
+// parser从这里取token，token可以借用tokenizer自己的缓冲，所以不用Iterator
+// 每个token和它在输入里的byte范围一起返回，范围首尾相接，没有token的byte（比如结尾的 \）算在下一个里
+pub trait TokenSource {
+    fn next_token(&mut self) -> Option<io::Result<(Token<'_>, Range<u64>)>>;
+
+    // 不合法的utf8怎么处理，只影响还没读到的输入
+    fn set_utf8_policy(&mut self, _policy: Utf8Policy) {}
+
+    // 到目前为止读到的不合法的byte，按位置排好序
+    fn take_invalid(&mut self) -> Vec<InvalidByte> {
+        Vec::new()
+    }
+
+    // 上一个token里offset所在的字符前面有几个换行，所在的行前面有几个字符
+    // 用来算不合法的byte的行和列，不知道的时候按token开头算
+    fn locate(&self, _offset: u64) -> Option<(u32, u32)> {
+        None
+    }
+}
+
 pub struct Tokenizer<'a, R: Read> {
-    iter: Peekable<ChrIter<R>>,
+    iter: Input<R>,
     state: State,
     cache: CharCache,
     temp_chr: Chr,
-    /* from above temp_chr OR cache */
+    // 长度不固定的内容放在这里，比如公式（开头是 $ 或者 $$）、容器的标题
+    text_buf: Vec<u8>,
+    // 上一个token的byte范围
+    span: Range<u64>,
+    /* from above temp_chr OR cache OR text_buf */
     _marker: PhantomData<&'a [u8]>,
 }
 
+// ChrIter外面加一层，读过的字符可以退回去重新读，比如没有闭合的公式
+struct Input<R: Read> {
+    iter: ChrIter<R>,
+    // 退回来的字符和它们的位置，先读这里
+    back: Vec<(io::Result<Chr>, u64)>,
+    back_pos: usize,
+    // Some的时候记下读过的字符，rewind时退回去
+    record: Option<Vec<(io::Result<Chr>, u64)>>,
+    // 还没有取走的不合法的byte
+    invalid: Vec<InvalidByte>,
+    // 当前token里读过的换行数和这一行读过的字符数，mark的时候的值rewind时恢复
+    lines: u32,
+    chars: u32,
+    mark_at: (u32, u32),
+    // 当前token里读到的不合法的byte在第几行、前面有几个字符
+    located: Vec<(u64, u32, u32)>,
+}
+
+impl<R: Read> Input<R> {
+    fn new(iter: ChrIter<R>) -> Self {
+        Self {
+            iter,
+            back: Vec::new(),
+            back_pos: 0,
+            record: None,
+            invalid: Vec::new(),
+            lines: 0,
+            chars: 0,
+            mark_at: (0, 0),
+            located: Vec::new(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&io::Result<Chr>> {
+        match self.back.get(self.back_pos) {
+            Some((chr, _)) => Some(chr),
+            None => self.iter.peek(),
+        }
+    }
+
+    // 下一个要读的字符在输入里的位置
+    fn pos(&self) -> u64 {
+        match self.back.get(self.back_pos) {
+            Some((_, pos)) => *pos,
+            None => self.iter.position(),
+        }
+    }
+
+    // 在退回来的字符或者记录中的时候返回空，一个一个读
+    // 成段的纯文本就是整个token，后面没有别的字符了，不用计数
+    fn next_plain(&mut self, set: &[u8]) -> &[u8] {
+        if self.back_pos < self.back.len() || self.record.is_some() {
+            return &[];
+        }
+        self.iter.next_plain(set)
+    }
+
+    // end之前的不合法的byte，peek到的后面的字符里的留着
+    fn take_invalid(&mut self, end: u64) -> Vec<InvalidByte> {
+        self.invalid.extend(self.iter.take_invalid());
+        let n = self.invalid.partition_point(|x| x.offset < end);
+        self.invalid.drain(..n).collect()
+    }
+
+    // 新的token从这里开始
+    fn start_token(&mut self) {
+        self.lines = 0;
+        self.chars = 0;
+        self.located.clear();
+    }
+
+    // 读掉了pos处的字符，它里面的不合法的byte记下位置
+    fn count(&mut self, chr: Chr, pos: u64) {
+        // 不合法的byte读出来只会是U+FFFD或者单独的一个高位byte
+        if chr == REPLACEMENT || matches!(chr, Chr::One([b]) if b >= 0x80) {
+            self.invalid.extend(self.iter.take_invalid());
+            let end = self.pos();
+            let from = self.invalid.partition_point(|x| x.offset < pos);
+            for invalid in self.invalid[from..].iter().take_while(|x| x.offset < end) {
+                self.located.push((invalid.offset, self.lines, self.chars));
+            }
+        }
+
+        if chr == b'\n' {
+            self.lines += 1;
+            self.chars = 0;
+        } else {
+            self.chars += 1;
+        }
+    }
+
+    fn locate(&self, offset: u64) -> Option<(u32, u32)> {
+        self.located
+            .iter()
+            .rev()
+            .find(|x| x.0 == offset)
+            .map(|&(_offset, lines, chars)| (lines, chars))
+    }
+
+    // 从这里开始记录
+    fn mark(&mut self) {
+        self.record = Some(Vec::new());
+        self.mark_at = (self.lines, self.chars);
+    }
+
+    // 不再记录
+    fn forget(&mut self) {
+        self.record = None;
+    }
+
+    // 把mark之后读过的字符退回去
+    fn rewind(&mut self) {
+        let mut back = self.record.take().unwrap_or_default();
+        back.extend(self.back.drain(self.back_pos..));
+        self.back = back;
+        self.back_pos = 0;
+        (self.lines, self.chars) = self.mark_at;
+    }
+}
+
+impl<R: Read> Iterator for Input<R> {
+    type Item = io::Result<Chr>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pos = self.pos();
+        let chr = match self.back.get(self.back_pos) {
+            Some(&(Ok(chr), _)) => {
+                self.back_pos += 1;
+                if self.back_pos == self.back.len() {
+                    self.back.clear();
+                    self.back_pos = 0;
+                }
+                Some(Ok(chr))
+            }
+            // 只退回读成功的字符
+            Some((Err(_), _)) => unreachable!(),
+            None => self.iter.next(),
+        };
+        if let Some(Ok(chr)) = chr {
+            self.count(chr, pos);
+            if let Some(record) = &mut self.record {
+                record.push((Ok(chr), pos));
+            }
+        }
+        chr
+    }
+}
+
 #[derive(Clone, Copy)]
-enum State {
+pub(crate) enum State {
     Start,
     LineFeed,
     Tab(u32),
     Other,
 }
 
-// max = 6 # + 4-byte u8 char
-const ARRAY_CAP: usize = 7 + 3;
-#[derive(Default)]
+// 行内的纯文本在这些byte前面断开，行首的标记只在换行之后才看
+pub(crate) const TEXT_STOP: &[u8] = b"\\ \t\n\r${[!";
+
+// 脚注的label最多32个byte，超过了就当做纯文本
+pub const FOOTNOTE_LABEL_CAP: usize = 32;
+// "[^" 的长度
+pub(crate) const FOOTNOTE_PREFIX_LEN: usize = 2;
+// max = max(6 # + 4-byte u8 char, "[^" + label)
+pub(crate) const ARRAY_CAP: usize = FOOTNOTE_PREFIX_LEN + FOOTNOTE_LABEL_CAP;
 struct CharCache {
     arr: [u8; ARRAY_CAP],
     len: usize,
     char_len: u32,
 }
 
+impl Default for CharCache {
+    fn default() -> Self {
+        Self {
+            arr: [0; ARRAY_CAP],
+            len: 0,
+            char_len: 0,
+        }
+    }
+}
+
 impl CharCache {
     fn has_element(&self) -> bool {
         self.len != 0
@@ -100,7 +404,8 @@ impl CharCache {
         for &b in <&Chr as Into<&[u8]>>::into(&chr) {
             self.put(b);
         }
-        self.char_len += 1;
+        // put按byte计数，一个字符只算1
+        self.char_len -= <&Chr as Into<&[u8]>>::into(&chr).len() as u32 - 1;
     }
 
     fn get(&self) -> &[u8] {
@@ -115,15 +420,32 @@ impl CharCache {
 
 impl<'a, R: Read + 'a> Tokenizer<'a, R> {
     pub fn new(r: R) -> Self {
+        Self::with_utf8_policy(r, Utf8Policy::PassThrough)
+    }
+
+    // 不合法的utf8返回错误、换成U+FFFD或者原样保留
+    pub fn with_utf8_policy(r: R, policy: Utf8Policy) -> Self {
         Self {
-            iter: ChrIter::new(r).peekable(),
+            iter: Input::new(ChrIter::with_policy(r, policy)),
             cache: CharCache::default(),
             state: State::Start,
+            text_buf: Vec::new(),
+            span: 0..0,
             _marker: PhantomData,
-            temp_chr: Chr::One([b' ']),
+            temp_chr: Chr::One(*b" "),
         }
     }
 
+    // 上一个token在输入里的byte范围，用Transcoder的时候是转成utf8之后的位置
+    pub fn span(&self) -> Range<u64> {
+        self.span.clone()
+    }
+
+    // 到上一个token为止遇到的不合法的byte和它们的位置
+    pub fn take_invalid(&mut self) -> Vec<InvalidByte> {
+        self.iter.take_invalid(self.span.end)
+    }
+
     fn get_lifetime(&self) -> &'a Self {
         unsafe { &*(self as *const Self) }
     }
@@ -133,34 +455,760 @@ impl<'a, R: Read + 'a> Tokenizer<'a, R> {
         (&self.get_lifetime().temp_chr).into()
     }
 
+    // 普通的ascii字符后面直到下一个特殊byte的纯文本，一次返回
+    fn get_text_run(&mut self, b: u8) -> Token<'a> {
+        self.text_buf.clear();
+        self.text_buf.push(b);
+        let run = self.iter.next_plain(TEXT_STOP);
+        self.text_buf.extend_from_slice(run);
+
+        let data = &self.get_lifetime().text_buf[..];
+        Token::PureText {
+            data,
+            char_len: chr_count(data),
+        }
+    }
+
     fn get_cache(&mut self) -> Option<io::Result<Token<'a>>> {
         let ret = Some(Ok((&self.get_lifetime().cache).into()));
         self.cache.clear();
         ret
     }
-}
 
-impl<'a, R: Read + 'a> Iterator for Tokenizer<'a, R> {
-    type Item = io::Result<Token<'a>>;
+    // 只有peek到Err时调用，把这个Err取出来
+    fn take_err(&mut self) -> Option<io::Result<Token<'a>>> {
+        match self.iter.next() {
+            Some(Err(e)) => Some(Err(e)),
+            _ => unreachable!(),
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    fn get_math(&mut self, display: bool) -> Option<io::Result<Token<'a>>> {
+        let prefix_len = if display { 2 } else { 1 };
+        let data = &self.get_lifetime().text_buf[prefix_len..];
+
+        Some(Ok(Token::Math { data, display }))
+    }
+
+    // 公式没有闭合，或者不是容器，读过的内容当做纯文本返回
+    fn get_text_buf(&mut self) -> Option<io::Result<Token<'a>>> {
+        let data = &self.get_lifetime().text_buf[..];
+
+        Some(Ok(Token::PureText {
+            data,
+            char_len: chr_count(data),
+        }))
+    }
+
+    // 没有匹配上，只有开头的marker是文本，mark之后读过的字符退回去重新解析
+    fn unmatched(&mut self, marker: &[u8]) -> Option<io::Result<Token<'a>>> {
+        self.iter.rewind();
+        self.text_buf.clear();
+        self.text_buf.extend_from_slice(marker);
+        self.get_text_buf()
+    }
+
+    fn peek_is(&mut self, b: u8) -> bool {
+        matches!(self.iter.peek(), Some(Ok(c)) if *c == b)
+    }
+
+    // 已经读到了 '$'，公式里的内容原样保留，不做任何解析
+    // 行内公式：开头的 $ 后面不能是空白，结尾的 $ 前面不能是空白、后面不能是数字，不能跨行
+    // 块级公式：$$ ... $$，可以跨行，但是遇到空行就认为没有闭合
+    // 没有闭合时只把开头的 $ 或者 $$ 当做文本，后面的内容退回去重新解析
+    fn math(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.text_buf.clear();
+        self.text_buf.push(b'$');
+
+        let display = self.peek_is(b'$');
+        if display {
+            self.iter.next();
+            self.text_buf.push(b'$');
+        } else {
+            match self.iter.peek() {
+                Some(Ok(Chr::One([b' ' | b'\t' | b'\n' | b'\r']))) | None => {
+                    return self.get_text_buf();
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => {}
+            }
+        }
+
+        let prefix_len = self.text_buf.len();
+        self.iter.mark();
+        match self.read_math(display) {
+            Ok(true) => {
+                self.iter.forget();
+                self.get_math(display)
+            }
+            Ok(false) => {
+                self.iter.rewind();
+                self.text_buf.truncate(prefix_len);
+                self.get_text_buf()
+            }
+            Err(e) => {
+                self.iter.forget();
+                Some(Err(e))
+            }
+        }
+    }
+
+    // 读公式的内容到text_buf，返回有没有闭合
+    fn read_math(&mut self, display: bool) -> io::Result<bool> {
+        let mut last = b'$';
+        loop {
+            let c = match self.iter.peek() {
+                Some(Ok(c)) => *c,
+                Some(Err(_e)) => return Err(self.iter.next().unwrap().unwrap_err()),
+                None => return Ok(false),
+            };
+
+            if display {
+                if (c == b'\n' && last == b'\n') || (c == b'\r' && matches!(last, b'\n' | b'\r')) {
+                    return Ok(false);
+                }
+            } else if c == b'\n' || c == b'\r' {
+                return Ok(false);
+            }
+
+            self.iter.next();
+
+            if c == b'$' {
+                if display {
+                    if self.peek_is(b'$') {
+                        self.iter.next();
+                        return Ok(true);
+                    }
+                } else if !matches!(last, b' ' | b'\t')
+                    && !matches!(self.iter.peek(), Some(Ok(Chr::One([b'0'..=b'9']))))
+                {
+                    return Ok(true);
+                }
+            } else if c == b'\\' {
+                // \$ 不会结束公式
+                self.text_buf.push(b'\\');
+                match self.iter.peek() {
+                    Some(Ok(next)) if *next == b'$' || *next == b'\\' => {
+                        let next = *next;
+                        self.iter.next();
+                        self.text_buf.extend_from_slice((&next).into());
+                        last = 0;
+                        continue;
+                    }
+                    _ => {
+                        last = b'\\';
+                        continue;
+                    }
+                }
+            }
+
+            self.text_buf.extend_from_slice((&c).into());
+            last = match c {
+                Chr::One([b]) => b,
+                _ => 0,
+            };
+        }
+    }
+
+    // 如果下一个是换行就读掉，返回是否到了行尾
+    fn take_line_end(&mut self) -> Option<io::Result<bool>> {
+        match self.iter.peek() {
+            None => Some(Ok(true)),
+            Some(Err(_e)) => match self.iter.next() {
+                Some(Err(e)) => Some(Err(e)),
+                _ => unreachable!(),
+            },
+            Some(Ok(c)) => {
+                if *c == b'\r' {
+                    self.iter.next();
+                    if self.peek_is(b'\n') {
+                        self.iter.next();
+                    }
+                } else if *c == b'\n' {
+                    self.iter.next();
+                } else {
+                    return Some(Ok(false));
+                }
+
+                self.state = State::LineFeed;
+                Some(Ok(true))
+            }
+        }
+    }
+
+    // 已经读到了 "> "，下一个是 '['，尝试解析 GitHub 的 > [!NOTE]，必须单独一行
+    // 不匹配的话先返回BlockQuote，从 '[' 开始重新解析
+    fn alert(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.iter.mark();
+        self.iter.next();
+        self.cache.put(b'[');
+
+        if !self.peek_is(b'!') {
+            return self.not_alert();
+        }
+        self.iter.next();
+        self.cache.put(b'!');
+
+        loop {
+            match self.iter.peek() {
+                Some(Ok(Chr::One([b])))
+                    if b.is_ascii_alphabetic() && self.cache.len < ARRAY_CAP =>
+                {
+                    let b = *b;
+                    self.iter.next();
+                    self.cache.put(b);
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => break,
+            }
+        }
+
+        let kind = AdmonitionKind::from_alert_name(&self.cache.get()[2..]);
+        if kind.is_none() || !self.peek_is(b']') {
+            return self.not_alert();
+        }
+        self.iter.next();
+
+        while self.peek_is(b' ') {
+            self.iter.next();
+        }
+
+        match self.take_line_end()? {
+            Ok(true) => {
+                self.cache.clear();
+                Some(Ok(Token::Admonition {
+                    kind: kind.unwrap(),
+                    title: &[],
+                    attrs: &[],
+                    fenced: false,
+                }))
+            }
+            Ok(false) => self.not_alert(),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    fn not_alert(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.cache.clear();
+        self.iter.rewind();
+        Some(Ok(Token::Tag(Tag::BlockQuote)))
+    }
+
+    // 把这一行剩下的内容读到text_buf的末尾，换行读掉但是不放进去
+    // 返回是否读到了内容，EOF并且没有内容时返回false
+    fn read_raw_line(&mut self) -> io::Result<bool> {
+        let mut has_content = false;
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c != b'\n' && *c != b'\r' => {
+                    let c = *c;
+                    self.iter.next();
+                    self.text_buf.extend_from_slice((&c).into());
+                    has_content = true;
+                }
+                Some(Err(_e)) => match self.iter.next() {
+                    Some(Err(e)) => return Err(e),
+                    _ => unreachable!(),
+                },
+                Some(Ok(_c)) => {
+                    if let Some(Err(e)) = self.take_line_end() {
+                        return Err(e);
+                    }
+                    return Ok(true);
+                }
+                None => return Ok(has_content),
+            }
+        }
+    }
+
+    // 已经读到了行首的 ':'，尝试解析 :::kind Title、::: name {attrs} 和单独一行的 :::
+    fn fence(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.cache.put(b':');
+        for _ in 0..2 {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c == b':' => {
+                    self.iter.next();
+                    self.cache.put(b':');
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => return self.get_cache(),
+            }
+        }
+
+        // 把这一行剩下的内容都读出来，先不读换行，不是容器的话只有 ::: 是文本
+        self.text_buf.clear();
+        self.text_buf.extend_from_slice(b":::");
+        self.iter.mark();
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c != b'\n' && *c != b'\r' => {
+                    let c = *c;
+                    self.iter.next();
+                    self.text_buf.extend_from_slice((&c).into());
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => break,
+            }
+        }
+        self.cache.clear();
+
+        let line = &self.get_lifetime().text_buf[..];
+        let (name, rest) = match fence_line(line) {
+            Some(x) => x,
+            None => {
+                if let Err(e) = self.take_line_end()? {
+                    return Some(Err(e));
+                }
+                return Some(Ok(Token::ContainerEnd));
+            }
+        };
+
+        if name.is_empty() || !name[0].is_ascii_alphabetic() {
+            return self.unmatched(b":::");
+        }
+        self.iter.forget();
+
+        if let Some(kind) = AdmonitionKind::from_name(name) {
+            if let Err(e) = self.take_line_end()? {
+                return Some(Err(e));
+            }
+            let (title, attrs) = split_trailing_attrs(rest);
+            return Some(Ok(Token::Admonition {
+                kind,
+                title,
+                attrs,
+                fenced: true,
+            }));
+        }
+
+        // 其他的名字都是指令，内容原样读出来，直到对应的 ::: 为止，中间可以嵌套
+        let name_range = offset_range(line, name);
+        let args_range = offset_range(line, rest);
+        let content_start = self.text_buf.len() + 1;
+        let mut depth = 1;
+        let mut closed = false;
+
+        if let Err(e) = self.take_line_end()? {
+            return Some(Err(e));
+        }
+        loop {
+            self.text_buf.push(b'\n');
+            let line_start = self.text_buf.len();
+
+            match self.read_raw_line() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Some(Err(e)),
+            }
+
+            let line = &self.text_buf[line_start..];
+            if trim(line).starts_with(b":::") {
+                match fence_line(line) {
+                    None => depth -= 1,
+                    Some((name, _rest)) if !name.is_empty() => depth += 1,
+                    _ => {}
+                }
+            }
+            if depth == 0 {
+                self.text_buf.truncate(line_start);
+                closed = true;
+                break;
+            }
+        }
+
+        let buf = &self.get_lifetime().text_buf;
+        let content_end = buf.len().saturating_sub(1).max(content_start);
+        let content = if content_start <= buf.len() {
+            &buf[content_start..content_end]
+        } else {
+            &[]
+        };
+        Some(Ok(Token::Directive {
+            name: &buf[name_range],
+            args: &buf[args_range],
+            content,
+            closed,
+        }))
+    }
+
+    // 把这一行的内容读到text_buf的末尾，直到读到end为止（包括end），不读换行
+    // 返回是否读到了end
+    fn read_until(&mut self, end: u8) -> io::Result<bool> {
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c != b'\n' && *c != b'\r' => {
+                    let c = *c;
+                    self.iter.next();
+                    self.text_buf.extend_from_slice((&c).into());
+
+                    if c == end {
+                        return Ok(true);
+                    }
+                }
+                Some(Err(_e)) => match self.iter.next() {
+                    Some(Err(e)) => return Err(e),
+                    _ => unreachable!(),
+                },
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // 链接的 (url "title")，url里的括号要配对，和CommonMark一样
+    fn read_link_inner(&mut self) -> io::Result<bool> {
+        let mut depth = 0;
+        let mut in_url = true;
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c != b'\n' && *c != b'\r' => {
+                    let c = *c;
+                    self.iter.next();
+                    self.text_buf.extend_from_slice((&c).into());
+
+                    if (c == b' ' || c == b'\t') && depth == 0 {
+                        in_url = false;
+                    } else if c == b'(' && in_url {
+                        depth += 1;
+                    } else if c == b')' {
+                        if depth == 0 {
+                            return Ok(true);
+                        }
+                        depth -= 1;
+                    }
+                }
+                Some(Err(_e)) => match self.iter.next() {
+                    Some(Err(e)) => return Err(e),
+                    _ => unreachable!(),
+                },
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // 已经读到了 '{'，{{< 开头的是短代码，否则尝试解析行尾的属性列表 {#id .class key=value}
+    // 属性列表后面只能有空白，不匹配的话只有 { 是文本
+    fn brace(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.text_buf.clear();
+        self.text_buf.push(b'{');
+        self.iter.mark();
+
+        if self.peek_is(b'{') {
+            return self.shortcode();
+        }
+
+        match self.read_until(b'}') {
+            Ok(true) => {}
+            Ok(false) => return self.unmatched(b"{"),
+            Err(e) => return Some(Err(e)),
+        }
+        let attrs_len = self.text_buf.len();
+
+        while self.peek_is(b' ') || self.peek_is(b'\t') {
+            self.iter.next();
+        }
+        match self.iter.peek() {
+            Some(Ok(c)) if *c != b'\n' && *c != b'\r' => return self.unmatched(b"{"),
+            Some(Err(_e)) => return self.take_err(),
+            _ => {}
+        }
+
+        Some(Ok(Token::Attrs {
+            data: &self.get_lifetime().text_buf[..attrs_len],
+        }))
+    }
+
+    // 已经读到了 '[' 或者 "![", 尝试解析 [text](url "title"){attrs}，不能跨行
+    // 不匹配的话只有开头的 [ 或者 ! 是文本，"![" 的时候在 '[' 前面已经mark过了
+    fn link(&mut self, image: bool) -> Option<io::Result<Token<'a>>> {
+        if !image {
+            self.iter.mark();
+        }
+        let marker: &[u8] = if image { b"!" } else { b"[" };
+        self.text_buf.clear();
+        self.text_buf
+            .extend_from_slice(if image { b"![" } else { b"[" });
+        let text_start = self.text_buf.len();
+
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) => {
+                    let c = *c;
+                    if c == b']' {
+                        self.iter.next();
+                        break;
+                    }
+                    if matches!(c, Chr::One([b'\n' | b'\r' | b'['])) {
+                        return self.unmatched(marker);
+                    }
+
+                    self.iter.next();
+                    // 转义的字符原样放进去
+                    if c == b'\\' {
+                        match self.iter.peek() {
+                            Some(Ok(next)) if *next != b'\n' && *next != b'\r' => {
+                                let next = *next;
+                                self.iter.next();
+                                self.text_buf.extend_from_slice((&next).into());
+                                continue;
+                            }
+                            Some(Err(_e)) => return self.take_err(),
+                            _ => {}
+                        }
+                    }
+                    self.text_buf.extend_from_slice((&c).into());
+                }
+                Some(Err(_e)) => return self.take_err(),
+                None => return self.unmatched(marker),
+            }
+        }
+        let text_end = self.text_buf.len();
+        self.text_buf.push(b']');
+
+        if !self.peek_is(b'(') {
+            return self.unmatched(marker);
+        }
+        self.iter.next();
+        self.text_buf.push(b'(');
+
+        let inner_start = self.text_buf.len();
+        match self.read_link_inner() {
+            Ok(true) => {}
+            Ok(false) => return self.unmatched(marker),
+            Err(e) => return Some(Err(e)),
+        }
+        let inner_end = self.text_buf.len() - 1;
+
+        let mut attrs_range = 0..0;
+        if self.peek_is(b'{') {
+            // 属性没有结束的话链接没有属性，{ 重新解析
+            self.iter.forget();
+            self.iter.mark();
+            self.iter.next();
+            let attrs_start = self.text_buf.len();
+            self.text_buf.push(b'{');
+
+            match self.read_until(b'}') {
+                Ok(true) => attrs_range = attrs_start..self.text_buf.len(),
+                Ok(false) => {
+                    self.iter.rewind();
+                    self.text_buf.truncate(attrs_start);
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let buf = &self.get_lifetime().text_buf;
+        let inner = trim(&buf[inner_start..inner_end]);
+        let url_len = inner
+            .iter()
+            .take_while(|&&b| b != b' ' && b != b'\t')
+            .count();
+        let title = trim(&inner[url_len..]);
+        let title = match title {
+            [q @ (b'"' | b'\''), rest @ .., end] if q == end => rest,
+            _ => title,
+        };
+
+        Some(Ok(Token::Link {
+            text: &buf[text_start..text_end],
+            url: &inner[..url_len],
+            title,
+            attrs: &buf[attrs_range],
+            image,
+        }))
+    }
+
+    // 已经读到了行首的 '`' 或者 '~'，至少3个才是代码块，后面是语言和属性列表
+    // 内容原样读出来，直到不少于开头数量的结束符为止
+    fn code_block(&mut self, fence: u8) -> Option<io::Result<Token<'a>>> {
+        self.cache.put(fence);
+        while self.peek_is(fence) && self.cache.len < ARRAY_CAP {
+            self.iter.next();
+            self.cache.put(fence);
+        }
+        let fence_len = self.cache.len;
+        if fence_len < 3 {
+            return self.get_cache();
+        }
+        self.cache.clear();
+
+        self.text_buf.clear();
+        if let Err(e) = self.read_raw_line() {
+            return Some(Err(e));
+        }
+        let info_end = self.text_buf.len();
+
+        let mut closed = false;
+        loop {
+            self.text_buf.push(b'\n');
+            let line_start = self.text_buf.len();
+
+            match self.read_raw_line() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => return Some(Err(e)),
+            }
+
+            let line = trim(&self.text_buf[line_start..]);
+            if line.len() >= fence_len && line.iter().all(|&b| b == fence) {
+                self.text_buf.truncate(line_start);
+                closed = true;
+                break;
+            }
+        }
+
+        let buf = &self.get_lifetime().text_buf;
+        // 去掉info后面的换行和最后一行的换行
+        let content = &buf[(info_end + 1).min(buf.len() - 1)..buf.len() - 1];
+        let info = trim(&buf[..info_end]);
+        let lang_len = info
+            .iter()
+            .take_while(|&&b| b != b' ' && b != b'\t' && b != b'{')
+            .count();
+
+        Some(Ok(Token::CodeBlock {
+            lang: &info[..lang_len],
+            attrs: trim(&info[lang_len..]),
+            content,
+            closed,
+        }))
+    }
+
+    // 已经读到了 "{{"，尝试解析 {{< name args >}}，不能跨行
+    fn shortcode(&mut self) -> Option<io::Result<Token<'a>>> {
+        for b in [b'{', b'<'] {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c == b => {
+                    self.iter.next();
+                    self.text_buf.push(b);
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => return self.unmatched(b"{"),
+            }
+        }
+
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) if *c != b'\n' && *c != b'\r' => {
+                    let c = *c;
+                    self.iter.next();
+                    self.text_buf.extend_from_slice((&c).into());
+
+                    if self.text_buf.len() > 6 && self.text_buf.ends_with(b">}}") {
+                        let buf = &self.get_lifetime().text_buf;
+                        let inner = trim(&buf[3..buf.len() - 3]);
+                        let name_len = inner
+                            .iter()
+                            .take_while(|&&b| b != b' ' && b != b'\t')
+                            .count();
+
+                        if name_len == 0 {
+                            return self.unmatched(b"{");
+                        }
+                        return Some(Ok(Token::Shortcode {
+                            name: &inner[..name_len],
+                            args: trim(&inner[name_len..]),
+                        }));
+                    }
+                }
+                Some(Err(_e)) => return self.take_err(),
+                _ => return self.unmatched(b"{"),
+            }
+        }
+    }
+
+    // 已经读到了 '['，尝试解析 [^label]，如果在行首并且后面跟着 ':' 就是脚注的定义，不是 [^ 的话当做链接
+    // 不匹配的话只有 [ 是文本
+    fn footnote(&mut self, line_start: bool) -> Option<io::Result<Token<'a>>> {
+        self.iter.mark();
+        match self.iter.peek() {
+            Some(Ok(c)) if *c == b'^' => {
+                self.iter.next();
+                self.cache.put(b'[');
+                self.cache.put(b'^');
+            }
+            Some(Err(_e)) => return self.take_err(),
+            _ => return self.link(false),
+        }
+
+        loop {
+            match self.iter.peek() {
+                Some(Ok(c)) => {
+                    let c = *c;
+
+                    if c == b']' {
+                        // [^] 不是脚注
+                        if self.cache.len == FOOTNOTE_PREFIX_LEN {
+                            return self.not_footnote();
+                        }
+                        self.iter.next();
+                        break;
+                    }
+
+                    let byte_len = <&Chr as Into<&[u8]>>::into(&c).len();
+                    if matches!(c, Chr::One([b' ' | b'\t' | b'\n' | b'\r' | b'[']))
+                        || self.cache.len + byte_len > ARRAY_CAP
+                    {
+                        return self.not_footnote();
+                    }
+
+                    self.iter.next();
+                    self.cache.put_chr(c);
+                }
+                Some(Err(_e)) => return self.take_err(),
+                None => return self.not_footnote(),
+            }
+        }
+
+        let label_len = self.cache.len;
+        self.cache.clear();
+        let label = &self.get_lifetime().cache.arr[FOOTNOTE_PREFIX_LEN..label_len];
+
+        if line_start {
+            if let Some(Ok(c)) = self.iter.peek() {
+                if *c == b':' {
+                    self.iter.next();
+                    let space = self.peek_is(b' ');
+                    if space {
+                        self.iter.next();
+                    }
+
+                    return Some(Ok(Token::FootnoteDef { label, space }));
+                }
+            }
+        }
+
+        Some(Ok(Token::FootnoteRef { label }))
+    }
+
+    fn not_footnote(&mut self) -> Option<io::Result<Token<'a>>> {
+        self.cache.clear();
+        self.unmatched(b"[")
+    }
+}
+
+impl<'a, R: Read + 'a> Tokenizer<'a, R> {
+    fn read_token(&mut self) -> Option<io::Result<Token<'a>>> {
         'outer: loop {
             match self.iter.next()? {
                 Ok(chr) => {
                     return Some(Ok(if let Chr::One(b) = chr {
                         match unsafe { *b.get_unchecked(0) } {
-                            b'\\' => match self.iter.peek() {
-                                Some(res) => match res {
-                                    Ok(chr) => {
-                                        let chr = *chr;
-                                        self.iter.next();
-                                        self.state = State::Other;
-
-                                        self.get_pure_text(chr)
-                                    }
-                                    Err(_e) => continue 'outer,
-                                },
-                                None => return None,
+                            b'\\' => match self.iter.peek()? {
+                                // 行尾的 \ 是普通文本，不转义换行
+                                Ok(next) if *next == b'\n' || *next == b'\r' => {
+                                    self.state = State::Other;
+
+                                    self.get_pure_text(chr)
+                                }
+                                Ok(chr) => {
+                                    let chr = *chr;
+                                    self.iter.next();
+                                    self.state = State::Other;
+
+                                    self.get_pure_text(chr)
+                                }
+                                Err(_e) => continue 'outer,
                             },
                             b' ' => {
                                 self.state = State::Other;
@@ -200,6 +1248,27 @@ impl<'a, R: Read + 'a> Iterator for Tokenizer<'a, R> {
                                 let last_state = self.state;
                                 self.state = State::Other;
 
+                                if b == b'$' {
+                                    return self.math();
+                                }
+
+                                if b == b'{' {
+                                    return self.brace();
+                                }
+
+                                if b == b'!' && self.peek_is(b'[') {
+                                    self.iter.mark();
+                                    self.iter.next();
+                                    return self.link(true);
+                                }
+
+                                if b == b'[' {
+                                    return self.footnote(matches!(
+                                        last_state,
+                                        State::LineFeed | State::Start
+                                    ));
+                                }
+
                                 match last_state {
                                     // 如果上个状态是换行或Start，才进行Tag的解析，方便后续的Parser的操作
                                     State::LineFeed | State::Start => match b {
@@ -207,100 +1276,78 @@ impl<'a, R: Read + 'a> Iterator for Tokenizer<'a, R> {
                                             let mut head_level = 1;
                                             self.cache.put(HEADER_TAG);
 
-                                            while let Some(res) = self.iter.next() {
-                                                match res {
-                                                    Ok(c) => {
-                                                        self.cache.put_chr(c);
-
-                                                        if c == HEADER_TAG {
-                                                            head_level += 1;
-
-                                                            // 如果有7个#了，就把这7个#当做纯文本对待
-                                                            if head_level == 7 {
-                                                                return self.get_cache();
-                                                            }
-                                                        } else if c == b' ' {
-                                                            break;
-                                                        } else {
+                                            // 后面不是空格的话这些 # 是文本
+                                            loop {
+                                                match self.iter.peek() {
+                                                    Some(Ok(c)) if *c == HEADER_TAG => {
+                                                        self.iter.next();
+                                                        self.cache.put(HEADER_TAG);
+                                                        head_level += 1;
+
+                                                        // 如果有7个#了，就把这7个#当做纯文本对待
+                                                        if head_level == 7 {
                                                             return self.get_cache();
                                                         }
                                                     }
-                                                    Err(_e) => continue 'outer,
+                                                    Some(Ok(c)) if *c == b' ' => {
+                                                        self.iter.next();
+                                                        break;
+                                                    }
+                                                    Some(Ok(_c)) => return self.get_cache(),
+                                                    Some(Err(_e)) => return self.take_err(),
+                                                    None => break,
                                                 }
                                             }
 
                                             self.cache.clear();
                                             Token::Tag(Tag::Header(head_level))
                                         }
-                                        crate::BLOCK_QUOTE_TAG => match self.iter.next() {
-                                            Some(e) => match e {
-                                                Ok(c) => {
-                                                    if c == b' ' {
-                                                        Token::Tag(Tag::BlockQuote)
-                                                    } else {
-                                                        self.cache.put(BLOCK_QUOTE_TAG);
-                                                        self.cache.put_chr(c);
-
-                                                        return self.get_cache();
-                                                    }
+                                        crate::BLOCK_QUOTE_TAG => {
+                                            if self.peek_is(b' ') {
+                                                self.iter.next();
+                                                if self.peek_is(b'[') {
+                                                    return self.alert();
                                                 }
-                                                Err(_e) => continue,
-                                            },
-                                            None => self.get_pure_text(BLOCK_QUOTE_TAG.into()),
-                                        },
+
+                                                Token::Tag(Tag::BlockQuote)
+                                            } else {
+                                                self.get_pure_text(BLOCK_QUOTE_TAG.into())
+                                            }
+                                        }
+                                        b':' => return self.fence(),
+                                        b'`' | b'~' => return self.code_block(b),
+                                        // 不是 - [ ] 或者 - [x] 的话只有 - 是文本
                                         b'-' => {
                                             let mut is_done_task = false;
-                                            self.cache.put(b'-');
-
-                                            for mut counter in [b' ', b'[', b'?', b']', b' '] {
-                                                match self.iter.next() {
-                                                    Some(res) => match res {
-                                                        Ok(cur_chr) => {
-                                                            if counter == b'?' {
-                                                                match cur_chr {
-                                                                    Chr::One([b' ']) => {
-                                                                        /* fall down */
-                                                                        counter = b' ';
-                                                                    }
-                                                                    Chr::One([b'x']) => {
-                                                                        counter = b'x';
-                                                                        is_done_task = true;
-                                                                    }
-                                                                    _ => {
-                                                                        self.cache.put_chr(cur_chr);
-                                                                        return self.get_cache();
-                                                                    }
-                                                                }
-                                                            } else {
-                                                                #[allow(
-                                                                    clippy::collapsible_else_if
-                                                                )]
-                                                                if cur_chr != counter {
-                                                                    self.cache.put_chr(cur_chr);
-                                                                    return self.get_cache();
-                                                                }
-                                                            }
-
-                                                            self.cache.put(counter);
-                                                        }
-                                                        Err(_e) => continue 'outer,
-                                                    },
-                                                    None => continue 'outer,
+                                            self.iter.mark();
+
+                                            for counter in *b" [?] " {
+                                                let matched = match self.iter.next() {
+                                                    Some(Ok(c)) if counter == b'?' => {
+                                                        is_done_task = c == b'x';
+                                                        c == b' ' || is_done_task
+                                                    }
+                                                    Some(Ok(c)) => c == counter,
+                                                    Some(Err(e)) => return Some(Err(e)),
+                                                    None => false,
+                                                };
+                                                if !matched {
+                                                    return self.unmatched(b"-");
                                                 }
                                             }
 
-                                            // must clear, because it means all match!
-                                            self.cache.clear();
                                             Token::Tag(Tag::TaskList(is_done_task))
                                         }
 
-                                        _ => self.get_pure_text(chr),
+                                        _ => self.get_text_run(b),
                                     },
-                                    _ => self.get_pure_text(chr),
+                                    _ => self.get_text_run(b),
                                 }
                             }
                         }
                     } else {
+                        // 读过一个字符就不在行首了，不管是不是空白
+                        self.state = State::Other;
                         self.get_pure_text(chr)
                     }));
                 }
@@ -310,22 +1357,251 @@ impl<'a, R: Read + 'a> Iterator for Tokenizer<'a, R> {
     }
 }
 
+impl<'a, R: Read + 'a> Tokenizer<'a, R> {
+    // token借用的是text_buf等自己的缓冲，下一次调用就变了
+    // 所以不能是Iterator，只通过TokenSource::next_token给出去，借用检查保证用完了才能取下一个
+    fn next_token0(&mut self) -> Option<io::Result<Token<'a>>> {
+        // 退回去只在一个token里面，不会留到下一个
+        self.iter.forget();
+        self.iter.start_token();
+        let start = self.iter.pos();
+        let token = self.read_token();
+        self.span = start..self.iter.pos();
+        token
+    }
+}
+
+impl<'a, R: Read + 'a> TokenSource for Tokenizer<'a, R> {
+    fn next_token(&mut self) -> Option<io::Result<(Token<'_>, Range<u64>)>> {
+        let token = self.next_token0()?;
+        Some(token.map(|token| (token, self.span.clone())))
+    }
+
+    fn set_utf8_policy(&mut self, policy: Utf8Policy) {
+        self.iter.iter.set_policy(policy);
+    }
+
+    fn take_invalid(&mut self) -> Vec<InvalidByte> {
+        self.iter.take_invalid(self.span.end)
+    }
+
+    fn locate(&self, offset: u64) -> Option<(u32, u32)> {
+        self.iter.locate(offset)
+    }
+}
+
 impl Token<'_> {
     pub fn char_len(self) -> u32 {
         match self {
             Token::Space => 1,
             Token::Tab => 1,
             Token::Ln => 0,
-            Token::PureText { data, char_len } => char_len,
+            Token::PureText { char_len, .. } => char_len,
             Token::Tag(tag) => tag.char_len(),
+            // [^ + label + ]
+            Token::FootnoteRef { label } => chr_count(label) + 3,
+            // [^ + label + ]: + 空格
+            Token::FootnoteDef { label, space } => chr_count(label) + 4 + space as u32,
+            Token::Math { data, display } => chr_count(data) + if display { 4 } else { 2 },
+            // 换行也一起读掉了
+            Token::Admonition { .. } | Token::ContainerEnd | Token::Directive { .. } => 0,
+            Token::Shortcode { name, args } => chr_count(name) + chr_count(args) + 8,
+            Token::Attrs { data } => chr_count(data),
+            // [ + text + ]( + url + 空格和引号 + title + ) + attrs
+            Token::Link {
+                text,
+                url,
+                title,
+                attrs,
+                image,
+            } => {
+                let title_len = if title.is_empty() {
+                    0
+                } else {
+                    chr_count(title) + 3
+                };
+                chr_count(text) + chr_count(url) + title_len + chr_count(attrs) + 4 + image as u32
+            }
+            Token::CodeBlock { .. } => 0,
+        }
+    }
+}
+
+// ::: 开头的一行，返回 (name, 剩下的内容)，单独的 ::: 返回None
+pub(crate) fn fence_line(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = trim(line).strip_prefix(b":::")?;
+    // ::::: 这样更多的冒号也可以
+    let rest = trim(&rest[rest.iter().take_while(|&&b| b == b':').count()..]);
+
+    if rest.is_empty() {
+        return None;
+    }
+
+    let name_len = rest
+        .iter()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        .count();
+    Some((&rest[..name_len], trim(&rest[name_len..])))
+}
+
+pub(crate) fn offset_range(outer: &[u8], inner: &[u8]) -> std::ops::Range<usize> {
+    let start = inner.as_ptr() as usize - outer.as_ptr() as usize;
+    start..start + inner.len()
+}
+
+pub(crate) fn trim(data: &[u8]) -> &[u8] {
+    let start = data
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t')
+        .unwrap_or(data.len());
+    let end = data
+        .iter()
+        .rposition(|&b| b != b' ' && b != b'\t')
+        .map_or(start, |i| i + 1);
+    &data[start..end]
+}
+
+pub(crate) fn chr_count(data: &[u8]) -> u32 {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.chars().count() as u32,
+        // 不合法的byte和ChrIter的PassThrough一样，每个算一个字符
+        Err(_e) => {
+            let mut count = 0;
+            let mut i = 0;
+            while i < data.len() {
+                i += match decode(&data[i..]) {
+                    Decoded::Chr(len) => len,
+                    Decoded::Invalid(_) | Decoded::Incomplete => 1,
+                };
+                count += 1;
+            }
+            count
         }
     }
 }
 
 #[test]
 fn test() {
-    let tokenizer = Tokenizer::new(std::fs::File::open("test.md").unwrap());
-    for t in tokenizer {
+    use crate::html_writer::HtmlWriterImpl;
+    use crate::mapper::Mapper;
+    use crate::parser::Parser;
+    use crate::parser::StatefulParser;
+    use crate::renderer::Pos;
+    use crate::schema::DefaultSchema;
+
+    let mut tokenizer = Tokenizer::new(std::fs::File::open("test.md").unwrap());
+    while let Some(t) = tokenizer.next_token() {
         println!("{:?}", t);
     }
+
+    // 每个token的byte范围，> [ 不是alert的时候引用只到 [ 前面，后面退回去一个一个重新读
+    let input = "> [!NOPE] x\r\n## 中文 [a](u)\n";
+    let mut tokenizer = Tokenizer::new(input.as_bytes());
+    let mut spans = Vec::new();
+    while let Some(token) = tokenizer.next_token() {
+        let (_token, span) = token.unwrap();
+        spans.push(&input[span.start as usize..span.end as usize]);
+    }
+    assert_eq!(
+        vec![
+            "> ", "[", "!", "N", "O", "P", "E", "]", " ", "x", "\r\n", "## ", "中", "文", " ",
+            "[a](u)", "\n"
+        ],
+        spans
+    );
+
+    // 脚注定义的长度按实际读到的算，':' 后面不一定有空格
+    for input in ["[^a]: x\n", "[^a]:x\n"] {
+        let mut tokenizer = Tokenizer::new(input.as_bytes());
+        let (token, span) = tokenizer.next_token().unwrap().unwrap();
+        assert_eq!(span.end - span.start, token.char_len() as u64);
+        let mut tokenizer = crate::slice_tokenizer::SliceTokenizer::new(input.as_bytes());
+        let (token, span) = tokenizer.next_token().unwrap().unwrap();
+        assert_eq!(span.end - span.start, token.char_len() as u64);
+    }
+
+    // 没有闭合的公式只有 $$ 是文本，后面的空行、链接照常解析
+    let input = "a $$ b\n\nc [l](u) $x\n";
+    let mut tokenizer = Tokenizer::new(input.as_bytes());
+    let mut spans = Vec::new();
+    while let Some(token) = tokenizer.next_token() {
+        let (_token, span) = token.unwrap();
+        spans.push(&input[span.start as usize..span.end as usize]);
+    }
+    assert_eq!(
+        vec!["a", " ", "$$", " ", "b", "\n", "\n", "c", " ", "[l](u)", " ", "$", "x", "\n"],
+        spans
+    );
+    let mut html = Vec::new();
+    StatefulParser::new(
+        input.as_bytes(),
+        DefaultSchema,
+        HtmlWriterImpl::new(&mut html),
+    )
+    .parse_and_write()
+    .unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains("a&nbsp;$$&nbsp;b<br>"), "{}", html);
+    assert!(html.contains("<a href=\"u\">l</a>"), "{}", html);
+
+    // url里配对的括号是url的一部分
+    let input = "[Rust](https://en.wikipedia.org/wiki/Rust_(language) \"t\") [a](b(c)\n";
+    for from_slice in [false, true] {
+        let mut html = Vec::new();
+        let mapper = Mapper::new(DefaultSchema, HtmlWriterImpl::new(&mut html));
+        if from_slice {
+            StatefulParser::from_slice(input.as_bytes(), mapper).parse_and_write()
+        } else {
+            StatefulParser::with_renderer(input.as_bytes(), mapper).parse_and_write()
+        }
+        .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(
+            html.contains(
+                "<a href=\"https://en.wikipedia.org/wiki/Rust_(language)\" title=\"t\">Rust</a>"
+            ),
+            "{}",
+            html
+        );
+        assert!(!html.contains("<a href=\"b"), "{}", html);
+    }
+
+    // 行首是multibyte的字符时后面的标记都是普通文本
+    for input in [
+        "中# x",
+        "中- [ ] x",
+        "中> [!NOTE]",
+        "中:::tip",
+        "中```rs",
+        "é[^1]: x",
+        "　# x",
+    ] {
+        let render = |from_slice: bool| {
+            let mut html = Vec::new();
+            let mapper = Mapper::new(DefaultSchema, HtmlWriterImpl::new(&mut html));
+            if from_slice {
+                StatefulParser::from_slice(input.as_bytes(), mapper).parse_and_write()
+            } else {
+                StatefulParser::with_renderer(input.as_bytes(), mapper).parse_and_write()
+            }
+            .unwrap();
+            String::from_utf8(html).unwrap()
+        };
+        let html = render(false);
+        assert_eq!(html, render(true));
+        assert!(
+            !html[..html.find("<style>").unwrap()].contains('<'),
+            "{}",
+            html
+        );
+    }
+
+    let input = "a\n\n:::\n{{< nope >}}\n";
+    let mut parser = StatefulParser::from_slice(
+        input.as_bytes(),
+        Mapper::new(DefaultSchema, HtmlWriterImpl::new(Vec::new())),
+    );
+    parser.parse_and_write().unwrap();
+    let warnings: Vec<Pos> = parser.take_warnings().iter().map(|w| w.pos()).collect();
+    assert_eq!(vec![Pos::new(3, 1, 3), Pos::new(4, 1, 7)], warnings);
 }